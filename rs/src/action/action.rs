@@ -1,10 +1,62 @@
-use rshacks::types::Delay;
+use rshacks::dashboard::RuleStatus;
+use rshacks::dispatch::{Dispatcher, InputSink};
+use rshacks::grid::{Cell, Grid, Selection, Slot};
+use rshacks::input::KeyTrigger;
+use rshacks::snapshot::{self, Snapshot};
+use rshacks::timing::Timing;
+use rshacks::types::{Delay, Modifiers, Position};
 use rshacks::win::proc::Process;
 use std::fmt;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use super::pre::GameState;
-use super::{PostCondition, PostResult, PreCondition};
+use super::expr::{Compare, Expr, Op, Operand};
+use super::pre::{GameState, Since};
+use super::{Memory, PostCondition, PostResult, PreCondition};
+
+const DEFAULT_CRAFT_ATTEMPTS: u32 = 10;
+
+// Words which can't be used as group names, since they could be read either way.
+const KEYWORDS: &[&str] = &[
+    "on",
+    "do",
+    "toggle",
+    "every",
+    "after",
+    "silent",
+    "consume",
+    "dry",
+    "hold",
+    "gap",
+    "jitter",
+    "group",
+    "priority",
+    "exclusive",
+];
+
+// Words which start a precondition, so variables can't be named like them.
+const CONDITIONS: &[&str] = &[
+    "life",
+    "es",
+    "mana",
+    "flask",
+    "key",
+    "skill",
+    "wheel",
+    "town",
+    "map",
+    "transition",
+    "chat",
+    "inventory",
+    "stash",
+    "device",
+    "group",
+    "rule",
+    "death",
+    "dead",
+    "focus",
+    "blur",
+    "since",
+];
 
 // Avoid spamming actions by default,
 // or the server may send "too many actions" on accident.
@@ -14,23 +66,85 @@ const DEFAULT_ACTION_WINDUP: Delay = Delay(Duration::ZERO);
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct Action {
+    /// The name given with `rule NAME:`, so other rules and control clients can refer to it.
+    pub name: Option<String>,
     pub pre: Vec<PreCondition>,
     /// The boolean remembers which preconditions have been true at some point,
     /// and are reset once the action is triggered.
     pub after_pre: Vec<(PreCondition, bool)>,
     pub post: PostCondition,
     pub last_trigger: Instant,
+    /// Whether `last_trigger` is when the action actually ran, as it starts out in the past.
+    pub triggered: bool,
     pub delay: Delay,
+    /// Extra time to wait on top of `delay`, picked every time the action runs.
+    pub jitter: Duration,
+    /// The timing configured for this rule, overriding the global timing.
+    pub timing: Timing,
     pub windup_start: Option<Instant>,
     pub windup_time: Delay,
+    /// The next step of an action taking one step per check (such as crafting), while it runs.
+    pub step: Option<u32>,
     pub silent: bool,
+    /// Whether the keys triggering the action should be kept from reaching the game.
+    pub consume: bool,
+    /// Whether the input is only recorded instead of sent, even if the others' is sent.
+    pub dry: bool,
+    /// The group the rule belongs to, which can be enabled and disabled as a whole.
+    pub group: Option<String>,
+    /// Rules with a higher priority are checked first.
+    pub priority: i32,
+    /// Once an exclusive rule triggers, the rules ranked below it in its group don't run until the
+    /// next check.
+    pub exclusive: bool,
     /// `Some(toggled on)` if it can be toggled on and off, `None` otherwise (immediate one-shot).
     pub toggle: Option<bool>,
     /// When toggling an action, the preconditions are being held to true.
     /// This is used to prevent it from being toggled back until the precondition
     /// has been checked to be false at least once during a check to toggle.
     pub toggle_pre_held: bool,
-    source: String,
+    /// The line the rule was parsed from, which identifies it in saved state and the journal.
+    pub source: String,
+}
+
+fn group_name(word: &str) -> Result<String, String> {
+    if KEYWORDS.contains(&word) {
+        Err(format!("cannot use keyword '{}' as a group name", word))
+    } else {
+        Ok(word.to_string())
+    }
+}
+
+pub(super) fn variable_name(word: &str) -> Result<String, String> {
+    if KEYWORDS.contains(&word) || CONDITIONS.contains(&word) {
+        Err(format!("cannot use '{}' as a variable name", word))
+    } else if word.starts_with(|c: char| c.is_ascii_alphabetic())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(word.to_string())
+    } else {
+        Err(format!("found invalid variable name '{}'", word))
+    }
+}
+
+/// A number, or the value of a variable.
+fn operand(word: &str) -> Result<Operand, String> {
+    match word.parse() {
+        Ok(n) => Ok(Operand::Number(n)),
+        Err(_) => Ok(Operand::Variable(variable_name(word)?)),
+    }
+}
+
+fn rule_name(word: &str) -> Result<String, String> {
+    match word.strip_suffix(':').unwrap_or(word) {
+        "" => Err("found a rule without a name".into()),
+        name if KEYWORDS.contains(&name) || name.contains(':') => {
+            Err(format!("cannot use '{}' as a rule name", name))
+        }
+        name => Ok(name.to_string()),
+    }
 }
 
 pub enum TriggerResult {
@@ -46,40 +160,197 @@ impl Action {
             return Ok(None);
         }
 
+        let mut name = None;
         let mut pre: Vec<PreCondition> = Vec::new();
         let mut after_pre: Vec<usize> = Vec::new(); // which `pre` are actually `after`
         let mut post: Option<PostCondition> = None;
         let mut delay = None;
         let mut after = DEFAULT_ACTION_WINDUP;
         let mut silent = false;
+        let mut consume = false;
+        let mut dry = false;
+        let mut group = None;
+        let mut priority = 0;
+        let mut exclusive = false;
+        let mut timing = Timing::default();
         let mut toggle = None;
+        // whether the position being parsed is relative to the game window
+        let mut window = false;
 
         enum State {
             WaitKeyword,
+            WaitRuleName,
 
             WaitPreKind,
             WaitLifeValue,
             WaitEsValue,
             WaitManaValue,
             WaitKeyValue,
+            WaitKeyTrigger,
+            WaitKeyHeldValue,
             WaitDirectionValue,
             WaitChatValue,
+            WaitInventoryValue,
+            WaitStashValue,
+            WaitMapDeviceValue,
+            WaitGroupValue,
+            WaitGroupEnabledValue(String),
+            WaitRuleFiredName,
+            WaitRuleFired(String),
+            WaitCompare(String),
+            WaitCompareValue(String, Compare),
+            WaitSince,
+            WaitSinceRule,
+            WaitSinceCompare(Since),
+            WaitSinceValue(Since, Compare),
 
             WaitPostKind,
             WaitPostValue,
             WaitPostClick,
-            WaitPostRemaining,
+            WaitPostClickOption,
+            WaitPostClickAt,
+            WaitPostMove,
+            WaitPostScroll,
+            WaitPostDragFrom,
+            WaitPostDragFromValue,
+            WaitPostDragTo(Position),
+            WaitPostDragToValue(Position),
+            WaitPostGrid,
+            WaitPostSelection(Grid),
+            WaitPostSelectionOption,
+            WaitPostCells(Grid),
+            WaitPostExcept,
+            WaitPostExceptCols,
+            WaitPostCraftGrid(Option<Slot>),
+            WaitPostCraftCell(Option<Slot>, Grid),
+            WaitPostCraftTo(Slot),
+            WaitPostCraftOption(Slot, Slot, u32),
+            WaitPostCraftAttempts(Slot, Slot),
+            WaitPostScreenshot,
+            WaitPostGroupOption,
+            WaitPostToggleGroup,
+            WaitPostRun,
+            WaitPostSet,
+            WaitPostSetValue(String),
+            WaitPostSetOption,
+            WaitPostSetOperand(Op),
+            WaitPostChange(Op),
+            WaitPostChangeOption,
 
             WaitDelayValue,
             WaitAfterValue,
+            WaitTimingValue(&'static str),
+            WaitRuleGroup,
+            WaitPriorityValue,
         }
 
         let mut state = State::WaitKeyword;
-        let line = line.to_lowercase();
-        for word in line.split_whitespace() {
+        for raw in line.split_whitespace() {
             use State::*;
+            let word = raw.to_lowercase();
+            let word = word.as_str();
+            if let WaitKeyTrigger = state {
+                // the key trigger is optional, so anything else is the next keyword
+                state = WaitKeyword;
+                if let Some(PreCondition::KeyPress { trigger, .. }) = pre.last_mut() {
+                    match word {
+                        "held" => {
+                            state = WaitKeyHeldValue;
+                            continue;
+                        }
+                        "pressed" | "released" | "double-tap" => {
+                            *trigger = word.parse()?;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if let WaitPostClickOption = state {
+                // so are the click position and whether to restore the cursor afterwards
+                state = WaitKeyword;
+                if let Some(PostCondition::Click { at, restore, .. }) = post.as_mut() {
+                    match word {
+                        "at" if at.is_none() => {
+                            state = WaitPostClickAt;
+                            continue;
+                        }
+                        "restore" if at.is_some() => {
+                            *restore = true;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if let WaitPostGroupOption = state {
+                // without a group, every rule sending input is enabled or disabled
+                state = WaitKeyword;
+                if let Some(PostCondition::SetEnabled { group, .. }) = post.as_mut() {
+                    if !KEYWORDS.contains(&word) {
+                        *group = Some(group_name(word)?);
+                        continue;
+                    }
+                }
+            }
+            if let WaitPostSetOption = state {
+                // the value can be combined with another one
+                state = WaitKeyword;
+                if let (Ok(op), Some(PostCondition::Set { .. })) = (word.parse(), &post) {
+                    state = WaitPostSetOperand(op);
+                    continue;
+                }
+            }
+            if let WaitPostChangeOption = state {
+                // and how much to change a variable by is optional
+                state = WaitKeyword;
+                if let Some(PostCondition::Set {
+                    value:
+                        Expr {
+                            right: Some((_, by)),
+                            ..
+                        },
+                    ..
+                }) = post.as_mut()
+                {
+                    if !KEYWORDS.contains(&word) {
+                        *by = operand(word)?;
+                        continue;
+                    }
+                }
+            }
+            if let WaitPostSelectionOption = state {
+                // leaving out some columns is optional too
+                state = WaitKeyword;
+                if let ("except", Some(PostCondition::CtrlClickCells { .. })) = (word, &post) {
+                    state = WaitPostExcept;
+                    continue;
+                }
+            }
+            if word == "window"
+                && matches!(
+                    state,
+                    WaitPostClickAt | WaitPostMove | WaitPostDragFromValue | WaitPostDragToValue(_)
+                )
+            {
+                window = true;
+                continue;
+            }
+            // the rest of the line after the current word, as written
+            let rest = || {
+                let start = raw.as_ptr() as usize - line.as_ptr() as usize + raw.len();
+                line[start..].trim()
+            };
+            let mut position = || -> Result<Position, String> {
+                Ok(Position {
+                    window: std::mem::take(&mut window),
+                    ..word.parse()?
+                })
+            };
             state = match &state {
                 WaitKeyword => match word {
+                    // the name comes before anything else
+                    "rule" if name.is_none() && pre.is_empty() && post.is_none() => WaitRuleName,
                     "on" => WaitPreKind,
                     "do" => WaitPostKind,
                     "toggle" => {
@@ -96,8 +367,29 @@ impl Action {
                         silent = true;
                         WaitKeyword
                     }
+                    "consume" => {
+                        consume = true;
+                        WaitKeyword
+                    }
+                    "dry" => {
+                        dry = true;
+                        WaitKeyword
+                    }
+                    "hold" => WaitTimingValue("hold"),
+                    "gap" => WaitTimingValue("gap"),
+                    "jitter" => WaitTimingValue("jitter"),
+                    "group" => WaitRuleGroup,
+                    "priority" => WaitPriorityValue,
+                    "exclusive" => {
+                        exclusive = true;
+                        WaitKeyword
+                    }
                     _ => return Err(format!("found unexpected keyword '{}'", word)),
                 },
+                WaitRuleName => {
+                    name = Some(rule_name(word)?);
+                    WaitKeyword
+                }
 
                 WaitPreKind | WaitAfterValue => match word {
                     "life" => WaitLifeValue,
@@ -118,6 +410,16 @@ impl Action {
                         WaitKeyword
                     }
                     "chat" => WaitChatValue,
+                    "inventory" => WaitInventoryValue,
+                    "stash" => WaitStashValue,
+                    "device" => WaitMapDeviceValue,
+                    "group" => WaitGroupValue,
+                    "rule" => WaitRuleFiredName,
+                    "since" => WaitSince,
+                    "death" | "dead" => {
+                        pre.push(PreCondition::Dead);
+                        WaitKeyword
+                    }
                     "focus" => {
                         pre.push(PreCondition::WindowFocus);
                         WaitKeyword
@@ -126,14 +428,53 @@ impl Action {
                         pre.push(PreCondition::WindowBlur);
                         WaitKeyword
                     }
-                    _ if matches!(state, WaitAfterValue) => {
+                    _ if matches!(state, WaitAfterValue) && word.parse::<Delay>().is_ok() => {
                         // next word wasn't a new precondition. it must be a delay
                         after_pre.pop();
                         after = word.parse()?;
                         WaitKeyword
                     }
-                    _ => return Err(format!("found unknown condition '{}'", word)),
+                    // otherwise it must be a variable being compared
+                    _ => match variable_name(word) {
+                        Ok(variable) => WaitCompare(variable),
+                        Err(_) if matches!(state, WaitAfterValue) => {
+                            return Err(format!("found unknown condition or duration '{}'", word))
+                        }
+                        Err(_) => return Err(format!("found unknown condition '{}'", word)),
+                    },
+                },
+                WaitCompare(variable) => match word.parse() {
+                    Ok(compare) => WaitCompareValue(variable.clone(), compare),
+                    Err(_) => return Err(format!("found unknown condition '{}'", variable)),
                 },
+                WaitCompareValue(variable, compare) => {
+                    pre.push(PreCondition::Compare {
+                        variable: variable.clone(),
+                        compare: *compare,
+                        value: operand(word)?,
+                    });
+                    WaitKeyword
+                }
+                WaitSince => match word {
+                    "transition" => WaitSinceCompare(Since::Transition),
+                    "rule" => WaitSinceRule,
+                    _ => {
+                        return Err(format!(
+                            "expected 'transition' or 'rule' after since, found '{}'",
+                            word
+                        ))
+                    }
+                },
+                WaitSinceRule => WaitSinceCompare(Since::Rule(rule_name(word)?)),
+                WaitSinceCompare(since) => WaitSinceValue(since.clone(), word.parse()?),
+                WaitSinceValue(since, compare) => {
+                    pre.push(PreCondition::Since {
+                        since: since.clone(),
+                        compare: *compare,
+                        duration: word.parse()?,
+                    });
+                    WaitKeyword
+                }
                 WaitLifeValue => {
                     let threshold = word.parse()?;
                     pre.push(PreCondition::LifeBelow { threshold });
@@ -150,7 +491,19 @@ impl Action {
                     WaitKeyword
                 }
                 WaitKeyValue => {
-                    pre.push(PreCondition::KeyPress { vk: word.parse()? });
+                    let (modifiers, key) = Modifiers::split(word)?;
+                    pre.push(PreCondition::KeyPress {
+                        vk: key.parse()?,
+                        modifiers,
+                        trigger: KeyTrigger::Down,
+                    });
+                    WaitKeyTrigger
+                }
+                WaitKeyTrigger => unreachable!(),
+                WaitKeyHeldValue => {
+                    if let Some(PreCondition::KeyPress { trigger, .. }) = pre.last_mut() {
+                        *trigger = KeyTrigger::Held(word.parse::<Delay>()?.0);
+                    }
                     WaitKeyword
                 }
                 WaitDirectionValue => {
@@ -163,6 +516,44 @@ impl Action {
                     });
                     WaitKeyword
                 }
+                WaitInventoryValue => {
+                    pre.push(PreCondition::Inventory {
+                        open: word.parse()?,
+                    });
+                    WaitKeyword
+                }
+                WaitStashValue => {
+                    pre.push(PreCondition::Stash {
+                        open: word.parse()?,
+                    });
+                    WaitKeyword
+                }
+                WaitGroupValue => WaitGroupEnabledValue(group_name(word)?),
+                WaitGroupEnabledValue(group) => {
+                    pre.push(PreCondition::Group {
+                        group: group.clone(),
+                        enabled: match word {
+                            "enabled" => true,
+                            "disabled" => false,
+                            _ => return Err("group can only be enabled or disabled".into()),
+                        },
+                    });
+                    WaitKeyword
+                }
+                WaitRuleFiredName => WaitRuleFired(rule_name(word)?),
+                WaitRuleFired(rule) => match word {
+                    "fired" => {
+                        pre.push(PreCondition::RuleFired { rule: rule.clone() });
+                        WaitKeyword
+                    }
+                    _ => return Err(format!("expected 'fired' after rule, found '{}'", word)),
+                },
+                WaitMapDeviceValue => {
+                    pre.push(PreCondition::MapDevice {
+                        open: word.parse()?,
+                    });
+                    WaitKeyword
+                }
 
                 WaitPostKind => match word {
                     "disconnect" => {
@@ -171,11 +562,17 @@ impl Action {
                     }
                     "flask" | "key" | "skill" => WaitPostValue,
                     "click" => WaitPostClick,
+                    "move" => WaitPostMove,
+                    "scroll" => WaitPostScroll,
+                    "drag" => WaitPostDragFrom,
+                    "ctrl-click" => WaitPostGrid,
+                    "craft" => WaitPostCraftGrid(None),
                     "type" => {
+                        // the rest of the line is typed exactly as written, casing and all
                         post = Some(PostCondition::Type {
-                            string: String::new(),
+                            string: rest().to_string(),
                         });
-                        WaitPostRemaining
+                        break;
                     }
                     "invite" => {
                         post = Some(PostCondition::InviteLast);
@@ -185,43 +582,221 @@ impl Action {
                         post = Some(PostCondition::Destroy);
                         WaitKeyword
                     }
-                    "disable" => {
-                        post = Some(PostCondition::SetKeySuppression { suppress: true });
-                        WaitKeyword
-                    }
-                    "enable" => {
-                        post = Some(PostCondition::SetKeySuppression { suppress: false });
-                        WaitKeyword
+                    "disable" | "enable" => {
+                        post = Some(PostCondition::SetEnabled {
+                            group: None,
+                            enabled: word == "enable",
+                        });
+                        WaitPostGroupOption
                     }
+                    "toggle-group" => WaitPostToggleGroup,
+                    "screenshot" => WaitPostScreenshot,
+                    "run" => WaitPostRun,
+                    "set" => WaitPostSet,
+                    "inc" => WaitPostChange(Op::Add),
+                    "dec" => WaitPostChange(Op::Sub),
                     _ => return Err(format!("found unknown action '{}'", word)),
                 },
                 WaitPostValue => {
-                    post = Some(PostCondition::PressKey { vk: word.parse()? });
+                    let (modifiers, key) = Modifiers::split(word)?;
+                    post = Some(PostCondition::PressKey {
+                        vk: key.parse()?,
+                        modifiers,
+                    });
                     WaitKeyword
                 }
                 WaitPostClick => {
+                    let (modifiers, button) = Modifiers::split(word)?;
                     post = Some(PostCondition::Click {
-                        button: word.parse()?,
+                        button: button.parse()?,
+                        modifiers,
+                        at: None,
+                        restore: false,
+                    });
+                    WaitPostClickOption
+                }
+                WaitPostClickOption => unreachable!(),
+                WaitPostClickAt => {
+                    if let Some(PostCondition::Click { at, .. }) = post.as_mut() {
+                        *at = Some(position()?);
+                    }
+                    WaitPostClickOption
+                }
+                WaitPostMove => {
+                    post = Some(PostCondition::Move { to: position()? });
+                    WaitKeyword
+                }
+                WaitPostScroll => {
+                    post = Some(PostCondition::Scroll {
+                        amount: word
+                            .parse()
+                            .map_err(|_| "scroll amount must be a whole number")?,
+                    });
+                    WaitKeyword
+                }
+                WaitPostDragFrom => match word {
+                    "from" => WaitPostDragFromValue,
+                    _ => return Err(format!("expected 'from' after drag, found '{}'", word)),
+                },
+                WaitPostDragFromValue => WaitPostDragTo(position()?),
+                WaitPostDragTo(from) => match word {
+                    "to" => WaitPostDragToValue(*from),
+                    _ => return Err(format!("expected 'to' after drag, found '{}'", word)),
+                },
+                WaitPostDragToValue(from) => {
+                    post = Some(PostCondition::Drag {
+                        from: *from,
+                        to: position()?,
                     });
                     WaitKeyword
                 }
-                WaitPostRemaining => {
-                    match post {
-                        Some(PostCondition::Type { ref mut string }) => {
-                            if !string.is_empty() {
-                                string.push(' ');
-                            }
-                            string.push_str(word);
+                WaitPostGrid => WaitPostSelection(word.parse()?),
+                WaitPostSelection(grid) => match word {
+                    "all" => {
+                        post = Some(PostCondition::CtrlClickCells {
+                            grid: *grid,
+                            selection: Selection::default(),
+                        });
+                        WaitPostSelectionOption
+                    }
+                    "cells" => WaitPostCells(*grid),
+                    _ => return Err(format!("expected 'all' or 'cells', found '{}'", word)),
+                },
+                WaitPostSelectionOption => unreachable!(),
+                WaitPostCells(grid) => {
+                    let (first, last) = Cell::parse_range(word)?;
+                    if !grid.contains(last) {
+                        return Err(format!("found cell '{}' outside the {}", last, grid));
+                    }
+                    post = Some(PostCondition::CtrlClickCells {
+                        grid: *grid,
+                        selection: Selection {
+                            cells: Some((first, last)),
+                            except_cols: None,
+                        },
+                    });
+                    WaitPostSelectionOption
+                }
+                WaitPostExcept => match word {
+                    "cols" | "col" => WaitPostExceptCols,
+                    _ => return Err(format!("expected 'cols' after except, found '{}'", word)),
+                },
+                WaitPostExceptCols => {
+                    if let Some(PostCondition::CtrlClickCells { selection, .. }) = post.as_mut() {
+                        selection.except_cols = Some(word.parse()?);
+                    }
+                    WaitKeyword
+                }
+                WaitPostCraftGrid(currency) => WaitPostCraftCell(*currency, word.parse()?),
+                WaitPostCraftCell(currency, grid) => {
+                    let slot = Slot {
+                        grid: *grid,
+                        cell: word.parse()?,
+                    };
+                    if !grid.contains(slot.cell) {
+                        return Err(format!("found cell '{}' outside the {}", slot.cell, grid));
+                    }
+                    match currency {
+                        None => WaitPostCraftTo(slot),
+                        Some(currency) => {
+                            WaitPostCraftOption(*currency, slot, DEFAULT_CRAFT_ATTEMPTS)
                         }
-                        _ => return Err(format!("cannot parse remaining unless action is typing")),
                     }
-                    WaitPostRemaining
+                }
+                WaitPostCraftTo(currency) => match word {
+                    "to" => WaitPostCraftGrid(Some(*currency)),
+                    _ => return Err(format!("expected 'to' after craft, found '{}'", word)),
+                },
+                WaitPostCraftOption(currency, target, attempts) => match word {
+                    "attempts" => WaitPostCraftAttempts(*currency, *target),
+                    "until" => {
+                        // the mod is the rest of the line, so it can have spaces
+                        post = Some(PostCondition::Craft {
+                            currency: *currency,
+                            target: *target,
+                            attempts: *attempts,
+                            until: rest().parse()?,
+                        });
+                        break;
+                    }
+                    _ => return Err(format!("expected 'attempts' or 'until', found '{}'", word)),
+                },
+                WaitPostCraftAttempts(currency, target) => match word.parse() {
+                    Ok(attempts) if attempts > 0 => {
+                        WaitPostCraftOption(*currency, *target, attempts)
+                    }
+                    _ => return Err(format!("found invalid number of attempts '{}'", word)),
+                },
+                WaitPostGroupOption => unreachable!(),
+                WaitPostToggleGroup => {
+                    post = Some(PostCondition::ToggleGroup {
+                        group: group_name(word)?,
+                    });
+                    WaitKeyword
+                }
+                WaitPostRun => {
+                    post = Some(PostCondition::Run {
+                        rule: rule_name(word)?,
+                    });
+                    WaitKeyword
+                }
+                WaitPostSet => WaitPostSetValue(variable_name(word)?),
+                WaitPostSetValue(variable) => {
+                    post = Some(PostCondition::Set {
+                        variable: variable.clone(),
+                        value: Expr {
+                            left: operand(word)?,
+                            right: None,
+                        },
+                    });
+                    WaitPostSetOption
+                }
+                WaitPostSetOption | WaitPostChangeOption => unreachable!(),
+                WaitPostSetOperand(op) => {
+                    if let Some(PostCondition::Set { value, .. }) = post.as_mut() {
+                        value.right = Some((*op, operand(word)?));
+                    }
+                    WaitKeyword
+                }
+                WaitPostChange(op) => {
+                    let variable = variable_name(word)?;
+                    post = Some(PostCondition::Set {
+                        variable: variable.clone(),
+                        value: Expr {
+                            left: Operand::Variable(variable),
+                            right: Some((*op, Operand::Number(1))),
+                        },
+                    });
+                    WaitPostChangeOption
+                }
+                WaitPostScreenshot => {
+                    post = Some(PostCondition::Screenshot {
+                        detector: match word {
+                            "window" => None,
+                            _ => Some(word.parse()?),
+                        },
+                    });
+                    WaitKeyword
                 }
 
                 WaitDelayValue => {
                     delay = Some(word.parse()?);
                     WaitKeyword
                 }
+                WaitTimingValue(name) => {
+                    timing.set(name, word)?;
+                    WaitKeyword
+                }
+                WaitRuleGroup => {
+                    group = Some(group_name(word)?);
+                    WaitKeyword
+                }
+                WaitPriorityValue => {
+                    priority = word
+                        .parse()
+                        .map_err(|_| format!("found invalid priority '{}'", word))?;
+                    WaitKeyword
+                }
             }
         }
 
@@ -238,78 +813,236 @@ impl Action {
             Some(post) => post,
             None => return Err("it has no action to perform".into()),
         };
+        if consume
+            && !pre
+                .iter()
+                .any(|p| matches!(p, PreCondition::KeyPress { .. }))
+        {
+            return Err("it has no key to consume".into());
+        }
+        if dry && !post.sends_input() && post != PostCondition::Disconnect {
+            return Err("it sends no input, so it can't be dry".into());
+        }
 
         let delay = delay.unwrap_or_else(|| match post {
-            PostCondition::SetKeySuppression { .. } => Delay(Duration::default()),
+            PostCondition::SetEnabled { .. }
+            | PostCondition::Run { .. }
+            | PostCondition::Set { .. } => Delay(Duration::default()),
             _ => DEFAULT_ACTION_DELAY,
         });
 
         Ok(Some(Action {
+            name,
             pre,
             after_pre,
             post,
             delay,
+            jitter: Duration::ZERO,
+            timing,
             windup_time: after,
             last_trigger: Instant::now() - delay.0,
+            triggered: false,
             windup_start: None,
+            step: None,
             silent,
+            consume,
+            dry,
+            group,
+            priority,
+            exclusive,
             toggle,
             toggle_pre_held: false,
-            source: line,
+            source: line.trim().to_string(),
         }))
     }
 
+    /// The order in which the action is checked, highest first.
+    ///
+    /// Disconnecting always comes before anything else.
+    pub fn rank(&self) -> (bool, i32) {
+        (self.post == PostCondition::Disconnect, self.priority)
+    }
+
+    /// Remember whether the action is toggled on and when it last ran, if it's still cooling down.
+    ///
+    /// Actions are told apart by the line they were written in.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        if self.toggle == Some(true) {
+            snapshot.push("toggled", &self.source);
+        }
+        let elapsed = self.last_trigger.elapsed();
+        if elapsed < self.delay.0 + self.jitter {
+            let triggered = snapshot::to_millis(SystemTime::now() - elapsed);
+            snapshot.push("triggered", format!("{} {}", triggered, self.source));
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if self.toggle.is_some() && snapshot.get_all("toggled").any(|s| s == self.source) {
+            self.toggle = Some(true);
+        }
+        for value in snapshot.get_all("triggered") {
+            let (millis, source) = value.split_once(' ').unwrap_or_default();
+            if let (true, Ok(millis)) = (source == self.source, millis.parse()) {
+                let elapsed = snapshot::from_millis(millis).elapsed().unwrap_or_default();
+                if let Some(last_trigger) = Instant::now().checked_sub(elapsed) {
+                    self.last_trigger = last_trigger;
+                    self.triggered = true;
+                }
+            }
+        }
+    }
+
+    /// How the action is doing, to show on the dashboard.
+    pub fn status(&self) -> RuleStatus {
+        let elapsed = self.last_trigger.elapsed();
+        RuleStatus {
+            rule: self.name.clone().unwrap_or_else(|| self.source.clone()),
+            cooldown: (self.delay.0 + self.jitter).saturating_sub(elapsed),
+            windup: self
+                .windup_start
+                .map(|start| self.windup_time.0.saturating_sub(start.elapsed())),
+            toggled: self.toggle,
+            last_triggered: self.triggered.then_some(elapsed),
+        }
+    }
+
+    /// The keys that should be kept from reaching the game right now, if the action consumes
+    /// them: only while pressing them would run it, so its other preconditions must hold and it
+    /// can't be cooling down (unless it's toggled, which any press does). Dry rules send nothing
+    /// in their place, so they never consume anything.
+    pub fn consumed_keys<'a>(
+        &'a self,
+        state: &GameState,
+        memory: &Memory,
+    ) -> impl Iterator<Item = u16> + 'a {
+        let is_key = |p: &PreCondition| matches!(p, PreCondition::KeyPress { .. });
+        let armed = self.consume
+            && !self.dry
+            && self
+                .after_pre
+                .iter()
+                .all(|(_, previously_true)| *previously_true)
+            && self
+                .pre
+                .iter()
+                .all(|p| is_key(p) || p.is_valid(state, memory))
+            && (self.toggle.is_some() || self.last_trigger.elapsed() > self.delay.0 + self.jitter);
+        self.pre.iter().filter_map(move |p| match p {
+            PreCondition::KeyPress { vk, .. } if armed => Some(vk.0),
+            _ => None,
+        })
+    }
+
     /// Check preconditions.
-    fn check_pre(&self, state: &GameState) -> bool {
+    fn check_pre(&self, state: &GameState, memory: &Memory) -> bool {
         self.after_pre
             .iter()
             .all(|(_, previously_true)| *previously_true)
-            && self.pre.iter().all(|p| p.is_valid(state))
+            && self.pre.iter().all(|p| p.is_valid(state, memory))
     }
 
     /// Returns `true` if `trigger` should be called.
-    pub fn check(&self, state: &GameState) -> bool {
+    pub fn check(&self, state: &GameState, memory: &Memory) -> bool {
         self.windup_start.is_some()
-            || ((matches!(self.toggle, Some(true)) || self.check_pre(state))
-                && self.last_trigger.elapsed() > self.delay.0)
+            || self.step.is_some()
+            || ((matches!(self.toggle, Some(true)) || self.check_pre(state, memory))
+                && self.last_trigger.elapsed() > self.delay.0 + self.jitter)
     }
 
     /// Attempt to toggle the action on or off (if the action is not a one-shot).
     ///
     /// It's also used to enable the checks needed prior to running pre-conditions.
-    pub fn try_toggle(&mut self, state: &GameState) {
+    pub fn try_toggle(&mut self, state: &GameState, memory: &Memory) {
         self.after_pre
             .iter_mut()
             .for_each(|(pre, previously_true)| {
                 if !*previously_true {
-                    *previously_true = pre.is_valid(state);
+                    *previously_true = pre.is_valid(state, memory);
                 }
             });
 
         if let Some(enabled) = self.toggle {
             // `toggle_pre_held` needs to be false at least once to toggle an action back.
             if self.toggle_pre_held {
-                self.toggle_pre_held = self.check_pre(state);
-            } else if self.check_pre(state) {
+                self.toggle_pre_held = self.check_pre(state, memory);
+            } else if self.check_pre(state, memory) {
                 self.toggle = Some(!enabled);
                 self.toggle_pre_held = true;
             }
         }
     }
 
-    /// Trigger the action.
-    fn trigger(&mut self, process: &Process) -> Result<PostResult, &'static str> {
+    /// Trigger the action, sending its input with its own timing or else the global one.
+    fn trigger<S: InputSink>(
+        &mut self,
+        process: &Process,
+        input: &mut Dispatcher<S>,
+        timing: &Timing,
+        memory: &Memory,
+    ) -> TriggerResult {
         self.after_pre
             .iter_mut()
             .for_each(|(_, previously_true)| *previously_true = false);
         self.last_trigger = Instant::now();
-        self.post.act(process)
+        self.triggered = true;
+        input.begin(self.timing.or(timing));
+        self.jitter = input.jitter();
+        self.act(process, input, memory, 0)
+    }
+
+    /// Take a step of the action, which for most of them is all there is to do. Those taking one
+    /// step per check keep going during the next ones, so other rules (such as disconnecting)
+    /// still run in between.
+    fn act<S: InputSink>(
+        &mut self,
+        process: &Process,
+        input: &mut Dispatcher<S>,
+        memory: &Memory,
+        step: u32,
+    ) -> TriggerResult {
+        match self.post.act(process, input, memory, step) {
+            Ok(PostResult::Continue) => {
+                self.step = Some(step + 1);
+                TriggerResult::Delayed
+            }
+            Ok(result) => {
+                self.step = None;
+                TriggerResult::Success(result)
+            }
+            Err(reason) => {
+                self.step = None;
+                TriggerResult::Failed { reason }
+            }
+        }
+    }
+
+    /// Trigger the action right away, regardless of its preconditions and cooldown.
+    pub fn fire<S: InputSink>(
+        &mut self,
+        process: &Process,
+        input: &mut Dispatcher<S>,
+        timing: &Timing,
+        memory: &Memory,
+    ) -> TriggerResult {
+        self.windup_start = None;
+        self.trigger(process, input, timing, memory)
     }
 
     /// Try to trigger the action.
     ///
     /// If it has windup, the action will be delayed.
-    pub fn try_trigger(&mut self, process: &Process) -> TriggerResult {
+    pub fn try_trigger<S: InputSink>(
+        &mut self,
+        process: &Process,
+        input: &mut Dispatcher<S>,
+        timing: &Timing,
+        memory: &Memory,
+    ) -> TriggerResult {
+        if let Some(step) = self.step {
+            input.begin(self.timing.or(timing));
+            return self.act(process, input, memory, step);
+        }
         if self.windup_time.0 > Duration::ZERO {
             let now = Instant::now();
             if let Some(start) = self.windup_start {
@@ -324,15 +1057,15 @@ impl Action {
             }
         }
 
-        match self.trigger(process) {
-            Ok(result) => TriggerResult::Success(result),
-            Err(reason) => TriggerResult::Failed { reason },
-        }
+        self.trigger(process, input, timing, memory)
     }
 }
 
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "rule {}: ", name)?;
+        }
         for p in self.pre.iter() {
             write!(f, "on {} ", p)?;
         }
@@ -348,12 +1081,32 @@ impl fmt::Display for Action {
         if self.silent {
             write!(f, "silent ")?;
         }
+        if self.consume {
+            write!(f, "consume ")?;
+        }
+        if self.dry {
+            write!(f, "dry ")?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, "group {} ", group)?;
+        }
+        if self.priority != 0 {
+            write!(f, "priority {} ", self.priority)?;
+        }
+        if self.exclusive {
+            write!(f, "exclusive ")?;
+        }
+        if !self.timing.is_empty() {
+            write!(f, "{} ", self.timing)?;
+        }
         write!(f, "do {}", self.post)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use rshacks::checker::FocusState;
+    use rshacks::grid::Span;
     use rshacks::types::{Value, Vk};
 
     use super::*;
@@ -464,24 +1217,408 @@ mod tests {
     fn key() {
         assert_eq!(
             action("on key z do disconnect").pre,
-            vec![PreCondition::KeyPress { vk: Vk(0x5A) }]
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x5A),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Down
+            }]
         );
         assert_eq!(
             action("on key Z do disconnect").pre,
-            vec![PreCondition::KeyPress { vk: Vk(0x5A) }]
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x5A),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Down
+            }]
         );
         assert_eq!(
             action("on key 6 do disconnect").pre,
-            vec![PreCondition::KeyPress { vk: Vk(0x36) }]
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x36),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Down
+            }]
         );
         assert_eq!(
             action("on key F11 do disconnect").pre,
-            vec![PreCondition::KeyPress { vk: Vk(0x7A) }]
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x7A),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Down
+            }]
         );
         assert_eq!(
             action("on key 0x2 do disconnect").pre,
-            vec![PreCondition::KeyPress { vk: Vk(0x02) }]
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x02),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Down
+            }]
+        );
+        assert_eq!(
+            action("on key 0x02 pressed do disconnect").pre,
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x02),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Pressed
+            }]
+        );
+        assert_eq!(
+            action("on key Q held 300ms do disconnect").pre,
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x51),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Held(Duration::from_millis(300))
+            }]
+        );
+        assert_eq!(
+            action("on key Q double-tap on focus do disconnect").pre,
+            vec![
+                PreCondition::KeyPress {
+                    vk: Vk(0x51),
+                    modifiers: Modifiers::NONE,
+                    trigger: KeyTrigger::DoubleTap
+                },
+                PreCondition::WindowFocus
+            ]
+        );
+        assert_eq!(
+            action("on key Q released do disconnect").pre,
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x51),
+                modifiers: Modifiers::NONE,
+                trigger: KeyTrigger::Released
+            }]
+        );
+    }
+
+    #[test]
+    fn type_keeps_text() {
+        assert_eq!(
+            action("on key F5 do type @Someone  Привет, ça va? ").post,
+            PostCondition::Type {
+                string: "@Someone  Привет, ça va?".to_string()
+            }
+        );
+        assert_eq!(
+            action("on key F5 every 2s do type /hideout").post,
+            PostCondition::Type {
+                string: "/hideout".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn consume() {
+        let mut state = GameState {
+            area: None,
+            focus: Some(FocusState {
+                in_foreground: true,
+            }),
+            keyboard: None,
+            mouse: None,
+            player: None,
+            screen: None,
+        };
+        let memory = Memory::default();
+        let consumed = |a: &Action, state: &GameState| a.consumed_keys(state, &memory).count();
+
+        let mut consuming = action("on key Q pressed on focus do disconnect consume");
+        assert!(consuming.consume);
+        assert_eq!(
+            consuming.consumed_keys(&state, &memory).collect::<Vec<_>>(),
+            vec![0x51]
+        );
+        assert_eq!(consumed(&action("on key Q do disconnect"), &state), 0);
+        assert_eq!(
+            consumed(&action("on key Q dry do key W consume"), &state),
+            0
+        );
+        // The key goes through while the rule couldn't run anyway.
+        consuming.last_trigger = Instant::now();
+        assert_eq!(consumed(&consuming, &state), 0);
+        let consuming = action("on key Q pressed on focus do disconnect consume");
+        state.focus = Some(FocusState {
+            in_foreground: false,
+        });
+        assert_eq!(consumed(&consuming, &state), 0);
+        assert!(Action::from_line("on focus do disconnect consume").is_err());
+    }
+
+    #[test]
+    fn dry() {
+        assert!(action("on key F1 dry do flask 1").dry);
+        assert!(!action("on key F1 do flask 1").dry);
+        assert!(Action::from_line("on chat open do disable dry").is_err());
+    }
+
+    #[test]
+    fn chords() {
+        assert_eq!(
+            action("on key ctrl+shift+d pressed do key alt+1").pre,
+            vec![PreCondition::KeyPress {
+                vk: Vk(0x44),
+                modifiers: Modifiers::CTRL.with(Modifiers::SHIFT),
+                trigger: KeyTrigger::Pressed
+            }]
+        );
+        assert_eq!(
+            action("on key ctrl+shift+d pressed do key alt+1").post,
+            PostCondition::PressKey {
+                vk: Vk(0x31),
+                modifiers: Modifiers::ALT
+            }
+        );
+        assert!(Action::from_line("on key ctrl+ do disconnect").is_err());
+        assert!(Action::from_line("on key hyper+d do disconnect").is_err());
+    }
+
+    #[test]
+    fn mouse() {
+        assert_eq!(
+            action("on key F2 do click shift+right at window 10,-5 restore").post,
+            PostCondition::Click {
+                button: "right".parse().unwrap(),
+                modifiers: Modifiers::SHIFT,
+                at: Some(Position {
+                    x: 10,
+                    y: -5,
+                    window: true
+                }),
+                restore: true
+            }
+        );
+        assert_eq!(
+            action("on key F2 do drag from window 1,2 to 3,4").post,
+            PostCondition::Drag {
+                from: Position {
+                    x: 1,
+                    y: 2,
+                    window: true
+                },
+                to: Position {
+                    x: 3,
+                    y: 4,
+                    window: false
+                }
+            }
+        );
+        assert_eq!(
+            action("on key F2 do scroll -2").post,
+            PostCondition::Scroll { amount: -2 }
+        );
+        assert!(Action::from_line("on key F2 do click left restore").is_err());
+        assert!(Action::from_line("on key F2 do drag from 1,2").is_err());
+        assert!(Action::from_line("on key F2 do move 1;2").is_err());
+    }
+
+    #[test]
+    fn grid() {
+        assert_eq!(
+            action("on key F3 do ctrl-click inventory all except cols 11-12").post,
+            PostCondition::CtrlClickCells {
+                grid: Grid::Inventory,
+                selection: Selection {
+                    cells: None,
+                    except_cols: Some(Span(11, 12))
+                }
+            }
+        );
+        assert_eq!(
+            action("on key F3 do ctrl-click stash cells 1,1-12,6 silent").post,
+            PostCondition::CtrlClickCells {
+                grid: Grid::Stash,
+                selection: Selection {
+                    cells: Some((Cell { col: 1, row: 1 }, Cell { col: 12, row: 6 })),
+                    except_cols: None
+                }
+            }
         );
+        assert!(Action::from_line("on key F3 do ctrl-click stash cells 1,1-13,1").is_err());
+        assert!(Action::from_line("on key F3 do ctrl-click inventory").is_err());
+        assert!(Action::from_line("on key F3 do ctrl-click inventory all except 12").is_err());
+    }
+
+    #[test]
+    fn craft() {
+        assert_eq!(
+            action("on key F4 do craft stash 1,1 to quad-stash 24,1 attempts 50 until 25+% increased Movement Speed").post,
+            PostCondition::Craft {
+                currency: Slot {
+                    grid: Grid::Stash,
+                    cell: Cell { col: 1, row: 1 }
+                },
+                target: Slot {
+                    grid: Grid::QuadStash,
+                    cell: Cell { col: 24, row: 1 }
+                },
+                attempts: 50,
+                until: "25+% increased Movement Speed".parse().unwrap()
+            }
+        );
+        assert!(Action::from_line("on key F4 do craft stash 1,1 to stash 2,2").is_err());
+        assert!(
+            Action::from_line("on key F4 do craft stash 1,1 to stash 2,2 attempts 0 until x")
+                .is_err()
+        );
+        assert!(
+            Action::from_line("on key F4 do craft stash 1,1 to inventory 1,6 until x").is_err()
+        );
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut toggled = action("on key F1 toggle click left every 150ms");
+        toggled.toggle = Some(true);
+        toggled.last_trigger = Instant::now();
+        let idle = action("on key F2 do flask 1");
+        let mut snapshot = Snapshot::new(1);
+        toggled.snapshot(&mut snapshot);
+        idle.snapshot(&mut snapshot);
+        assert_eq!(snapshot.get_all("toggled").count(), 1);
+        assert_eq!(snapshot.get_all("triggered").count(), 1);
+
+        let mut restored = action("on key F1 toggle click left every 150ms");
+        assert_eq!(restored.toggle, Some(false));
+        restored.restore(&snapshot);
+        assert_eq!(restored.toggle, Some(true));
+        assert!(restored.last_trigger.elapsed() < Duration::from_millis(150));
+
+        let mut other = action("on key F2 do flask 1");
+        other.restore(&snapshot);
+        assert_eq!(other.toggle, None);
+        assert!(other.last_trigger.elapsed() >= other.delay.0);
+    }
+
+    #[test]
+    fn priority() {
+        let flask = action("on life 50% priority 5 exclusive do flask 1");
+        assert_eq!(flask.priority, 5);
+        assert!(flask.exclusive);
+        let other = action("on life 50% priority -1 do flask 2");
+        assert!(flask.rank() > other.rank());
+        assert!(action("on life 10% do disconnect").rank() > flask.rank());
+        assert!(Action::from_line("on life 50% priority high do flask 1").is_err());
+    }
+
+    #[test]
+    fn groups() {
+        let flask = action("on life 50% group flasks do flask 1");
+        assert_eq!(flask.group, Some("flasks".to_string()));
+        assert_eq!(
+            action("on chat open do disable flasks silent").post,
+            PostCondition::SetEnabled {
+                group: Some("flasks".to_string()),
+                enabled: false
+            }
+        );
+        let all = action("on chat closed do enable silent");
+        assert!(all.silent);
+        assert_eq!(
+            all.post,
+            PostCondition::SetEnabled {
+                group: None,
+                enabled: true
+            }
+        );
+        assert_eq!(
+            action("on key F1 do toggle-group trade").post,
+            PostCondition::ToggleGroup {
+                group: "trade".to_string()
+            }
+        );
+        assert_eq!(
+            action("on group trade enabled do disconnect").pre,
+            vec![PreCondition::Group {
+                group: "trade".to_string(),
+                enabled: true
+            }]
+        );
+        assert!(Action::from_line("on key F1 do toggle-group silent").is_err());
+        assert!(Action::from_line("on group trade on do disconnect").is_err());
+    }
+
+    #[test]
+    fn names() {
+        let named = action("rule Flask-Life: on life 70% do flask 1");
+        assert_eq!(named.name, Some("flask-life".to_string()));
+        assert_eq!(named.source, "rule Flask-Life: on life 70% do flask 1");
+        assert_eq!(
+            action("on key F1 do run flask-life").post,
+            PostCondition::Run {
+                rule: "flask-life".to_string()
+            }
+        );
+        assert_eq!(
+            action("on key F1 do run flask-life").delay.0,
+            Duration::ZERO
+        );
+        assert_eq!(
+            action("on rule flask-life fired do flask 2").pre,
+            vec![PreCondition::RuleFired {
+                rule: "flask-life".to_string()
+            }]
+        );
+        assert!(Action::from_line("rule every: on key F1 do flask 1").is_err());
+        assert!(Action::from_line("on key F1 rule late: do flask 1").is_err());
+        assert!(Action::from_line("on rule flask-life do flask 2").is_err());
+        assert!(Action::from_line("on key F1 do run").is_err());
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            action("on stacks >= max-stacks do key 2").pre,
+            vec![PreCondition::Compare {
+                variable: "stacks".to_string(),
+                compare: Compare::GreaterOrEqual,
+                value: Operand::Variable("max-stacks".to_string())
+            }]
+        );
+        assert_eq!(
+            action("on key 1 pressed do inc stacks").post,
+            PostCondition::Set {
+                variable: "stacks".to_string(),
+                value: Expr {
+                    left: Operand::Variable("stacks".to_string()),
+                    right: Some((Op::Add, Operand::Number(1)))
+                }
+            }
+        );
+        assert_eq!(
+            action("on key 2 do set total stacks * 2 silent").post,
+            PostCondition::Set {
+                variable: "total".to_string(),
+                value: Expr {
+                    left: Operand::Variable("stacks".to_string()),
+                    right: Some((Op::Mul, Operand::Number(2)))
+                }
+            }
+        );
+        assert_eq!(
+            action("on map after since transition > 30s do flask 3").after_pre,
+            vec![(
+                PreCondition::Since {
+                    since: Since::Transition,
+                    compare: Compare::Greater,
+                    duration: Delay(Duration::from_secs(30))
+                },
+                false
+            )]
+        );
+        assert_eq!(
+            action("on since rule flask-life <= 4s do flask 2").pre,
+            vec![PreCondition::Since {
+                since: Since::Rule("flask-life".to_string()),
+                compare: Compare::LessOrEqual,
+                duration: Delay(Duration::from_secs(4))
+            }]
+        );
+        assert!(Action::from_line("on lfie 50% do flask 1").is_err());
+        assert!(Action::from_line("on stacks => 5 do flask 1").is_err());
+        assert!(Action::from_line("on since death > 5s do flask 1").is_err());
+        assert!(Action::from_line("on key 1 do set life 5").is_err());
+        assert!(Action::from_line("on key 1 do inc stacks 2x").is_err());
     }
 
     #[test]
@@ -501,12 +1638,16 @@ mod tests {
         fn parse_self(line: &str) {
             let parsed = action(line);
             let reparsed = action(&parsed.to_string());
+            assert_eq!(parsed.name, reparsed.name);
             assert_eq!(parsed.pre, reparsed.pre);
             assert_eq!(parsed.after_pre, reparsed.after_pre);
             assert_eq!(parsed.post, reparsed.post);
             assert_eq!(parsed.delay, reparsed.delay);
             assert_eq!(parsed.windup_time, reparsed.windup_time);
             assert_eq!(parsed.silent, reparsed.silent);
+            assert_eq!(parsed.consume, reparsed.consume);
+            assert_eq!(parsed.dry, reparsed.dry);
+            assert_eq!(parsed.timing, reparsed.timing);
         }
 
         parse_self("on life 50% do disconnect");
@@ -521,19 +1662,54 @@ mod tests {
         parse_self("on key A do disable silent");
         parse_self("on key B do enable");
         parse_self("on focus after blur do disconnect");
+        parse_self("on inventory open on stash closed do disable");
+        parse_self("on device opened after death do enable");
+        parse_self("on key F9 do screenshot window");
+        parse_self("on chat open do screenshot chat");
+        parse_self("on key Q pressed after key W released do disconnect");
+        parse_self("on key Q held 1s every 2s do flask 1");
+        parse_self("on key ctrl on wheel up do click left every 5ms");
+        parse_self("on key mouse5 released do key numpad3");
+        parse_self("on key shift+ctrl+d double-tap do key ctrl+alt+f4");
+        parse_self("on key Q double-tap do disable");
+        parse_self("on key Q pressed do disconnect consume");
+        parse_self("on life 30% do disconnect dry");
+        parse_self("on key Q hold 20-40ms gap 30ms~5ms jitter 0-1s do type hi");
+        parse_self("on key F2 do move window 100,-20");
+        parse_self("on key F2 do scroll -3 every 1s");
+        parse_self("on key F2 do drag from 10,20 to window 30,40");
+        parse_self("on key F2 do click ctrl+left at window 5,5 restore silent");
+        parse_self("on key F3 do ctrl-click inventory all except cols 11-12 every 5s");
+        parse_self("on key F3 do ctrl-click quad-stash cells 1,1-24,2 except col 3");
+        parse_self("on key F3 do ctrl-click stash cells 2,2");
+        parse_self("on key F1 group flasks do flask 1");
+        parse_self("on life 50% priority -2 exclusive do flask 1");
+        parse_self("on chat open do disable trade silent");
+        parse_self("on key F1 on group trade disabled do toggle-group trade");
+        parse_self("rule flask-life: on life 70% do flask 1");
+        parse_self("rule guard on rule flask-life fired do run flask-mana");
+        parse_self("on stacks != 5 on key 1 do inc stacks");
+        parse_self("on key 1 do dec stacks 2");
+        parse_self("on key 1 do inc stacks step");
+        parse_self("on key 1 do set stacks -1");
+        parse_self("on key 1 do set stacks stacks / divisor");
+        parse_self("on map on since transition >= 30s after since rule flask-life > 4s do flask 2");
+        parse_self(
+            "on key F4 every 0 do craft stash 1,1 to inventory 1,1 until +#% to Cold Resistance",
+        );
     }
 
     #[test]
     fn display() {
         assert_eq!(
             action("on key Z do disconnect every 2s").to_string(),
-            "on key 0x5A every 2000ms do disconnect"
+            "on key Z every 2000ms do disconnect"
         );
 
         assert_eq!(
             action("on key A every 200ms do disconnect after 10ms on key Z every 300ms after 30ms do type test")
                 .to_string(),
-            "on key 0x41 on key 0x5A every 300ms after 30ms do type test"
+            "on key A on key Z every 300ms after 30ms do type test"
         );
     }
 }
@@ -1,104 +1,594 @@
+use rshacks::control::ControlConfig;
+use rshacks::dashboard::RuleStatus;
+use rshacks::detect::Detector;
+use rshacks::dispatch::{Dispatcher, Intent};
+use rshacks::journal::{Journal, Json, Verbosity};
+use rshacks::snapshot::Snapshot;
+use rshacks::timing::{Rng, Timing};
+use rshacks::win::input::SystemInput;
 use rshacks::win::proc::Process;
 
 use super::action::{Action, TriggerResult};
+use super::key_file::KeyFile;
 use super::pre::{GameState, PreRequirement};
-use super::{PostCondition, PostResult};
+use super::{Memory, PostCondition, PostResult, PreCondition};
+use std::cmp::Reverse;
+use std::collections::HashSet;
 use std::fmt;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub struct ActionSet {
     actions: Vec<Action>,
-    inhibit_key_presses: bool,
+    memory: Memory,
     created: Instant,
+    /// The timing for actions which don't configure their own.
+    timing: Timing,
+    input: Dispatcher<SystemInput>,
+    /// Whether every rule only records its input instead of sending it, not just the dry ones.
+    dry_run: bool,
+    verbosity: Verbosity,
+    control: Option<ControlConfig>,
+    /// The profile the rules were loaded for, if any.
+    profile: Option<String>,
+    /// The profiles which have their own section in the file.
+    profiles: Vec<String>,
 }
 
-impl ActionSet {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, &'static str> {
-        let actions: Vec<Action> = match File::open(path) {
-            Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                return Err("poe key file not found");
+/// Parse a `timing` directive, such as `timing hold 20-40ms gap 30ms seed 7`.
+fn parse_timing(line: &str, timing: &mut Timing, seed: &mut Option<u64>) -> Result<(), String> {
+    let line = line.to_lowercase();
+    let mut words = line.split_whitespace().skip(1);
+    while let Some(name) = words.next() {
+        let value = match words.next() {
+            Some(value) => value,
+            None => return Err(format!("timing '{}' has no value", name)),
+        };
+        match name {
+            "seed" => match value.parse() {
+                Ok(value) => *seed = Some(value),
+                Err(_) => return Err(format!("found unknown seed '{}'", value)),
+            },
+            _ => timing.set(name, value)?,
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `verbosity` directive, such as `verbosity quiet`.
+fn parse_verbosity(line: &str, verbosity: &mut Verbosity) -> Result<(), String> {
+    let line = line.to_lowercase();
+    match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["verbosity", value] => {
+            *verbosity = value.parse()?;
+            Ok(())
+        }
+        _ => Err("verbosity must be followed by quiet, normal or verbose".into()),
+    }
+}
+
+/// Apply what a rule that ran asked for, such as changing groups or running another rule.
+fn apply(memory: &mut Memory, result: PostResult) {
+    match result {
+        PostResult::SetEnabled { group, enabled } => {
+            memory.groups.set_enabled(group.as_deref(), enabled);
+        }
+        PostResult::ToggleGroup { group } => memory.groups.toggle(&group),
+        PostResult::Run { rule } => memory.queue_run(rule),
+        PostResult::Set { variable, value } => memory.set(&variable, value),
+        PostResult::None | PostResult::Continue => {}
+    }
+}
+
+/// Skip rules named like an earlier one, and warn about references to rules which don't exist
+/// and variables which are never declared nor set.
+fn check_references(actions: Vec<Action>, memory: &Memory) -> Vec<Action> {
+    let mut names = HashSet::new();
+    let actions = actions
+        .into_iter()
+        .filter(|action| match action.name.as_deref() {
+            Some(name) if !names.insert(name.to_string()) => {
+                eprintln!(
+                    "warning: skipping '{}' because there's already a rule named '{}'",
+                    action.source, name
+                );
+                false
             }
-            Err(_) => {
-                return Err("failed to open poe key file, lack of permissions?");
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    for action in actions.iter() {
+        let pre = action
+            .pre
+            .iter()
+            .chain(action.after_pre.iter().map(|(p, _)| p));
+        let referenced = pre
+            .filter_map(|p| match p {
+                PreCondition::RuleFired { rule } => Some(rule),
+                _ => None,
+            })
+            .chain(match &action.post {
+                PostCondition::Run { rule } => Some(rule),
+                _ => None,
+            });
+        for rule in referenced.filter(|rule| !names.contains(*rule)) {
+            eprintln!(
+                "warning: '{}' refers to rule '{}', which doesn't exist",
+                action.source, rule
+            );
+        }
+    }
+
+    let assigned = actions
+        .iter()
+        .filter_map(|action| match &action.post {
+            PostCondition::Set { variable, .. } => Some(variable.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    for action in actions.iter() {
+        let mut read = Vec::new();
+        for p in action
+            .pre
+            .iter()
+            .chain(action.after_pre.iter().map(|(p, _)| p))
+        {
+            if let PreCondition::Compare {
+                variable, value, ..
+            } = p
+            {
+                read.push(variable.as_str());
+                read.extend(value.variable());
             }
-            Ok(file) => BufReader::new(file)
-                .lines()
-                .map(|line| line.expect("failed to read file"))
-                .flat_map(|line| match Action::from_line(&line) {
+        }
+        if let PostCondition::Set { value, .. } = &action.post {
+            read.extend(value.variables());
+        }
+        for variable in read
+            .into_iter()
+            .filter(|v| !assigned.contains(v) && !memory.is_declared(v))
+        {
+            eprintln!(
+                "warning: '{}' uses variable '{}', which is never set",
+                action.source, variable
+            );
+        }
+    }
+    actions
+}
+
+/// Record the input a rule would have sent, had it not been a dry run.
+fn report_dry_run(journal: &mut Journal, uptime: Duration, a: &Action, intents: Vec<Intent>) {
+    let shown = if a.silent {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    for intent in intents {
+        journal.record(
+            "dry_run",
+            Json::object()
+                .with("rule", a.source.as_str())
+                .with("name", a.name.as_deref())
+                .with("input", intent.to_string()),
+        );
+        journal.note(
+            shown,
+            format_args!("[{:?}] dry run: would {}: {}", uptime, intent, a),
+        );
+    }
+}
+
+/// Record how running the rule went, and show it on the console if the verbosity allows.
+///
+/// Returns what the rule asked for, if it ran successfully.
+fn report(
+    journal: &mut Journal,
+    uptime: Duration,
+    a: &Action,
+    result: TriggerResult,
+    checked: Instant,
+    state: &GameState,
+) -> Option<PostResult> {
+    let shown = if a.silent {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    let event = |result: &str| {
+        Json::object()
+            .with("rule", a.source.as_str())
+            .with("name", a.name.as_deref())
+            .with("group", a.group.as_deref())
+            .with("result", result)
+            .with("latency_ms", checked.elapsed().as_secs_f64() * 1000.0)
+            .with("state", state.to_json())
+    };
+    match result {
+        TriggerResult::Success(result) => {
+            journal.record("trigger", event("success"));
+            journal.note(
+                shown,
+                format_args!("[{:?}] note: ran successfully: {}", uptime, a),
+            );
+            Some(result)
+        }
+        TriggerResult::Failed { reason } => {
+            journal.record("trigger", event("failed").with("reason", reason));
+            eprintln!("[{:?}] warning: run failed: {}: {}", uptime, a, reason);
+            None
+        }
+        TriggerResult::Queued => {
+            journal.record("trigger", event("queued"));
+            journal.note(
+                shown,
+                format_args!("[{:?}] note: queued action: {}", uptime, a),
+            );
+            None
+        }
+        TriggerResult::Delayed => None,
+    }
+}
+
+impl ActionSet {
+    /// Load the rules of the file (and the files it includes) for the given profile, if any.
+    pub fn from_file<P: AsRef<Path>>(path: P, profile: Option<&str>) -> Result<Self, String> {
+        let mut timing = Timing::default();
+        let mut seed = None;
+        let mut memory = Memory::default();
+        let mut verbosity = Verbosity::default();
+        let mut control = None;
+        let key_file = KeyFile::read(path)?;
+        let profile = profile.map(str::to_lowercase);
+        let actions: Vec<Action> = key_file
+            .lines_for(profile.as_deref())
+            .flat_map(|line| {
+                let text = &line.text;
+                let result = match text.split_whitespace().next() {
+                    Some("timing") => parse_timing(text, &mut timing, &mut seed).map(|_| None),
+                    Some("gate") => memory.groups.parse_gate(text).map(|_| None),
+                    Some("set") | Some("counter") => memory.parse_declaration(text).map(|_| None),
+                    Some("verbosity") => parse_verbosity(text, &mut verbosity).map(|_| None),
+                    Some("control") => ControlConfig::parse(text).map(|config| {
+                        control = Some(config);
+                        None
+                    }),
+                    _ => Action::from_line(text),
+                };
+                match result {
                     Ok(action) => action,
                     Err(message) => {
-                        eprintln!("warning: skipping '{}' because {}", line, message);
+                        eprintln!("warning: {}: skipping '{}' because {}", line, text, message);
                         None
                     }
-                })
-                .collect(),
-        };
+                }
+            })
+            .collect();
+        let mut actions = check_references(actions, &memory);
+
+        // The sort is stable, so rules with the same priority keep the order they were written in.
+        actions.sort_by_key(|action| Reverse(action.rank()));
 
+        let rng = match seed {
+            Some(seed) => Rng::new(seed),
+            None => Rng::from_time(),
+        };
         Ok(ActionSet {
             actions,
-            inhibit_key_presses: false,
+            memory,
             created: Instant::now(),
+            timing,
+            input: Dispatcher::new(SystemInput, rng),
+            dry_run: false,
+            verbosity,
+            control,
+            profile,
+            profiles: key_file.profiles(),
         })
     }
 
     pub fn requires(&self, requirement: PreRequirement) -> bool {
+        self.actions.iter().any(|action| {
+            // Consumed keys never reach the game, so only the keyboard hook can see them.
+            (action.consume && requirement == PreRequirement::Keyboard)
+                || action.pre.iter().any(|pre| pre.requires(requirement))
+        }) || (requirement == PreRequirement::Area && self.memory.has_counters())
+    }
+
+    /// The keys that should be kept from reaching the game right now, those of the rules
+    /// consuming them which would run if they were pressed.
+    pub fn consumed_keys(&self, state: &GameState) -> Vec<u16> {
+        let memory = &self.memory;
         self.actions
             .iter()
-            .any(|action| action.pre.iter().any(|pre| pre.requires(requirement)))
+            .filter(|action| {
+                !memory
+                    .groups
+                    .stops(action.group.as_deref(), action.post.sends_input())
+            })
+            .flat_map(|action| action.consumed_keys(state, memory))
+            .collect()
     }
 
-    pub fn check_all(&mut self, state: &GameState, process: &Process) {
-        let actions = &mut self.actions;
-        let inhibit_key_presses = &mut self.inhibit_key_presses;
-        let skip_key_presses = *inhibit_key_presses;
-        let created = &self.created;
-        actions
-            .iter_mut()
-            .map(|a| {
-                a.try_toggle(state);
-                a
+    /// How much should be shown on the console, as configured by the `verbosity` directive.
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// The profiles which have their own rules, which are only loaded for that profile.
+    pub fn profiles(&self) -> &[String] {
+        &self.profiles
+    }
+
+    /// Where to listen for other programs controlling poe-hacks, if anywhere.
+    pub fn control(&self) -> Option<&ControlConfig> {
+        self.control.as_ref()
+    }
+
+    /// The screen detectors needed by the preconditions of any action.
+    pub fn required_detectors(&self) -> Vec<Detector> {
+        Detector::ALL
+            .iter()
+            .copied()
+            .filter(|detector| self.requires(PreRequirement::Screen(*detector)))
+            .collect()
+    }
+
+    /// Run the rules without sending any input to the game (or disconnecting from it),
+    /// recording what they would have sent instead.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn set_enabled(&mut self, group: Option<&str>, enabled: bool) {
+        self.memory.groups.set_enabled(group, enabled);
+    }
+
+    pub fn toggle_group(&mut self, group: &str) {
+        self.memory.groups.toggle(group);
+    }
+
+    /// Find a rule by its name or the line it was written in (ignoring case).
+    fn find(&self, rule: &str) -> Result<usize, String> {
+        let rule = rule.trim();
+        self.actions
+            .iter()
+            .position(|action| {
+                action.source.eq_ignore_ascii_case(rule)
+                    || action
+                        .name
+                        .as_deref()
+                        .is_some_and(|name| name.eq_ignore_ascii_case(rule))
             })
-            .filter(|a| !(skip_key_presses && matches!(a.post, PostCondition::PressKey { .. })))
-            .filter(|a| a.check(state))
-            .for_each(|a| match a.try_trigger(process) {
-                TriggerResult::Success(result) => {
-                    if !a.silent {
-                        eprintln!("[{:?}] note: ran successfully: {}", created.elapsed(), a);
-                    }
-                    match result {
-                        PostResult::SetKeySuppression { suppress } => {
-                            *inhibit_key_presses = suppress;
-                        }
-                        PostResult::None => {}
-                    }
+            .ok_or_else(|| format!("found no rule '{}'", rule))
+    }
+
+    /// Toggle a rule on or off, as if its preconditions were met.
+    pub fn toggle_rule(&mut self, rule: &str) -> Result<(), String> {
+        let i = self.find(rule)?;
+        let action = &mut self.actions[i];
+        match action.toggle {
+            Some(enabled) => {
+                action.toggle = Some(!enabled);
+                Ok(())
+            }
+            None => Err(format!("rule '{}' is not toggled", action.source)),
+        }
+    }
+
+    /// Run a rule right away, regardless of its preconditions and cooldown.
+    ///
+    /// The rules it runs in turn do so during the next check.
+    pub fn fire(
+        &mut self,
+        rule: &str,
+        process: &Process,
+        journal: &mut Journal,
+    ) -> Result<(), String> {
+        let i = self.find(rule)?;
+        let action = &mut self.actions[i];
+        self.input.set_dry_run(self.dry_run || action.dry);
+        let result = action.fire(process, &mut self.input, &self.timing, &self.memory);
+        let uptime = self.created.elapsed();
+        report_dry_run(journal, uptime, action, self.input.take_intents());
+        match result {
+            TriggerResult::Success(result) => {
+                if let Some(name) = action.name.as_deref() {
+                    self.memory.record_fired(name);
+                }
+                apply(&mut self.memory, result);
+                Ok(())
+            }
+            TriggerResult::Failed { reason } => Err(reason.into()),
+            // It keeps going during the next checks.
+            TriggerResult::Queued | TriggerResult::Delayed => Ok(()),
+        }
+    }
+
+    /// How every rule is doing, in the order they're checked.
+    pub fn statuses(&self) -> Vec<RuleStatus> {
+        self.actions.iter().map(|action| action.status()).collect()
+    }
+
+    /// Remember the state of the groups and actions, which would otherwise be lost on restart.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        self.memory.snapshot(snapshot);
+        for action in self.actions.iter() {
+            action.snapshot(snapshot);
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory.restore(snapshot);
+        for action in self.actions.iter_mut() {
+            action.restore(snapshot);
+        }
+    }
+
+    pub fn check_all(&mut self, state: &GameState, process: &Process, journal: &mut Journal) {
+        let uptime = self.created.elapsed();
+        self.memory.next_check(
+            state
+                .area
+                .as_ref()
+                .is_some_and(|area| area.just_transitioned),
+        );
+        let memory = &mut self.memory;
+        // The game state is as old as the check, which is what the latency of every rule counts from.
+        let checked = Instant::now();
+        // The groups in which an exclusive rule already triggered, so the rest of them are skipped.
+        let mut exclusive_ran = HashSet::new();
+        for a in self.actions.iter_mut() {
+            a.try_toggle(state, memory);
+            // Groups changed by earlier rules already apply to the later ones.
+            if memory
+                .groups
+                .stops(a.group.as_deref(), a.post.sends_input())
+                || exclusive_ran.contains(&a.group)
+                || !a.check(state, memory)
+            {
+                continue;
+            }
+            self.input.set_dry_run(self.dry_run || a.dry);
+            let result = a.try_trigger(process, &mut self.input, &self.timing, memory);
+            // Winding up or taking another step counts too, or a lower rule could get in between.
+            if a.exclusive && !matches!(result, TriggerResult::Failed { .. }) {
+                exclusive_ran.insert(a.group.clone());
+            }
+            report_dry_run(journal, uptime, a, self.input.take_intents());
+            if let Some(result) = report(journal, uptime, a, result, checked, state) {
+                if a.post == PostCondition::Disconnect {
+                    // Nothing else matters after disconnecting.
+                    return;
                 }
-                TriggerResult::Failed { reason } => {
-                    eprintln!(
-                        "[{:?}] warning: run failed: {}: {}",
-                        created.elapsed(),
-                        a,
-                        reason
-                    );
+                if let Some(name) = a.name.as_deref() {
+                    memory.record_fired(name);
                 }
-                TriggerResult::Queued => {
-                    if !a.silent {
-                        eprintln!("[{:?}] note: queued action: {}", created.elapsed(), a);
-                    }
+                apply(memory, result);
+            }
+        }
+
+        // Rules run by others skip their preconditions and cooldown, but still only run once per
+        // check, which also keeps rules running each other from going on forever.
+        while let Some(rule) = memory.next_run() {
+            let a = match self
+                .actions
+                .iter_mut()
+                .find(|a| a.name.as_deref() == Some(rule.as_str()))
+            {
+                Some(a) => a,
+                None => continue,
+            };
+            if memory.firing(&rule)
+                || memory
+                    .groups
+                    .stops(a.group.as_deref(), a.post.sends_input())
+            {
+                continue;
+            }
+            self.input.set_dry_run(self.dry_run || a.dry);
+            let result = a.fire(process, &mut self.input, &self.timing, memory);
+            report_dry_run(journal, uptime, a, self.input.take_intents());
+            if let Some(result) = report(journal, uptime, a, result, checked, state) {
+                if a.post == PostCondition::Disconnect {
+                    return;
                 }
-                TriggerResult::Delayed => {}
-            });
+                memory.record_fired(&rule);
+                apply(memory, result);
+            }
+        }
     }
 }
 
 impl fmt::Display for ActionSet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} actions for:", self.actions.len(),)?;
+        if let Some(profile) = self.profile.as_ref() {
+            write!(f, "\n- profile {}", profile)?;
+        }
+        if !self.timing.is_empty() {
+            write!(f, "\n- timing {}", self.timing)?;
+        }
+        if self.verbosity != Verbosity::default() {
+            write!(f, "\n- verbosity {}", self.verbosity)?;
+        }
+        if let Some(control) = self.control.as_ref() {
+            write!(f, "\n- {}", control)?;
+        }
+        for gate in self.memory.groups.gates() {
+            write!(f, "\n- {}", gate)?;
+        }
+        for declaration in self.memory.declarations() {
+            write!(f, "\n- {}", declaration)?;
+        }
         for action in self.actions.iter() {
             write!(f, "\n- {}", action)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rshacks::checker::FocusState;
+
+    fn load(name: &str, rules: &str) -> ActionSet {
+        let path = std::env::temp_dir().join(format!("{}-{}.key", name, std::process::id()));
+        std::fs::write(&path, rules).unwrap();
+        let mut actions = ActionSet::from_file(&path, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        actions.set_dry_run(true);
+        actions
+    }
+
+    fn check(actions: &mut ActionSet) -> Vec<RuleStatus> {
+        let state = GameState {
+            area: None,
+            focus: Some(FocusState {
+                in_foreground: true,
+            }),
+            keyboard: None,
+            mouse: None,
+            player: None,
+            screen: None,
+        };
+        let mut journal = Journal::console(Verbosity::default());
+        actions.check_all(&state, &Process::default(), &mut journal);
+        actions.statuses()
+    }
+
+    #[test]
+    fn exclusive_skips_lower_rules() {
+        let mut actions = load(
+            "exclusive",
+            "on focus group flasks do key 3\n\
+             on focus group flasks priority 1 exclusive do key 2\n\
+             on focus do key 4\n",
+        );
+        let statuses = check(&mut actions);
+        let ran: Vec<bool> = statuses
+            .iter()
+            .map(|s| s.last_triggered.is_some())
+            .collect();
+        // The exclusive rule is ranked first, and only the rule outside its group runs too.
+        assert_eq!(ran, vec![true, false, true]);
+
+        let mut actions = load(
+            "exclusive-windup",
+            "on focus group flasks exclusive after 500ms do key 2\n\
+             on focus group flasks do key 3\n",
+        );
+        let statuses = check(&mut actions);
+        assert!(statuses[0].windup.is_some());
+        assert!(statuses[1].last_triggered.is_none());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::Memory;
+
+/// How a value is compared in a precondition, like `on stacks >= 5`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+/// The arithmetic that can be done when setting a variable, like `do set total stacks * 2`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operand {
+    Number(i64),
+    Variable(String),
+}
+
+/// A value, optionally combined with another one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    pub left: Operand,
+    pub right: Option<(Op, Operand)>,
+}
+
+impl Compare {
+    pub fn holds<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Self::Less => left < right,
+            Self::LessOrEqual => left <= right,
+            Self::Greater => left > right,
+            Self::GreaterOrEqual => left >= right,
+            Self::Equal => left == right,
+            Self::NotEqual => left != right,
+        }
+    }
+}
+
+impl Op {
+    /// Apply the operation, saturating instead of overflowing (and dividing by zero gives zero).
+    pub fn apply(&self, left: i64, right: i64) -> i64 {
+        match self {
+            Self::Add => left.saturating_add(right),
+            Self::Sub => left.saturating_sub(right),
+            Self::Mul => left.saturating_mul(right),
+            Self::Div => left.checked_div(right).unwrap_or(0),
+        }
+    }
+}
+
+impl Operand {
+    pub fn eval(&self, memory: &Memory) -> i64 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Variable(name) => memory.get(name),
+        }
+    }
+
+    pub fn variable(&self) -> Option<&str> {
+        match self {
+            Self::Number(_) => None,
+            Self::Variable(name) => Some(name),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, memory: &Memory) -> i64 {
+        let left = self.left.eval(memory);
+        match &self.right {
+            Some((op, right)) => op.apply(left, right.eval(memory)),
+            None => left,
+        }
+    }
+
+    /// The variables the expression reads.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.left
+            .variable()
+            .into_iter()
+            .chain(self.right.iter().filter_map(|(_, right)| right.variable()))
+    }
+}
+
+impl FromStr for Compare {
+    type Err = String;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        match word {
+            "<" => Ok(Self::Less),
+            "<=" => Ok(Self::LessOrEqual),
+            ">" => Ok(Self::Greater),
+            ">=" => Ok(Self::GreaterOrEqual),
+            "=" | "==" => Ok(Self::Equal),
+            "!=" => Ok(Self::NotEqual),
+            _ => Err(format!("found unknown comparison '{}'", word)),
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        match word {
+            "+" => Ok(Self::Add),
+            "-" => Ok(Self::Sub),
+            "*" => Ok(Self::Mul),
+            "/" => Ok(Self::Div),
+            _ => Err(format!("found unknown operation '{}'", word)),
+        }
+    }
+}
+
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+        })
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+        })
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Variable(name) => f.write_str(name),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.left)?;
+        if let Some((op, right)) = &self.right {
+            write!(f, " {} {}", op, right)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval() {
+        let mut memory = Memory::default();
+        memory.set("stacks", 4);
+        let expr = Expr {
+            left: Operand::Variable("stacks".into()),
+            right: Some((Op::Mul, Operand::Number(3))),
+        };
+        assert_eq!(expr.eval(&memory), 12);
+        assert_eq!(expr.to_string(), "stacks * 3");
+        assert_eq!(expr.variables().collect::<Vec<_>>(), vec!["stacks"]);
+        assert_eq!(Op::Div.apply(5, 0), 0);
+        assert_eq!(Op::Add.apply(i64::MAX, 1), i64::MAX);
+        assert!(">=".parse::<Compare>().unwrap().holds(5, 5));
+        assert!(!"!=".parse::<Compare>().unwrap().holds(5, 5));
+        assert!("=>".parse::<Compare>().is_err());
+    }
+}
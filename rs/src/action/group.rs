@@ -0,0 +1,157 @@
+use rshacks::snapshot::Snapshot;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which rules of a group stop running while the group is disabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Gate {
+    /// Only the rules which send input to the game (keys, clicks, typing...).
+    /// Rules which disconnect, take screenshots or enable groups keep running.
+    #[default]
+    Input,
+    /// Every rule in the group.
+    All,
+}
+
+/// Which rule groups are enabled, and what disabling each of them stops.
+#[derive(Debug, Default, PartialEq)]
+pub struct Groups {
+    /// Set by `do disable` (without a group), and stops every rule sending input.
+    all_disabled: bool,
+    disabled: HashSet<String>,
+    gates: HashMap<String, Gate>,
+}
+
+impl Groups {
+    /// Parse a `gate` directive, such as `gate trade all`.
+    pub fn parse_gate(&mut self, line: &str) -> Result<(), String> {
+        let line = line.to_lowercase();
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["gate", group, gate] => {
+                self.gates.insert(group.to_string(), gate.parse()?);
+                Ok(())
+            }
+            _ => Err("gate must be followed by a group and either input or all".into()),
+        }
+    }
+
+    /// Whether the group is enabled, or every rule sending input when there's no group.
+    pub fn is_enabled(&self, group: Option<&str>) -> bool {
+        match group {
+            Some(group) => !self.disabled.contains(group),
+            None => !self.all_disabled,
+        }
+    }
+
+    pub fn set_enabled(&mut self, group: Option<&str>, enabled: bool) {
+        match group {
+            Some(group) if enabled => {
+                self.disabled.remove(group);
+            }
+            Some(group) => {
+                self.disabled.insert(group.to_string());
+            }
+            None => self.all_disabled = !enabled,
+        }
+    }
+
+    pub fn toggle(&mut self, group: &str) {
+        self.set_enabled(Some(group), !self.is_enabled(Some(group)));
+    }
+
+    pub fn gate(&self, group: &str) -> Gate {
+        self.gates.get(group).copied().unwrap_or_default()
+    }
+
+    /// Whether a rule in the group (if any) should be skipped, given whether it sends input.
+    pub fn stops(&self, group: Option<&str>, sends_input: bool) -> bool {
+        if sends_input && self.all_disabled {
+            return true;
+        }
+        match group {
+            Some(group) if !self.is_enabled(Some(group)) => match self.gate(group) {
+                Gate::Input => sends_input,
+                Gate::All => true,
+            },
+            _ => false,
+        }
+    }
+
+    /// Remember which groups are disabled.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        if self.all_disabled {
+            snapshot.push("all-disabled", true);
+        }
+        let mut disabled = self.disabled.iter().collect::<Vec<_>>();
+        disabled.sort();
+        for group in disabled {
+            snapshot.push("disabled", group);
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.all_disabled = snapshot.get("all-disabled") == Some("true");
+        self.disabled = snapshot.get_all("disabled").map(String::from).collect();
+    }
+
+    /// The gate directives, in the same form they're parsed.
+    pub fn gates(&self) -> Vec<String> {
+        let mut gates = self
+            .gates
+            .iter()
+            .map(|(group, gate)| format!("gate {} {}", group, gate))
+            .collect::<Vec<_>>();
+        gates.sort();
+        gates
+    }
+}
+
+impl FromStr for Gate {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        match word {
+            "input" => Ok(Self::Input),
+            "all" => Ok(Self::All),
+            _ => Err("gate can only be input or all"),
+        }
+    }
+}
+
+impl fmt::Display for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Input => "input",
+            Self::All => "all",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gates() {
+        let mut groups = Groups::default();
+        groups.parse_gate("gate trade all").unwrap();
+        assert!(groups.parse_gate("gate trade some").is_err());
+
+        groups.set_enabled(Some("flasks"), false);
+        assert!(groups.stops(Some("flasks"), true));
+        assert!(!groups.stops(Some("flasks"), false));
+        assert!(!groups.stops(None, true));
+
+        groups.toggle("trade");
+        assert!(!groups.is_enabled(Some("trade")));
+        assert!(groups.stops(Some("trade"), false));
+        groups.toggle("trade");
+        assert!(!groups.stops(Some("trade"), true));
+
+        groups.set_enabled(None, false);
+        assert!(groups.stops(Some("trade"), true));
+        assert!(!groups.stops(Some("trade"), false));
+        assert!(groups.is_enabled(Some("trade")));
+    }
+}
@@ -0,0 +1,230 @@
+//! Reading .key files, along with the files they include.
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A line of a .key file, remembering where it was written.
+pub struct Line {
+    pub file: PathBuf,
+    pub number: usize,
+    pub text: String,
+    /// The profile section the line is in, if any.
+    pub profile: Option<String>,
+}
+
+/// Reads a .key file, replacing the `include` lines with the lines of the files they name.
+#[derive(Default)]
+pub struct KeyFile {
+    pub lines: Vec<Line>,
+    /// The files being read, from the first one to the one currently being read.
+    including: Vec<PathBuf>,
+    /// The files already read, and in which profile section.
+    read: HashSet<(PathBuf, Option<String>)>,
+    /// The files included in a profile section, along with the lines they added.
+    in_sections: Vec<(PathBuf, Range<usize>)>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.number)
+    }
+}
+
+impl KeyFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => "poe key file not found".to_string(),
+            _ => "failed to open poe key file, lack of permissions?".to_string(),
+        })?;
+        let mut key_file = Self::default();
+        key_file.read_file(file, path, None)?;
+
+        // Files included for everyone already apply to every profile, and their rules
+        // shouldn't run twice, whichever include came first.
+        let twice = key_file
+            .in_sections
+            .iter()
+            .filter(|(canonical, _)| key_file.read.contains(&(canonical.clone(), None)))
+            .flat_map(|(_, lines)| lines.clone())
+            .collect::<HashSet<_>>();
+        let mut i = 0;
+        key_file.lines.retain(|_| {
+            i += 1;
+            !twice.contains(&(i - 1))
+        });
+        Ok(key_file)
+    }
+
+    /// The profiles which have their own section, sorted.
+    pub fn profiles(&self) -> Vec<String> {
+        let mut profiles = self
+            .lines
+            .iter()
+            .filter_map(|line| line.profile.clone())
+            .collect::<Vec<_>>();
+        profiles.sort();
+        profiles.dedup();
+        profiles
+    }
+
+    /// The lines which apply to the profile, which are those outside of any section and
+    /// those in the profile's own section.
+    pub fn lines_for<'a>(&'a self, profile: Option<&'a str>) -> impl Iterator<Item = &'a Line> {
+        self.lines
+            .iter()
+            .filter(move |line| line.profile.is_none() || line.profile.as_deref() == profile)
+    }
+
+    /// Read the lines of a file, which are in the given profile section unless it says otherwise.
+    fn read_file(
+        &mut self,
+        file: File,
+        path: &Path,
+        section: Option<String>,
+    ) -> Result<(), String> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.including.push(canonical.clone());
+        self.read.insert((canonical, section.clone()));
+
+        let mut profile = section.clone();
+        for (i, text) in BufReader::new(file).lines().enumerate() {
+            let line = Line {
+                file: path.to_path_buf(),
+                number: i + 1,
+                text: text.map_err(|e| {
+                    format!("{}:{}: could not be read: {}", path.display(), i + 1, e)
+                })?,
+                profile: profile.clone(),
+            };
+            let lowercase = line.text.to_lowercase();
+            match lowercase.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["include", ..] => self.include(&line, path, profile.clone())?,
+                ["profile", "all"] => profile = section.clone(),
+                ["profile", name] => profile = Some(name.to_string()),
+                ["profile", ..] => eprintln!(
+                    "warning: {}: skipping '{}' because profile must be followed by a name or all",
+                    line, line.text
+                ),
+                _ => self.lines.push(line),
+            }
+        }
+
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Read the file named by an `include` line, relative to the file it's written in.
+    ///
+    /// A file which can't be read is an error rather than a warning, since running without
+    /// the rules it has (say, a shared disconnect) is worse than not running at all.
+    fn include(&mut self, line: &Line, path: &Path, profile: Option<String>) -> Result<(), String> {
+        let name = line.text.trim()["include".len()..].trim();
+        if name.is_empty() {
+            eprintln!(
+                "warning: {}: skipping 'include' because it has no file",
+                line
+            );
+            return Ok(());
+        }
+        let included = path.parent().unwrap_or(Path::new("")).join(name);
+        let canonical = fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+        if self.including.contains(&canonical) {
+            eprintln!(
+                "warning: {}: skipping '{}' because it's already being included",
+                line,
+                line.text.trim()
+            );
+            return Ok(());
+        }
+        if self.read.contains(&(canonical.clone(), None))
+            || self.read.contains(&(canonical.clone(), profile.clone()))
+        {
+            // it was already included elsewhere, and its rules shouldn't run twice
+            return Ok(());
+        }
+        let file = File::open(&included)
+            .map_err(|e| format!("{}: could not include '{}': {}", line, name, e))?;
+        let start = self.lines.len();
+        self.read_file(file, &included, profile.clone())?;
+        if profile.is_some() {
+            self.in_sections.push((canonical, start..self.lines.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes_and_profiles() {
+        let dir = std::env::temp_dir().join(format!("key-file-{}", std::process::id()));
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(
+            dir.join("main.key"),
+            "include shared/common.key\non key F1 do flask 1\nprofile Hardcore\ninclude shared/common.key\ninclude shared/late.key\non life 50% do disconnect\nprofile all\ninclude shared/late.key\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("shared/common.key"),
+            "on key F5 do type /hideout\ninclude ../main.key\ninclude common.key\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("shared/late.key"),
+            "on key F6 do type /kingsmarch\n",
+        )
+        .unwrap();
+
+        let key_file = KeyFile::read(dir.join("main.key")).unwrap();
+        let lines = key_file
+            .lines
+            .iter()
+            .map(|line| {
+                format!(
+                    "{}:{} {:?} {}",
+                    line.file.file_name().unwrap().to_string_lossy(),
+                    line.number,
+                    line.profile,
+                    line.text
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "common.key:1 None on key F5 do type /hideout",
+                "main.key:2 None on key F1 do flask 1",
+                "main.key:6 Some(\"hardcore\") on life 50% do disconnect",
+                "late.key:1 None on key F6 do type /kingsmarch",
+            ]
+        );
+        assert_eq!(key_file.profiles(), vec!["hardcore"]);
+        assert_eq!(key_file.lines_for(None).count(), 3);
+        assert_eq!(key_file.lines_for(Some("hardcore")).count(), 4);
+        assert_eq!(key_file.lines_for(Some("softcore")).count(), 3);
+        assert_eq!(
+            KeyFile::read(dir.join("missing.key")).err().as_deref(),
+            Some("poe key file not found")
+        );
+
+        fs::write(
+            dir.join("broken.key"),
+            "on key F1 do flask 1\ninclude missing.key\n",
+        )
+        .unwrap();
+        let err = KeyFile::read(dir.join("broken.key")).err().unwrap();
+        assert!(
+            err.contains("broken.key:2: could not include 'missing.key'"),
+            "{}",
+            err
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use rshacks::snapshot::Snapshot;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use super::Groups;
+
+/// What the rules remember between checks, as opposed to the state of the game.
+#[derive(Debug, Default)]
+pub struct Memory {
+    pub groups: Groups,
+    /// The named rules which ran during the previous check.
+    fired: HashSet<String>,
+    /// The named rules which ran so far during the current check.
+    firing: HashSet<String>,
+    /// When each named rule last ran.
+    last_fired: HashMap<String, Instant>,
+    /// The named rules asked to run by `do run` (or control clients), in order.
+    runs: VecDeque<String>,
+    variables: HashMap<String, i64>,
+    /// The values variables are declared with (by `set` and `counter`), and whether they're
+    /// counters, which go back to that value on every area transition.
+    declared: HashMap<String, (i64, bool)>,
+    /// When the last area transition happened, if one was seen since starting.
+    transitioned: Option<Instant>,
+}
+
+impl Memory {
+    /// Parse a `set` or `counter` directive, such as `set stacks 0` or `counter kills`.
+    pub fn parse_declaration(&mut self, line: &str) -> Result<(), String> {
+        let line = line.to_lowercase();
+        let (counter, name, value) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["set", name, value] => (false, *name, *value),
+            ["counter", name] => (true, *name, "0"),
+            ["counter", name, value] => (true, *name, *value),
+            _ => {
+                return Err(
+                    "expected a variable and its value, like set stacks 0 or counter kills".into(),
+                )
+            }
+        };
+        let name = super::action::variable_name(name)?;
+        let value = value
+            .parse()
+            .map_err(|_| format!("found invalid value '{}' for {}", value, name))?;
+        self.variables.insert(name.clone(), value);
+        self.declared.insert(name, (value, counter));
+        Ok(())
+    }
+
+    /// Start a new check, after which the rules that ran in the last one are seen as fired.
+    ///
+    /// Every rule sees another rule fire exactly once, whether it's checked before or after it.
+    pub fn next_check(&mut self, transitioned: bool) {
+        self.fired = mem::take(&mut self.firing);
+        if transitioned {
+            self.transitioned = Some(Instant::now());
+            for (name, (value, counter)) in self.declared.iter() {
+                if *counter {
+                    self.variables.insert(name.clone(), *value);
+                }
+            }
+        }
+    }
+
+    /// Remember the named rule ran, returning `false` if it already did during this check.
+    pub fn record_fired(&mut self, name: &str) -> bool {
+        self.last_fired.insert(name.to_string(), Instant::now());
+        self.firing.insert(name.to_string())
+    }
+
+    /// Whether the named rule already ran during this check.
+    pub fn firing(&self, name: &str) -> bool {
+        self.firing.contains(name)
+    }
+
+    /// Whether the named rule ran during the previous check.
+    pub fn fired(&self, name: &str) -> bool {
+        self.fired.contains(name)
+    }
+
+    /// How long ago the named rule last ran, if it did.
+    pub fn since_fired(&self, name: &str) -> Option<Duration> {
+        self.last_fired.get(name).map(Instant::elapsed)
+    }
+
+    /// How long ago the last area transition was, if one was seen.
+    pub fn since_transition(&self) -> Option<Duration> {
+        self.transitioned.as_ref().map(Instant::elapsed)
+    }
+
+    pub fn queue_run(&mut self, name: String) {
+        self.runs.push_back(name);
+    }
+
+    pub fn next_run(&mut self) -> Option<String> {
+        self.runs.pop_front()
+    }
+
+    /// The value of the variable, which is 0 until it's set.
+    pub fn get(&self, name: &str) -> i64 {
+        self.variables.get(name).copied().unwrap_or(0)
+    }
+
+    pub fn set(&mut self, name: &str, value: i64) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn is_declared(&self, name: &str) -> bool {
+        self.declared.contains_key(name)
+    }
+
+    pub fn has_counters(&self) -> bool {
+        self.declared.values().any(|(_, counter)| *counter)
+    }
+
+    /// Replace every `{variable}` in the text with its value, leaving anything else in braces as is.
+    pub fn interpolate(&self, text: &str) -> String {
+        let mut result = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match rest.find('}').map(|end| (end, &rest[1..end])) {
+                Some((end, name)) if self.variables.contains_key(name) => {
+                    result.push_str(&self.get(name).to_string());
+                    rest = &rest[end + 1..];
+                }
+                _ => {
+                    result.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// The directives declaring variables, in the same form they're parsed.
+    pub fn declarations(&self) -> Vec<String> {
+        let mut declarations = self
+            .declared
+            .iter()
+            .map(|(name, (value, counter))| {
+                let directive = if *counter { "counter" } else { "set" };
+                format!("{} {} {}", directive, name, value)
+            })
+            .collect::<Vec<_>>();
+        declarations.sort();
+        declarations
+    }
+
+    /// Remember the groups and the values of the variables.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        self.groups.snapshot(snapshot);
+        let mut variables = self.variables.iter().collect::<Vec<_>>();
+        variables.sort();
+        for (name, value) in variables {
+            snapshot.push("variable", format!("{} {}", name, value));
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.groups.restore(snapshot);
+        for value in snapshot.get_all("variable") {
+            if let Some((name, Ok(value))) = value
+                .split_once(' ')
+                .map(|(name, value)| (name, value.parse()))
+            {
+                self.variables.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fired_once() {
+        let mut memory = Memory::default();
+        assert_eq!(memory.since_fired("flask-life"), None);
+        assert!(memory.record_fired("flask-life"));
+        assert!(!memory.record_fired("flask-life"));
+        assert!(!memory.fired("flask-life"));
+        memory.next_check(false);
+        assert!(memory.fired("flask-life"));
+        assert!(memory.since_fired("flask-life").is_some());
+        memory.next_check(false);
+        assert!(!memory.fired("flask-life"));
+    }
+
+    #[test]
+    fn variables() {
+        let mut memory = Memory::default();
+        memory.parse_declaration("set stacks 2").unwrap();
+        memory.parse_declaration("counter kills").unwrap();
+        assert!(memory.parse_declaration("set life 2").is_err());
+        assert!(memory.parse_declaration("set stacks many").is_err());
+        assert!(memory.has_counters());
+        assert_eq!(
+            memory.declarations(),
+            vec!["counter kills 0", "set stacks 2"]
+        );
+
+        memory.set("kills", 7);
+        memory.set("stacks", 5);
+        assert_eq!(
+            memory.interpolate("{kills} kills, {stacks} stacks, {missing} {"),
+            "7 kills, 5 stacks, {missing} {"
+        );
+        assert_eq!(memory.since_transition(), None);
+        memory.next_check(true);
+        assert_eq!(memory.get("kills"), 0);
+        assert_eq!(memory.get("stacks"), 5);
+        assert!(memory.since_transition().is_some());
+
+        let mut snapshot = Snapshot::new(1);
+        memory.snapshot(&mut snapshot);
+        let mut restored = Memory::default();
+        restored.restore(&snapshot);
+        assert_eq!(restored.get("stacks"), 5);
+    }
+}
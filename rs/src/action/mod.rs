@@ -1,8 +1,14 @@
 mod action;
 mod action_set;
+mod expr;
+mod group;
+mod key_file;
+mod memory;
 mod post;
 mod pre;
 
 pub use action_set::ActionSet;
+pub use group::Groups;
+pub use memory::Memory;
 pub use post::{PostCondition, PostResult};
 pub use pre::{GameState, PreCondition, PreRequirement};
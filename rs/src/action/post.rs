@@ -1,39 +1,276 @@
-use crate::win;
-use rshacks::types::{MouseButton, Vk};
+use rshacks::checker;
+use rshacks::detect::{Detector, Layout};
+use rshacks::dispatch::{
+    Dispatcher, InputSink, Intent, VK_CONTROL, VK_LBUTTON, VK_RBUTTON, VK_RETURN, VK_SHIFT,
+};
+use rshacks::grid::{Grid, Selection, Slot};
+use rshacks::item::{Item, ModPattern};
+use rshacks::types::{Modifiers, MouseButton, Position, Vk};
+use rshacks::win;
 use rshacks::win::proc::Process;
 use std::fmt;
 use std::thread::sleep;
 use std::time::Duration;
-use winapi::um::winuser::{VK_HOME, VK_RETURN, VK_RIGHT};
+use winapi::um::winuser::{VK_HOME, VK_RIGHT};
+
+use super::expr::{Expr, Op, Operand};
+use super::Memory;
 
 const DISCONNECT_DELAY: Duration = Duration::from_secs(1);
 
+// How long the game takes to copy an item's text after pressing ctrl+c.
+const COPY_ITEM_DELAY: Duration = Duration::from_millis(100);
+const VK_C: u16 = 0x43;
+
 #[derive(Debug, PartialEq)]
 pub enum PostCondition {
-    PressKey { vk: Vk },
-    Click { button: MouseButton },
+    PressKey {
+        vk: Vk,
+        modifiers: Modifiers,
+    },
+    /// Click while holding the modifiers, optionally at another position.
+    /// With `restore`, the cursor goes back to where it was afterwards.
+    Click {
+        button: MouseButton,
+        modifiers: Modifiers,
+        at: Option<Position>,
+        restore: bool,
+    },
+    Move {
+        to: Position,
+    },
+    /// Scroll the wheel up (positive) or down (negative).
+    Scroll {
+        amount: isize,
+    },
+    Drag {
+        from: Position,
+        to: Position,
+    },
+    /// Ctrl-click the cells of a grid one by one, such as to move items between inventory and stash.
+    CtrlClickCells {
+        grid: Grid,
+        selection: Selection,
+    },
+    /// Apply the currency on the target until the item has the mod, or there are no attempts left.
+    Craft {
+        currency: Slot,
+        target: Slot,
+        attempts: u32,
+        until: ModPattern,
+    },
     Disconnect,
-    Type { string: String },
+    Type {
+        string: String,
+    },
     InviteLast,
     Destroy,
-    SetKeySuppression { suppress: bool },
+    /// Enable or disable a group, or every rule sending input if there's no group.
+    SetEnabled {
+        group: Option<String>,
+        enabled: bool,
+    },
+    ToggleGroup {
+        group: String,
+    },
+    /// Save a capture of the game window (or only the region of the detector).
+    Screenshot {
+        detector: Option<Detector>,
+    },
+    /// Run another named rule right after this one.
+    Run {
+        rule: String,
+    },
+    Set {
+        variable: String,
+        value: Expr,
+    },
 }
 
 #[derive(Debug, PartialEq)]
 pub enum PostResult {
     None,
-    SetKeySuppression { suppress: bool },
+    /// The action isn't done, and takes its next step during the next check.
+    Continue,
+    SetEnabled {
+        group: Option<String>,
+        enabled: bool,
+    },
+    ToggleGroup {
+        group: String,
+    },
+    Run {
+        rule: String,
+    },
+    Set {
+        variable: String,
+        value: i64,
+    },
+}
+
+/// Find where the position is on the screen, which depends on the game window if it's relative to it.
+fn on_screen(process: &Process, position: &Position) -> Result<(isize, isize), &'static str> {
+    let origin = if position.window {
+        let area =
+            win::screen::client_rect(process.pid).map_err(|_| "failed to find game window")?;
+        (area.left, area.top)
+    } else {
+        (0, 0)
+    };
+    Ok(position.on_screen(origin))
+}
+
+fn has_focus(process: &Process) -> bool {
+    win::screen::get_foreground_pid().ok() == Some(process.pid)
+}
+
+/// Copy the text of the item under the cursor and parse it.
+fn copy_item<S: InputSink>(input: &mut Dispatcher<S>) -> Result<Item, &'static str> {
+    // Otherwise a copy that didn't happen would read the item as it was on the last attempt.
+    win::clipboard::clear()?;
+    input.press_with(&[VK_CONTROL], VK_C);
+    sleep(COPY_ITEM_DELAY);
+    match win::clipboard::get() {
+        Ok(text) if !text.is_empty() => Item::parse(&text).ok_or("failed to read the item"),
+        _ => Err("failed to copy the item"),
+    }
+}
+
+/// Take the given step of crafting on the item at `target` with the currency at `currency`.
+fn craft<S: InputSink>(
+    input: &mut Dispatcher<S>,
+    step: u32,
+    currency: (isize, isize),
+    target: (isize, isize),
+    attempts: u32,
+    until: &ModPattern,
+) -> Result<PostResult, &'static str> {
+    let apply = |input: &mut Dispatcher<S>| {
+        input.click(&[], VK_RBUTTON, Some(currency), false);
+        input.click(&[VK_SHIFT], VK_LBUTTON, Some(target), false);
+    };
+    if input.is_dry_run() {
+        // The clipboard would have whatever was copied last, so only the first attempt is shown.
+        input.move_to(target);
+        input.press_with(&[VK_CONTROL], VK_C);
+        apply(input);
+        return Ok(PostResult::None);
+    }
+
+    // Every attempt takes two steps, the first checking the item so that a good item is never
+    // crafted on, and the second applying the currency.
+    if step.is_multiple_of(2) {
+        input.move_to(target);
+        if copy_item(input)?.has_mod(until) {
+            return Ok(PostResult::None);
+        }
+        if step / 2 == attempts {
+            return Err("ran out of attempts without hitting the mod");
+        }
+    } else {
+        apply(input);
+    }
+    Ok(PostResult::Continue)
 }
 
 impl PostCondition {
-    pub fn act(&self, process: &Process) -> Result<PostResult, &'static str> {
+    /// Whether acting sends input to the game, as opposed to only affecting poe-hacks itself
+    /// (or the network, which must keep working even while input is disabled).
+    pub fn sends_input(&self) -> bool {
+        !matches!(
+            self,
+            Self::Disconnect
+                | Self::SetEnabled { .. }
+                | Self::ToggleGroup { .. }
+                | Self::Screenshot { .. }
+                | Self::Run { .. }
+                | Self::Set { .. }
+        )
+    }
+
+    /// Act, or take the given step for those taking one step per check (clicking a cell or
+    /// trying a currency at a time) rather than keeping every other rule waiting until done.
+    pub fn act<S: InputSink>(
+        &self,
+        process: &Process,
+        input: &mut Dispatcher<S>,
+        memory: &Memory,
+        step: u32,
+    ) -> Result<PostResult, &'static str> {
         match self {
-            Self::PressKey { vk } => {
-                win::keyboard::press(vk.0);
+            Self::PressKey { vk, modifiers } => {
+                input.press_with(&modifiers.vks(), vk.0);
+                Ok(PostResult::None)
+            }
+            Self::Click {
+                button,
+                modifiers,
+                at,
+                restore,
+            } => {
+                let at = at.as_ref().map(|at| on_screen(process, at)).transpose()?;
+                input.click(&modifiers.vks(), button.vk(), at, *restore);
+                Ok(PostResult::None)
+            }
+            Self::Move { to } => {
+                input.move_to(on_screen(process, to)?);
                 Ok(PostResult::None)
             }
-            Self::Click { button } => {
-                win::mouse::click(button.0);
+            Self::Scroll { amount } => {
+                input.scroll(*amount);
+                Ok(PostResult::None)
+            }
+            Self::Drag { from, to } => {
+                input.drag(on_screen(process, from)?, on_screen(process, to)?);
+                Ok(PostResult::None)
+            }
+            Self::CtrlClickCells { grid, selection } => {
+                let area = win::screen::client_rect(process.pid)
+                    .map_err(|_| "failed to find game window")?;
+                let layout = Layout::new(area);
+                let cells = grid.select(selection);
+                let cell = match cells.get(step as usize) {
+                    Some(cell) => *cell,
+                    None => return Ok(PostResult::None),
+                };
+                // Stop right away if the user switched to another window, or it'd get the clicks.
+                if !has_focus(process) {
+                    return Err("game window lost focus");
+                }
+                input.click(
+                    &[VK_CONTROL],
+                    VK_LBUTTON,
+                    Some(grid.center(&layout, cell)),
+                    false,
+                );
+                if step as usize + 1 < cells.len() {
+                    Ok(PostResult::Continue)
+                } else {
+                    Ok(PostResult::None)
+                }
+            }
+            Self::Craft {
+                currency,
+                target,
+                attempts,
+                until,
+            } => {
+                if !has_focus(process) {
+                    return Err(if step == 0 {
+                        "game window must have focus to craft"
+                    } else {
+                        "game window lost focus"
+                    });
+                }
+                let area = win::screen::client_rect(process.pid)
+                    .map_err(|_| "failed to find game window")?;
+                let layout = Layout::new(area);
+                let currency = currency.grid.center(&layout, currency.cell);
+                let target = target.grid.center(&layout, target.cell);
+                craft(input, step, currency, target, *attempts, until)
+            }
+            Self::Disconnect if input.is_dry_run() => {
+                input.record(Intent::Disconnect);
                 Ok(PostResult::None)
             }
             Self::Disconnect => match win::proc::kill_network(process.pid) {
@@ -46,30 +283,48 @@ impl PostCondition {
                 }
             },
             Self::Type { string } => {
-                win::keyboard::press(VK_RETURN as u16);
-                win::keyboard::type_string(&string);
-                win::keyboard::press(VK_RETURN as u16);
+                input.press(VK_RETURN);
+                input.type_string(&memory.interpolate(string));
+                input.press(VK_RETURN);
                 Ok(PostResult::None)
             }
             Self::InviteLast => {
-                win::keyboard::ctrl_press(VK_RETURN as u16);
-                win::keyboard::press(VK_HOME as u16);
-                win::keyboard::shift_press(VK_RIGHT as u16);
-                win::keyboard::type_string("/invite ");
-                win::keyboard::ctrl_press(VK_RETURN as u16);
+                input.press_with(&[VK_CONTROL], VK_RETURN);
+                input.press(VK_HOME as u16);
+                input.press_with(&[VK_SHIFT], VK_RIGHT as u16);
+                input.type_string("/invite ");
+                input.press_with(&[VK_CONTROL], VK_RETURN);
 
                 Ok(PostResult::None)
             }
             Self::Destroy => {
-                win::mouse::click(win::mouse::Button::Left);
-                win::keyboard::ctrl_press(VK_RETURN as u16);
-                win::keyboard::type_string("/destroy");
-                win::keyboard::ctrl_press(VK_RETURN as u16);
+                input.press(VK_LBUTTON);
+                input.press_with(&[VK_CONTROL], VK_RETURN);
+                input.type_string("/destroy");
+                input.press_with(&[VK_CONTROL], VK_RETURN);
 
                 Ok(PostResult::None)
             }
-            Self::SetKeySuppression { suppress } => Ok(PostResult::SetKeySuppression {
-                suppress: *suppress,
+            Self::SetEnabled { group, enabled } => Ok(PostResult::SetEnabled {
+                group: group.clone(),
+                enabled: *enabled,
+            }),
+            Self::ToggleGroup { group } => Ok(PostResult::ToggleGroup {
+                group: group.clone(),
+            }),
+            Self::Screenshot { detector } => {
+                match checker::save_window_capture(process.pid, *detector) {
+                    Ok((path, state)) => {
+                        eprintln!("saved capture to {}: {:?}", path.display(), state);
+                        Ok(PostResult::None)
+                    }
+                    Err(_) => Err("failed to capture game window"),
+                }
+            }
+            Self::Run { rule } => Ok(PostResult::Run { rule: rule.clone() }),
+            Self::Set { variable, value } => Ok(PostResult::Set {
+                variable: variable.clone(),
+                value: value.eval(memory),
             }),
         }
     }
@@ -78,19 +333,104 @@ impl PostCondition {
 impl fmt::Display for PostCondition {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::PressKey { vk } => write!(f, "press {vk}"),
-            Self::Click { button } => write!(f, "press {button}"),
+            Self::PressKey { vk, modifiers } => write!(f, "key {modifiers}{vk}"),
+            Self::Click {
+                button,
+                modifiers,
+                at,
+                restore,
+            } => {
+                write!(f, "click {modifiers}{button}")?;
+                if let Some(at) = at {
+                    write!(f, " at {at}")?;
+                }
+                if *restore {
+                    write!(f, " restore")?;
+                }
+                Ok(())
+            }
+            Self::Move { to } => write!(f, "move {to}"),
+            Self::Scroll { amount } => write!(f, "scroll {amount}"),
+            Self::Drag { from, to } => write!(f, "drag from {from} to {to}"),
+            Self::CtrlClickCells { grid, selection } => write!(f, "ctrl-click {grid} {selection}"),
+            Self::Craft {
+                currency,
+                target,
+                attempts,
+                until,
+            } => write!(
+                f,
+                "craft {currency} to {target} attempts {attempts} until {until}"
+            ),
             Self::Disconnect => write!(f, "disconnect"),
             Self::Type { string } => write!(f, "type {}", string),
             Self::InviteLast => write!(f, "invite"),
             Self::Destroy => write!(f, "destroy"),
-            Self::SetKeySuppression { suppress } => {
-                if *suppress {
-                    write!(f, "disable")
-                } else {
-                    write!(f, "enable")
+            Self::SetEnabled { group, enabled } => {
+                f.write_str(if *enabled { "enable" } else { "disable" })?;
+                match group {
+                    Some(group) => write!(f, " {}", group),
+                    None => Ok(()),
+                }
+            }
+            Self::ToggleGroup { group } => write!(f, "toggle-group {}", group),
+            Self::Screenshot { detector } => match detector {
+                Some(detector) => write!(f, "screenshot {}", detector),
+                None => write!(f, "screenshot window"),
+            },
+            Self::Run { rule } => write!(f, "run {}", rule),
+            // written the way `inc` and `dec` are parsed, if that's what it is
+            Self::Set {
+                variable,
+                value:
+                    Expr {
+                        left: Operand::Variable(left),
+                        right: Some((op @ (Op::Add | Op::Sub), by)),
+                    },
+            } if left == variable => {
+                write!(
+                    f,
+                    "{} {}",
+                    if *op == Op::Add { "inc" } else { "dec" },
+                    variable
+                )?;
+                match by {
+                    Operand::Number(1) => Ok(()),
+                    by => write!(f, " {}", by),
                 }
             }
+            Self::Set { variable, value } => write!(f, "set {} {}", variable, value),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rshacks::timing::Rng;
+    use rshacks::win::input::SystemInput;
+
+    #[test]
+    fn craft_dry_run() {
+        let mut input = Dispatcher::new(SystemInput, Rng::new(0));
+        input.set_dry_run(true);
+        let until = "25+% increased movement speed".parse().unwrap();
+        assert_eq!(
+            craft(&mut input, 0, (10, 20), (30, 40), 5, &until),
+            Ok(PostResult::None)
+        );
+        assert_eq!(
+            input
+                .take_intents()
+                .iter()
+                .map(Intent::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "move to 30,40",
+                "press ctrl+C",
+                "click rmb at 10,20",
+                "click shift+lmb at 30,40",
+            ]
+        );
+    }
+}
@@ -1,41 +1,162 @@
-use crate::win;
-use rshacks::checker::{FocusState, LogState, MemoryState, MouseState, ScreenState};
-use rshacks::types::{Direction, Opened, Value, Vk};
+use rshacks::checker::{FocusState, KeyboardState, LogState, MemoryState, MouseState, ScreenState};
+use rshacks::detect::Detector;
+use rshacks::input::KeyTrigger;
+use rshacks::journal::Json;
+use rshacks::types::{Delay, Direction, Modifiers, Opened, Value, Vk};
+use rshacks::win;
 use std::fmt;
 
+use super::expr::{Compare, Operand};
+use super::Memory;
+
 #[derive(Debug, PartialEq)]
 pub enum PreCondition {
-    LifeBelow { threshold: Value },
-    EnergyBelow { threshold: Value },
-    ManaBelow { threshold: Value },
-    KeyPress { vk: Vk },
-    MouseWheel { dir: Direction },
-    InArea { town: bool },
+    LifeBelow {
+        threshold: Value,
+    },
+    EnergyBelow {
+        threshold: Value,
+    },
+    ManaBelow {
+        threshold: Value,
+    },
+    /// Without modifiers, the key triggers regardless of which modifiers are held.
+    /// With modifiers, exactly those must be held.
+    KeyPress {
+        vk: Vk,
+        modifiers: Modifiers,
+        trigger: KeyTrigger,
+    },
+    MouseWheel {
+        dir: Direction,
+    },
+    InArea {
+        town: bool,
+    },
     JustTransitioned,
-    Chat { open: Opened },
+    Chat {
+        open: Opened,
+    },
+    Inventory {
+        open: Opened,
+    },
+    Stash {
+        open: Opened,
+    },
+    MapDevice {
+        open: Opened,
+    },
+    Dead,
     WindowFocus,
     WindowBlur,
+    Group {
+        group: String,
+        enabled: bool,
+    },
+    /// The named rule ran during the previous check.
+    RuleFired {
+        rule: String,
+    },
+    Compare {
+        variable: String,
+        compare: Compare,
+        value: Operand,
+    },
+    /// How long it's been since something happened, which is never true until it happens.
+    Since {
+        since: Since,
+        compare: Compare,
+        duration: Delay,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Since {
+    Transition,
+    Rule(String),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PreRequirement {
     Area,
     Focus,
+    Keyboard,
     Mouse,
     Player,
-    Screen,
+    Screen(Detector),
 }
 
 pub struct GameState {
     pub area: Option<LogState>,
     pub focus: Option<FocusState>,
+    pub keyboard: Option<KeyboardState>,
     pub mouse: Option<MouseState>,
     pub player: Option<MemoryState>,
     pub screen: Option<ScreenState>,
 }
 
+impl GameState {
+    /// The state as it's recorded in the journal, with only the parts that were checked.
+    pub fn to_json(&self) -> Json {
+        Json::object()
+            .with(
+                "area",
+                self.area.as_ref().map(|area| {
+                    Json::object()
+                        .with("town", area.in_town)
+                        .with("just_transitioned", area.just_transitioned)
+                }),
+            )
+            .with(
+                "focus",
+                self.focus
+                    .as_ref()
+                    .map(|focus| Json::object().with("in_foreground", focus.in_foreground)),
+            )
+            .with(
+                "mouse",
+                self.mouse.as_ref().map(|mouse| {
+                    Json::object()
+                        .with("scrolled_up", mouse.scrolled_up)
+                        .with("scrolled_down", mouse.scrolled_down)
+                }),
+            )
+            .with(
+                "player",
+                self.player.as_ref().map(|player| {
+                    Json::object()
+                        .with("hp", player.health.hp)
+                        .with("max_hp", player.health.max_hp)
+                        .with("es", player.health.es)
+                        .with("max_es", player.health.max_es)
+                        .with("mana", player.mana.mana)
+                        .with("max_mana", player.mana.max_mana)
+                }),
+            )
+            .with(
+                "screen",
+                self.screen.as_ref().map(|screen| {
+                    Json::object()
+                        .with("chat", screen.chat_open.to_string())
+                        .with("inventory", screen.inventory_open.to_string())
+                        .with("stash", screen.stash_open.to_string())
+                        .with("map_device", screen.map_device_open.to_string())
+                        .with("dead", screen.dead)
+                }),
+            )
+    }
+}
+
+/// The modifiers currently held down, according to the system.
+fn held_modifiers() -> Modifiers {
+    [Modifiers::CTRL, Modifiers::SHIFT, Modifiers::ALT]
+        .iter()
+        .filter(|modifier| modifier.vks().iter().all(|vk| win::keyboard::is_down(*vk)))
+        .fold(Modifiers::NONE, |held, modifier| held.with(*modifier))
+}
+
 impl PreCondition {
-    pub fn is_valid(&self, state: &GameState) -> bool {
+    pub fn is_valid(&self, state: &GameState, memory: &Memory) -> bool {
         fn ok() {}
         (|| match self {
             Self::LifeBelow { threshold } => threshold
@@ -56,7 +177,28 @@ impl PreCondition {
                     state.player.as_ref()?.mana.max_mana,
                 )
                 .then(ok),
-            Self::KeyPress { vk } => win::keyboard::is_down(vk.0).then(ok),
+            Self::KeyPress {
+                vk,
+                modifiers,
+                trigger: KeyTrigger::Down,
+            } => match state.keyboard.as_ref() {
+                // The hook also sees keys which were consumed and never reached the game.
+                Some(keyboard) => keyboard
+                    .check_chord(vk.0, *modifiers, KeyTrigger::Down)
+                    .then(ok),
+                None => (win::keyboard::is_down(vk.0)
+                    && (modifiers.is_empty() || *modifiers == held_modifiers()))
+                .then(ok),
+            },
+            Self::KeyPress {
+                vk,
+                modifiers,
+                trigger,
+            } => state
+                .keyboard
+                .as_ref()?
+                .check_chord(vk.0, *modifiers, *trigger)
+                .then(ok),
             Self::MouseWheel { dir } => match dir {
                 Direction::Up => state.mouse.as_ref()?.scrolled_up.then(ok),
                 Direction::Down => state.mouse.as_ref()?.scrolled_down.then(ok),
@@ -69,8 +211,34 @@ impl PreCondition {
                 .then(ok),
             Self::JustTransitioned => state.area.as_ref()?.just_transitioned.then(ok),
             Self::Chat { open } => (*open == state.screen.as_ref()?.chat_open).then(ok),
+            Self::Inventory { open } => (*open == state.screen.as_ref()?.inventory_open).then(ok),
+            Self::Stash { open } => (*open == state.screen.as_ref()?.stash_open).then(ok),
+            Self::MapDevice { open } => (*open == state.screen.as_ref()?.map_device_open).then(ok),
+            Self::Dead => state.screen.as_ref()?.dead.then(ok),
             Self::WindowFocus => state.focus.as_ref()?.in_foreground.then(ok),
             Self::WindowBlur => (!state.focus.as_ref()?.in_foreground).then(ok),
+            Self::Group { group, enabled } => {
+                (memory.groups.is_enabled(Some(group)) == *enabled).then(ok)
+            }
+            Self::RuleFired { rule } => memory.fired(rule).then(ok),
+            Self::Compare {
+                variable,
+                compare,
+                value,
+            } => compare
+                .holds(memory.get(variable), value.eval(memory))
+                .then(ok),
+            Self::Since {
+                since,
+                compare,
+                duration,
+            } => {
+                let elapsed = match since {
+                    Since::Transition => memory.since_transition()?,
+                    Since::Rule(rule) => memory.since_fired(rule)?,
+                };
+                compare.holds(elapsed, duration.0).then(ok)
+            }
         })()
         .is_some()
     }
@@ -80,11 +248,25 @@ impl PreCondition {
             Self::LifeBelow { .. } | Self::EnergyBelow { .. } | Self::ManaBelow { .. } => {
                 requirement == PreRequirement::Player
             }
-            Self::KeyPress { .. } => false,
+            Self::KeyPress { trigger, .. } => {
+                *trigger != KeyTrigger::Down && requirement == PreRequirement::Keyboard
+            }
             Self::MouseWheel { .. } => requirement == PreRequirement::Mouse,
             Self::InArea { .. } | Self::JustTransitioned => requirement == PreRequirement::Area,
-            Self::Chat { .. } => requirement == PreRequirement::Screen,
+            Self::Chat { .. } => requirement == PreRequirement::Screen(Detector::Chat),
+            Self::Inventory { .. } => requirement == PreRequirement::Screen(Detector::Inventory),
+            Self::Stash { .. } => requirement == PreRequirement::Screen(Detector::Stash),
+            Self::MapDevice { .. } => requirement == PreRequirement::Screen(Detector::MapDevice),
+            Self::Dead => requirement == PreRequirement::Screen(Detector::Death),
             Self::WindowFocus | Self::WindowBlur => requirement == PreRequirement::Focus,
+            Self::Since {
+                since: Since::Transition,
+                ..
+            } => requirement == PreRequirement::Area,
+            Self::Group { .. }
+            | Self::RuleFired { .. }
+            | Self::Compare { .. }
+            | Self::Since { .. } => false,
         }
     }
 }
@@ -95,7 +277,16 @@ impl fmt::Display for PreCondition {
             Self::LifeBelow { threshold } => write!(f, "life {}", threshold),
             Self::EnergyBelow { threshold } => write!(f, "es {}", threshold),
             Self::ManaBelow { threshold } => write!(f, "mana {}", threshold),
-            Self::KeyPress { vk } => write!(f, "key 0x{:02X}", vk.0),
+            Self::KeyPress {
+                vk,
+                modifiers,
+                trigger: KeyTrigger::Down,
+            } => write!(f, "key {}{}", modifiers, vk),
+            Self::KeyPress {
+                vk,
+                modifiers,
+                trigger,
+            } => write!(f, "key {}{} {}", modifiers, vk, trigger),
             Self::MouseWheel { dir } => write!(
                 f,
                 "wheel {}",
@@ -107,8 +298,38 @@ impl fmt::Display for PreCondition {
             Self::InArea { town } => write!(f, "{}", if *town { "town" } else { "map" }),
             Self::JustTransitioned => write!(f, "transition"),
             Self::Chat { open } => write!(f, "chat {open}"),
+            Self::Inventory { open } => write!(f, "inventory {open}"),
+            Self::Stash { open } => write!(f, "stash {open}"),
+            Self::MapDevice { open } => write!(f, "device {open}"),
+            Self::Dead => write!(f, "death"),
             Self::WindowFocus => write!(f, "focus"),
             Self::WindowBlur => write!(f, "blur"),
+            Self::Group { group, enabled } => write!(
+                f,
+                "group {} {}",
+                group,
+                if *enabled { "enabled" } else { "disabled" }
+            ),
+            Self::RuleFired { rule } => write!(f, "rule {} fired", rule),
+            Self::Compare {
+                variable,
+                compare,
+                value,
+            } => write!(f, "{} {} {}", variable, compare, value),
+            Self::Since {
+                since,
+                compare,
+                duration,
+            } => write!(f, "since {} {} {}", since, compare, duration),
+        }
+    }
+}
+
+impl fmt::Display for Since {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transition => write!(f, "transition"),
+            Self::Rule(rule) => write!(f, "rule {}", rule),
         }
     }
 }
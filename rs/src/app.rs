@@ -0,0 +1,473 @@
+use crate::action::{ActionSet, GameState};
+use crate::checkers::{self, Checkers};
+use rshacks::checker;
+use rshacks::cli::{Cli, Options, Subcommand, CONFIG_FILE, USAGE};
+use rshacks::control::{Command, ControlServer};
+use rshacks::dashboard::{CheckerStatus, Dashboard, Meter};
+use rshacks::journal::{Journal, Json, Verbosity};
+use rshacks::snapshot::Snapshot;
+use rshacks::types::{Delay, Vk};
+use rshacks::win;
+use rshacks::win::proc::Process;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// How much longer than the tick a check can take before it's worth warning about.
+const TOO_LONG: Duration = Duration::from_millis(100);
+const DRAW_DASHBOARD_EVERY: Duration = Duration::from_millis(250);
+const STREAM_STATE_EVERY: Duration = Duration::from_millis(250);
+
+// The state is saved next to the key file, with this extension added.
+const STATE_EXTENSION: &str = "state";
+const SAVE_STATE_EVERY: Duration = Duration::from_secs(30);
+const STATE_MAX_AGE: Duration = Duration::from_secs(15 * 60);
+
+// The journal is written next to the key file too, with this extension added.
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+pub fn main() {
+    match std::panic::catch_unwind(run) {
+        Ok(()) => {}
+        Err(err) => {
+            if let Some(msg) = err.downcast_ref::<String>() {
+                win::prompt::error("poe-hacks crashed!", msg);
+            } else if let Some(msg) = err.downcast_ref::<&str>() {
+                win::prompt::error("poe-hacks crashed!", msg);
+            } else {
+                win::prompt::error(
+                    "poe-hacks crashed!",
+                    "sorry, but there is no error information",
+                );
+            }
+
+            std::process::exit(101);
+        }
+    }
+}
+
+/// Save the state of the actions and checkers which would otherwise be lost on restart.
+fn save_state(path: &str, pid: u32, actions: &ActionSet, checkers: &Checkers) {
+    let mut snapshot = Snapshot::new(pid);
+    actions.snapshot(&mut snapshot);
+    checkers.snapshot(&mut snapshot);
+    if let Err(e) = snapshot.save(path) {
+        eprintln!("warning: failed to save state: {e}");
+    }
+}
+
+/// Run a command received by the control server.
+fn run_command(
+    command: &Command,
+    actions: &mut ActionSet,
+    file: &str,
+    process: &Process,
+    journal: &mut Journal,
+) -> Result<(), String> {
+    match command {
+        Command::SetEnabled { group, enabled } => actions.set_enabled(group.as_deref(), *enabled),
+        Command::ToggleGroup { group } => actions.toggle_group(group),
+        Command::Reload => {
+            let mut reloaded = ActionSet::from_file(file, actions.profile())?;
+            reloaded.set_dry_run(actions.is_dry_run());
+            // Rules which didn't change keep being toggled and cooling down.
+            let mut snapshot = Snapshot::new(process.pid);
+            actions.snapshot(&mut snapshot);
+            reloaded.restore(&snapshot);
+            *actions = reloaded;
+        }
+        Command::Toggle { rule } => actions.toggle_rule(rule)?,
+        Command::Fire { rule } => actions.fire(rule, process, journal)?,
+    }
+    Ok(())
+}
+
+/// Load the rules for the character: those in its own profile section if the file has one,
+/// or else in its own .key file next to the file if there's one, or else the rules for everyone.
+///
+/// Returns whether the rules changed, which they don't if they fail to load.
+fn switch_character(
+    actions: &mut ActionSet,
+    rules_file: &mut String,
+    file: &str,
+    profiles: &[String],
+    character: &str,
+) -> bool {
+    let own_file = Path::new(file).with_file_name(format!("{}.key", character));
+    let (switched_file, profile) = if profiles.contains(&character.to_lowercase()) {
+        (file.to_string(), Some(character))
+    } else if own_file.is_file() {
+        (own_file.to_string_lossy().into_owned(), None)
+    } else {
+        (file.to_string(), None)
+    };
+    if switched_file == *rules_file
+        && actions.profile() == profile.map(str::to_lowercase).as_deref()
+    {
+        return false;
+    }
+    match ActionSet::from_file(&switched_file, profile) {
+        Ok(mut switched) => {
+            switched.set_dry_run(actions.is_dry_run());
+            eprintln!(
+                "{} is playing, using the rules in '{}'",
+                character, switched_file
+            );
+            *actions = switched;
+            *rules_file = switched_file;
+            true
+        }
+        Err(e) => {
+            eprintln!("warning: could not load the rules for {}: {}", character, e);
+            false
+        }
+    }
+}
+
+/// Gather what's shown on the dashboard from the last state and the checkers in use.
+fn dashboard(state: &GameState, actions: &ActionSet, running: &Checkers) -> Dashboard {
+    let mut checkers = Vec::new();
+    if running.player.is_some() {
+        checkers.push(CheckerStatus {
+            name: "ptr.map",
+            healthy: state.player.is_some(),
+        });
+    }
+    if let Some(checker) = running.area.as_ref() {
+        checkers.push(CheckerStatus {
+            name: "log file",
+            healthy: checker.is_open(),
+        });
+    }
+    if let Some(checker) = running.screen.as_ref() {
+        checkers.push(CheckerStatus {
+            name: "screen",
+            healthy: checker.is_alive(),
+        });
+    }
+
+    let player = state.player.as_ref();
+    Dashboard {
+        life: player.map(|p| Meter {
+            current: p.health.hp,
+            max: p.health.max_hp,
+        }),
+        energy_shield: player.map(|p| Meter {
+            current: p.health.es,
+            max: p.health.max_es,
+        }),
+        mana: player.map(|p| Meter {
+            current: p.mana.mana,
+            max: p.mana.max_mana,
+        }),
+        in_town: state.area.as_ref().and_then(|area| area.in_town),
+        chat: state.screen.as_ref().map(|screen| screen.chat_open),
+        focus: state.focus.as_ref().map(|focus| focus.in_foreground),
+        checkers,
+        rules: actions.statuses(),
+    }
+}
+
+/// Draw the dashboard over the previous one, blanking the lines left over from it.
+fn draw_dashboard(dashboard: &Dashboard, drawn: &mut usize) {
+    // Writing up to the last column would wrap into the next line.
+    let width = win::console::size().0.saturating_sub(1);
+    let lines = dashboard.render(width);
+    win::console::set_cursor(0, 0);
+    let mut out = io::stdout().lock();
+    for line in lines.iter() {
+        let _ = writeln!(out, "{:<width$}", line);
+    }
+    for _ in lines.len()..*drawn {
+        let _ = writeln!(out, "{:width$}", "");
+    }
+    let _ = out.flush();
+    *drawn = lines.len();
+}
+
+/// Read the options from the config file next to the executable, and then from the command line.
+fn parse_cli() -> Cli {
+    let mut options = Options::default();
+    if let Ok(exe) = std::env::current_exe() {
+        let config = exe.with_file_name(CONFIG_FILE);
+        match fs::read_to_string(&config) {
+            Ok(text) => options
+                .apply_config(&text)
+                .unwrap_or_else(|e| panic!("invalid config file: {}", e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("warning: could not read {}: {e}", config.display()),
+        }
+    }
+    Cli::parse(std::env::args().skip(1), options).unwrap_or_else(|e| panic!("{}\n\n{}", e, USAGE))
+}
+
+fn open_process(options: &Options) -> Process {
+    Process::open_by_name(&options.process).expect("could not find poe running")
+}
+
+/// Save a capture of the game window after the delay (to have time to switch to it).
+fn capture(options: &Options, delay: Delay) {
+    let process = open_process(options);
+    sleep(delay.0);
+    let (path, state) =
+        checker::save_window_capture(process.pid, None).expect("failed to capture game window");
+    eprintln!("saved capture to {}: {:?}", path.display(), state);
+}
+
+/// Load the rules and show them, along with whatever is wrong with them (as warnings).
+fn check(options: &Options) {
+    let actions = ActionSet::from_file(&options.file, options.profile.as_deref())
+        .unwrap_or_else(|e| panic!("failed to load action set from '{}': {}", options.file, e));
+    println!("loaded {}", actions);
+    if !actions.profiles().is_empty() {
+        println!("profiles: {}", actions.profiles().join(", "));
+    }
+}
+
+fn scan_ptr(options: &Options) {
+    let process = Rc::new(open_process(options));
+    checkers::scan_ptr_map(
+        &process,
+        &checkers::ptr_map_path(options.ptr_map.as_deref()),
+    );
+}
+
+/// Show the names of the keys which can be used in rules, along with their codes.
+fn list_keys() {
+    for (name, vk) in Vk::names() {
+        println!("{:<12} 0x{:02X}", name, vk.0);
+    }
+    println!("a to z, 0 to 9 and f1 to f24 can be used as they are.");
+    println!("any other key can be used by its code, like 0xE2.");
+}
+
+fn run() {
+    let Cli { command, options } = parse_cli();
+    match command {
+        Subcommand::Run | Subcommand::Simulate => run_rules(&options),
+        Subcommand::Check => check(&options),
+        Subcommand::ScanPtr => scan_ptr(&options),
+        Subcommand::Capture { delay } => capture(&options, delay),
+        Subcommand::ListKeys => list_keys(),
+        Subcommand::Help => println!("{}", USAGE),
+    }
+}
+
+fn run_rules(options: &Options) {
+    let file = options.file.clone();
+    let profile = options.profile.clone();
+    // The dashboard replaces the usual messages, other than warnings.
+    let show_dashboard = options.dashboard;
+    let mut actions = ActionSet::from_file(&file, profile.as_deref())
+        .expect(&format!("failed to load action set from '{}'", file));
+    actions.set_dry_run(options.dry_run);
+    let journal_path = format!("{}.{}", file, JOURNAL_EXTENSION);
+    let verbosity = if show_dashboard {
+        Verbosity::Quiet
+    } else {
+        actions.verbosity()
+    };
+    let mut journal = Journal::open(&journal_path, verbosity).unwrap_or_else(|e| {
+        eprintln!("warning: journal will not be written: {e}");
+        Journal::console(verbosity)
+    });
+    journal.record(
+        "start",
+        Json::object()
+            .with("file", file.as_str())
+            .with("dry_run", options.dry_run),
+    );
+
+    let control = actions.control().and_then(|config| {
+        match ControlServer::start(config) {
+            Ok(server) => {
+                eprintln!("listening for control on {}", server.address());
+                if config.is_remote() && config.token.is_none() {
+                    eprintln!("warning: the control token changes every time, consider adding one");
+                }
+                if config.token.is_none() {
+                    eprintln!(
+                        "control token for this session: {} (add a token to the control line to keep one)",
+                        server.token()
+                    );
+                }
+                Some(server)
+            }
+            Err(e) => {
+                eprintln!("warning: could not start control server: {e}");
+                None
+            }
+        }
+    });
+    if let Some(server) = control.as_ref() {
+        let broadcaster = server.broadcaster();
+        journal.forward(move |line| broadcaster.send(line));
+    }
+    eprintln!("loaded action set from '{}'", file);
+    journal.note(Verbosity::Normal, format_args!("loaded {}", actions));
+    if options.dry_run {
+        eprintln!("dry run: the input rules would send is only shown and written to the journal");
+    }
+
+    if options.start_gate {
+        eprintln!("waiting for right click...");
+        while !win::keyboard::is_down(0x02) {
+            sleep(options.tick);
+        }
+        while win::keyboard::is_down(0x02) {
+            sleep(options.tick);
+        }
+    }
+
+    let process = Rc::new(open_process(options));
+
+    // Without a profile given, the rules follow the character playing.
+    let follow_character = profile.is_none();
+    let base_profiles = actions.profiles().to_vec();
+    let mut character = None;
+    let mut rules_file = file.clone();
+    let mut checkers = Checkers::new(
+        Rc::clone(&process),
+        checkers::ptr_map_path(options.ptr_map.as_deref()),
+    );
+    if follow_character {
+        checkers.follow_log();
+        match checkers.character() {
+            Some(playing) => {
+                character = Some(playing.to_string());
+                switch_character(
+                    &mut actions,
+                    &mut rules_file,
+                    &file,
+                    &base_profiles,
+                    playing,
+                );
+                journal.note(Verbosity::Normal, format_args!("loaded {}", actions));
+            }
+            None => eprintln!("note: could not tell which character is playing yet"),
+        }
+    }
+    checkers.start(&actions);
+
+    let state_path = format!("{}.{}", file, STATE_EXTENSION);
+    if let Some(snapshot) = Snapshot::load_fresh(&state_path, STATE_MAX_AGE, process.pid) {
+        eprintln!("restoring state saved {:?} ago", snapshot.age());
+        actions.restore(&snapshot);
+        checkers.restore(&snapshot);
+    }
+    if !win::console::catch_exit() {
+        eprintln!("warning: state will not be saved on exit");
+    }
+
+    // Nothing is sent in place of the keys in a dry run, so they must reach the game as usual.
+    win::hook::set_consume_target((!actions.is_dry_run()).then_some(process.pid));
+    println!("poe-hacks is now running");
+    let mut last = Instant::now();
+    let mut last_saved = Instant::now();
+    let mut last_drawn = Instant::now();
+    let mut last_streamed = Instant::now();
+    let mut drawn_lines = 0;
+    if show_dashboard {
+        win::console::clear();
+    }
+    loop {
+        if win::console::exit_requested() {
+            save_state(&state_path, process.pid, &actions, &checkers);
+            journal.record("exit", Json::object());
+            win::console::exit_done();
+            return;
+        }
+        if last_saved.elapsed() > SAVE_STATE_EVERY {
+            save_state(&state_path, process.pid, &actions, &checkers);
+            last_saved = Instant::now();
+        }
+
+        let now = Instant::now();
+        if (now - last) > options.tick + TOO_LONG {
+            journal.warn(format_args!("check is taking too long: {:?}", now - last));
+        }
+        last = now;
+        sleep(options.tick);
+
+        let state = checkers.check(&mut journal);
+
+        if follow_character {
+            if let Some(playing) = checkers.character().map(str::to_string) {
+                if character.as_ref() != Some(&playing) {
+                    if switch_character(
+                        &mut actions,
+                        &mut rules_file,
+                        &file,
+                        &base_profiles,
+                        &playing,
+                    ) {
+                        journal.record(
+                            "character",
+                            Json::object()
+                                .with("character", playing.as_str())
+                                .with("file", rules_file.as_str())
+                                .with("profile", actions.profile()),
+                        );
+                        journal.note(Verbosity::Normal, format_args!("loaded {}", actions));
+                        checkers.update(&actions);
+                    }
+                    character = Some(playing);
+                }
+            }
+        }
+
+        if show_dashboard && last_drawn.elapsed() > DRAW_DASHBOARD_EVERY {
+            let dashboard = dashboard(&state, &actions, &checkers);
+            draw_dashboard(&dashboard, &mut drawn_lines);
+            last_drawn = Instant::now();
+        }
+
+        if let Some(server) = control.as_ref() {
+            for command in server.commands() {
+                let result =
+                    run_command(&command, &mut actions, &rules_file, &process, &mut journal);
+                journal.record(
+                    "command",
+                    Json::object()
+                        .with("command", command.to_string())
+                        .with("result", if result.is_ok() { "success" } else { "failed" })
+                        .with("reason", result.as_ref().err().map(String::as_str)),
+                );
+                match result {
+                    Ok(()) if command == Command::Reload => {
+                        journal.note(Verbosity::Normal, format_args!("reloaded {}", actions));
+                        checkers.update(&actions);
+                    }
+                    Ok(()) => {}
+                    Err(e) => eprintln!("warning: command '{}' failed: {}", command, e),
+                }
+            }
+
+            if last_streamed.elapsed() > STREAM_STATE_EVERY {
+                server.set_state(
+                    Json::object()
+                        .with("event", "state")
+                        .with("state", state.to_json())
+                        .to_string(),
+                );
+                last_streamed = Instant::now();
+            }
+        }
+
+        if let Some(player) = state.player.as_ref() {
+            if player.health.hp == 0 {
+                // Don't bother running checks if the player is dead, nor keep keys from the game.
+                win::hook::set_consumed_keys(&[]);
+                continue;
+            }
+        }
+
+        actions.check_all(&state, process.as_ref(), &mut journal);
+        // What the rules need changes with the game, so the keys to consume are updated every
+        // check (and keys are let through while their rule is cooling down, or out of town...).
+        win::hook::set_consumed_keys(&actions.consumed_keys(&state));
+    }
+}
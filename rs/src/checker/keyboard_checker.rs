@@ -0,0 +1,44 @@
+use crate::input::{KeyEvent, KeyEventSource, KeyTracker};
+use crate::win;
+use std::time::Instant;
+
+pub struct KeyboardChecker {
+    tracker: KeyTracker,
+}
+
+pub type KeyboardState = KeyTracker;
+
+/// The events recorded by the low-level keyboard and mouse hooks.
+struct HookEvents;
+
+impl KeyEventSource for HookEvents {
+    fn poll(&mut self) -> Vec<KeyEvent> {
+        win::hook::poll_key_events()
+    }
+}
+
+impl KeyboardChecker {
+    pub fn new() -> Self {
+        win::hook::install_keyboard_hook();
+        Self {
+            tracker: KeyTracker::new(),
+        }
+    }
+
+    pub fn check(&mut self) -> KeyboardState {
+        self.tracker.update(&mut HookEvents, Instant::now());
+        self.tracker.clone()
+    }
+}
+
+impl Default for KeyboardChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for KeyboardChecker {
+    fn drop(&mut self) {
+        win::hook::uninstall_keyboard_hook()
+    }
+}
@@ -1,17 +1,17 @@
+use crate::client_log::{Characters, ClientLog, LogEvent};
+use crate::snapshot::Snapshot;
 use crate::win::proc::Process;
-use std::fs::File;
-use std::io::{self, BufRead as _, BufReader, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-trait ReadSeek: Read + Seek {}
-
-impl<T: Read + Seek> ReadSeek for T {}
-
 pub struct LogChecker {
     process: Rc<Process>,
-    log_buffer: String,
-    log_reader: BufReader<Box<dyn ReadSeek>>,
+    log: Option<ClientLog>,
+    /// Only known once an area has been entered.
+    in_town: Option<bool>,
+    /// The characters which levelled up since the player last logged in.
+    characters: Characters,
 }
 
 pub struct LogState {
@@ -19,75 +19,101 @@ pub struct LogState {
     pub just_transitioned: bool,
 }
 
+/// Where the game writes its log, next to its executable.
+pub fn log_path(process: &Process) -> io::Result<PathBuf> {
+    let file = process.file_name()?;
+    let mut path = Path::new(&file).parent().unwrap().to_path_buf();
+    path.push("logs");
+    path.push("Client.txt");
+    Ok(path)
+}
+
 impl LogChecker {
     pub fn new(process: Rc<Process>) -> Self {
         Self {
             process,
-            log_buffer: String::new(),
-            log_reader: BufReader::new(Box::new(io::empty())),
+            log: None,
+            in_town: None,
+            characters: Characters::default(),
+        }
+    }
+
+    /// Remember the area the player is in, if known.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        if let Some(in_town) = self.in_town {
+            snapshot.push("town", in_town);
+        }
+    }
+
+    /// Restore the area the player was in, unless the game was restarted since.
+    ///
+    /// The log knows better, so this only applies if it didn't say which area was last entered.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if self.in_town.is_none() && snapshot.pid() == self.process.pid {
+            if let Some(town) = snapshot.get("town") {
+                self.in_town = town.parse().ok();
+            }
         }
     }
 
+    /// Open the game's log and find the area the player is in from the last one entered.
     pub fn init(&mut self) -> Result<(), &'static str> {
-        match self.process.file_name() {
-            Ok(file) => {
-                let mut path = Path::new(&file).parent().unwrap().to_path_buf();
-                path.push("logs");
-                path.push("Client.txt");
-                match File::open(path) {
-                    Ok(f) => {
-                        self.log_reader = BufReader::new(Box::new(f));
-                        if let Err(e) = self.log_reader.seek(SeekFrom::End(-16 * 1024)) {
-                            eprintln!(
-                                    "warning: could not seek log file, may take a while to catch-up: {e}");
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("warning: could not open log file, log checks won't work: {e}")
-                    }
-                }
+        let path = match log_path(&self.process) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("warning: could not find log file, log checks won't work: {e}");
+                return Ok(());
             }
+        };
+        let log = match ClientLog::open(path) {
+            Ok(log) => log,
             Err(e) => {
-                eprintln!("warning: could not find log file, log checks won't work: {e}")
+                eprintln!("warning: could not open log file, log checks won't work: {e}");
+                return Ok(());
             }
+        };
+        match log.last_area() {
+            Ok(Some(town)) => self.in_town = Some(town),
+            Ok(None) => eprintln!("note: no area was entered yet according to the log file"),
+            Err(e) => eprintln!("warning: could not find the last area in the log file: {e}"),
+        }
+        match log.session_characters() {
+            Ok(characters) => self.characters = characters,
+            Err(e) => eprintln!("warning: could not find the character in the log file: {e}"),
         }
+        self.log = Some(log);
 
         Ok(())
     }
 
+    /// Whether the log file could be opened.
+    pub fn is_open(&self) -> bool {
+        self.log.is_some()
+    }
+
+    /// The character playing, once the log leaves no doubt about it.
+    pub fn character(&self) -> Option<&str> {
+        self.characters.playing()
+    }
+
     pub fn check(&mut self) -> Result<LogState, &'static str> {
         let mut result = LogState {
-            in_town: None,
+            in_town: self.in_town,
             just_transitioned: false,
         };
 
-        loop {
-            self.log_buffer.clear();
-            match self.log_reader.read_line(&mut self.log_buffer) {
-                Ok(n) => {
-                    if n == 0 {
-                        break;
-                    }
-                    if let Some(i) = self.log_buffer.find("]") {
-                        let msg = self.log_buffer[i + 1..].trim();
-                        if msg.starts_with("Generating level") {
-                            let mut matcher = msg.match_indices('"');
-                            if let Some((start, end)) = matcher.next().zip(matcher.next()) {
-                                let level = &msg[start.0 + 1..end.0];
-                                result.in_town = Some(level.ends_with("_town"));
-                            }
-                        } else if msg.starts_with("[SHADER] Delay: ON") {
-                            result.just_transitioned = true; // and finished loading
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("warning: failed to read from log file: {e}");
-                    break;
+        if let Some(log) = self.log.as_mut() {
+            for event in log.poll() {
+                self.characters.update(&event);
+                match event {
+                    LogEvent::Area { town } => result.in_town = Some(town),
+                    LogEvent::Transitioned => result.just_transitioned = true,
+                    _ => {}
                 }
             }
         }
 
+        self.in_town = result.in_town;
         Ok(result)
     }
 }
@@ -1,11 +1,15 @@
 mod focus_checker;
+mod keyboard_checker;
 mod log_checker;
 mod memory_checker;
 mod mouse_checker;
 mod screen_checker;
 
 pub use focus_checker::{FocusChecker, FocusState};
-pub use log_checker::{LogChecker, LogState};
+pub use keyboard_checker::{KeyboardChecker, KeyboardState};
+pub use log_checker::{log_path, LogChecker, LogState};
 pub use memory_checker::{MemoryChecker, MemoryState};
 pub use mouse_checker::{MouseChecker, MouseState};
-pub use screen_checker::{ScreenChecker, ScreenState};
+pub use screen_checker::{save_window_capture, ScreenChecker};
+
+pub use crate::detect::ScreenState;
@@ -1,71 +1,145 @@
-use crate::types::Opened;
+use crate::detect::{self, Detector, Layout, ScreenState};
 use crate::win;
+use std::fs;
+use std::io::Error;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 use std::time::{Duration, Instant};
+use win::screen::{Rect, Screen};
 
 const DELAY: Duration = Duration::from_millis(50);
+// How often to look for the game window in case it moved or was resized.
+const LAYOUT_DELAY: Duration = Duration::from_secs(1);
 
-const CHAT_BORDER_THICKNESS: usize = 2;
-const CHAT_APPROX_START_PCT: usize = 70;
-const CHAT_APPROX_HEIGHT_PCT: usize = 5;
-const CHAT_BORDER_COLOR: (u8, u8, u8) = (136, 98, 59);
-const CHAT_CHECK_HEIGHT: usize = 32;
-
-enum Message {
-    Chat { open: Opened },
-}
+const CAPTURE_DIR: &str = "captures";
 
 pub struct ScreenChecker {
-    rx: mpsc::Receiver<Message>,
+    rx: mpsc::Receiver<ScreenState>,
     tx: mpsc::Sender<()>,
     handle: Option<thread::JoinHandle<()>>,
-    chat_open: Opened,
+    state: ScreenState,
 }
 
-pub struct ScreenState {
-    pub chat_open: Opened,
+/// Detectors sharing the same capture, because their regions overlap.
+struct Capture {
+    screen: Screen,
+    detectors: Vec<(Detector, Rect)>,
 }
 
-fn check_chat(screen: &win::screen::Screen) -> Message {
-    let mut longest_run = 0;
-    for color in screen.screenshot().colors() {
-        if color == CHAT_BORDER_COLOR {
-            longest_run += 1;
-            if longest_run >= CHAT_CHECK_HEIGHT * CHAT_BORDER_THICKNESS {
-                return Message::Chat { open: Opened::Open };
-            }
-        } else {
-            longest_run = 0;
+/// Group the detectors whose regions overlap so that they can share a single capture.
+fn merge_regions(detectors: &[Detector], layout: &Layout) -> Vec<(Rect, Vec<(Detector, Rect)>)> {
+    let mut merged: Vec<(Rect, Vec<(Detector, Rect)>)> = Vec::new();
+    for detector in detectors {
+        let region = match detector.region(layout) {
+            Some(region) => region,
+            None => continue,
+        };
+        let mut capture = (region.clone(), vec![(*detector, region)]);
+        // Merging two captures can make the result overlap with a capture that was
+        // previously disjoint, so keep merging until nothing else overlaps.
+        while let Some(i) = merged.iter().position(|(r, _)| r.overlaps(&capture.0)) {
+            let (r, ds) = merged.swap_remove(i);
+            capture.0 = capture.0.union(&r);
+            capture.1.extend(ds);
         }
+        merged.push(capture);
     }
-    Message::Chat {
-        open: Opened::Closed,
-    }
+    merged
+}
+
+/// Create the captures needed by the detectors, sharing them where possible.
+fn create_captures(detectors: &[Detector], layout: &Layout) -> Result<Vec<Capture>, Error> {
+    merge_regions(detectors, layout)
+        .into_iter()
+        .map(|(region, detectors)| {
+            Ok(Capture {
+                screen: Screen::capture_region(region)?,
+                detectors,
+            })
+        })
+        .collect()
+}
+
+/// Save a capture of the game window into the captures folder, along with the detector verdicts.
+///
+/// If a detector is given, only the region it looks at is saved.
+pub fn save_window_capture(
+    pid: u32,
+    detector: Option<Detector>,
+) -> Result<(PathBuf, ScreenState), Error> {
+    let screenshot = win::screen::capture_window(pid)?;
+    fs::create_dir_all(CAPTURE_DIR)?;
+    let mut path = PathBuf::from(CAPTURE_DIR);
+    let timestamp = chrono::Local::now().format("%Y%m%d.%H%M%S%.3f");
+    let state = match detector {
+        Some(detector) => {
+            path.push(format!("{}.{}.png", timestamp, detector));
+            detect::save_detector_capture(&screenshot, detector, &path)?
+        }
+        None => {
+            path.push(format!("{}.png", timestamp));
+            detect::save_capture(&screenshot, &path)?
+        }
+    };
+    Ok((path, state))
 }
 
 impl ScreenChecker {
-    pub fn new() -> Self {
+    /// Create a checker running the given detectors on the main window of the process.
+    ///
+    /// The regions to capture follow the window as it moves or gets resized.
+    pub fn new(pid: u32, detectors: Vec<Detector>) -> Self {
         let (msg_tx, msg_rx) = mpsc::channel();
         let (kill_tx, kill_rx) = mpsc::channel();
 
         let handle = thread::spawn(move || {
-            let mut size = win::screen::size().unwrap();
-            size.width = CHAT_BORDER_THICKNESS;
-            size.top += (CHAT_APPROX_START_PCT * size.height) / 100;
-            size.height = (CHAT_APPROX_HEIGHT_PCT * size.height) / 100;
-            let mut screen = win::screen::Screen::capture_region(size).unwrap();
+            let mut layout = None;
+            let mut captures = Vec::new();
+            let mut last_layout_check = None::<Instant>;
 
+            let mut state = ScreenState::default();
             loop {
                 let start = Instant::now();
-                match screen.refresh() {
-                    Ok(_) => {
-                        if msg_tx.send(check_chat(&screen)).is_err() {
-                            break;
+                if last_layout_check.is_none_or(|t| t.elapsed() >= LAYOUT_DELAY) {
+                    last_layout_check = Some(start);
+                    let new_layout = win::screen::client_rect(pid)
+                        .map(Layout::new)
+                        .ok()
+                        .filter(|layout| !layout.is_empty());
+                    match new_layout {
+                        Some(new_layout) if layout.as_ref() != Some(&new_layout) => {
+                            match create_captures(&detectors, &new_layout) {
+                                Ok(c) => {
+                                    captures = c;
+                                    layout = Some(new_layout);
+                                }
+                                Err(e) => eprintln!("warning: screen capture failed: {}", e),
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            // The window is gone or minimized, so there's nothing to look at.
+                            captures.clear();
+                            layout = None;
                         }
                     }
-                    Err(e) => eprintln!("warning: screen check failed: {}", e),
-                };
+                }
+
+                for capture in captures.iter_mut() {
+                    match capture.screen.refresh() {
+                        Ok(_) => {
+                            let screenshot = capture.screen.screenshot();
+                            for (detector, region) in capture.detectors.iter() {
+                                detector.detect(&screenshot.crop(region), &mut state);
+                            }
+                        }
+                        Err(e) => eprintln!("warning: screen check failed: {}", e),
+                    };
+                }
+                if msg_tx.send(state).is_err() {
+                    break;
+                }
 
                 match kill_rx.recv_timeout(DELAY.saturating_sub(start.elapsed())) {
                     Ok(_) => break,
@@ -78,22 +152,30 @@ impl ScreenChecker {
             rx: msg_rx,
             tx: kill_tx,
             handle: Some(handle),
-            chat_open: Opened::Closed,
+            state: ScreenState::default(),
         }
     }
 
+    /// Whether the thread capturing the screen is still running.
+    pub fn is_alive(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
     pub fn check(&mut self) -> ScreenState {
         loop {
             match self.rx.try_recv() {
-                Ok(Message::Chat { open }) => self.chat_open = open,
+                Ok(state) => self.state = state,
                 Err(mpsc::TryRecvError::Empty) => break,
-                Err(mpsc::TryRecvError::Disconnected) => self.chat_open = Opened::Closed,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.state = ScreenState::default();
+                    break;
+                }
             }
         }
 
-        ScreenState {
-            chat_open: self.chat_open,
-        }
+        self.state
     }
 }
 
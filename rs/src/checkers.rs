@@ -0,0 +1,400 @@
+//! The checkers finding out the state of the game, which are started and stopped as the rules
+//! need them.
+use crate::action::{ActionSet, GameState, PreRequirement};
+use rshacks::checker::{
+    FocusChecker, KeyboardChecker, LogChecker, MemoryChecker, MemoryState, MouseChecker,
+    ScreenChecker,
+};
+use rshacks::detect::Detector;
+use rshacks::journal::Journal;
+use rshacks::snapshot::Snapshot;
+use rshacks::win;
+use rshacks::win::proc::Process;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const PTR_MAP_FILE: &str = "ptr.map";
+
+const SUSPICIOUS_MAX_HEALTH: i32 = 12000;
+const SUSPICIOUS_MAX_ENERGY: i32 = 15000;
+const SUSPICIOUS_MAX_MANA: i32 = 12000;
+
+// Since PoE 3.14 and 3.18 the base addresses have been 0x025F5988 to 0x02C51FA8,
+// and all of them end at 0x8. Subsequent offsets have never changed since 3.16,
+// but if they do, they would need to be found manually with other external tools.
+// (They may have also worked before 3.16 but a different offset had been used then).
+const MAX_BASE_ADDR_NUDGE: isize = 0x01000000;
+const BASE_ADDR_NUDGE_STEP: isize = 0x10;
+const REPORT_PROGRESS_EVERY: isize = 256;
+
+/// Return true if the health, energy shield or mana values seem abnormal
+/// (such as the life being negative or too much mana reservation).
+fn suspicious_hp_or_mana(MemoryState { health, mana }: &MemoryState) -> bool {
+    health.hp < 1
+        || health.max_hp > SUSPICIOUS_MAX_HEALTH
+        || health.hp > health.max_hp
+        || health.unreserved_hp < 1
+        || health.unreserved_hp > health.max_hp
+        || health.es < 0
+        || health.max_es > SUSPICIOUS_MAX_ENERGY
+        || health.es > health.max_es
+        || mana.mana < 0
+        || mana.max_mana > SUSPICIOUS_MAX_MANA
+        || mana.mana > mana.max_mana
+        || mana.unreserved_mana < 0
+        || mana.unreserved_mana > mana.max_mana
+}
+
+/// Try to obtain the health and mana values with the pointer map in use.
+/// If possible and the values are not suspicious, ask the user if they match.
+/// Return true if and only if the user interactively confirms these values.
+fn try_confirm_valid_hp_or_mana(checker: &MemoryChecker) -> bool {
+    let state = match checker.check() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    if !suspicious_hp_or_mana(&state) {
+        return win::prompt::ask(
+            "possible values found",
+            &format!("is this ok?: {:#?}", state),
+        )
+        .expect("search for new working ptr.map cancelled");
+    }
+
+    false
+}
+
+/// The pointer map to use, which unless told otherwise is the one next to the executable.
+pub fn ptr_map_path(ptr_map: Option<&Path>) -> PathBuf {
+    match ptr_map {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let mut path = std::env::current_exe().expect("could not locate self file location");
+            path.set_file_name(PTR_MAP_FILE);
+            path
+        }
+    }
+}
+
+fn load_memory_checker(process: &Rc<Process>, ptr_map: &Path) -> MemoryChecker {
+    match MemoryChecker::load_ptr_map(ptr_map, process.clone()) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            panic!("pointer .map file not found at: {}\n\nthe file must exist for the program to read the in-game ehp value", ptr_map.to_string_lossy());
+        }
+        Err(err) => {
+            panic!("failed to initialize memory checker: {}", err);
+        }
+        Ok(checker) => checker,
+    }
+}
+
+/// Nudge the base address of the pointer map until the values it reads are confirmed by the user,
+/// and save the pointer map (after backing up the old one) once they are.
+fn fix_base_addr(checker: &mut MemoryChecker, ptr_map: &Path) {
+    write!(io::stderr(), "scanning for new base address...        \r").unwrap();
+
+    let mut nudge_amount = 0;
+    while nudge_amount < MAX_BASE_ADDR_NUDGE {
+        if nudge_amount % (REPORT_PROGRESS_EVERY * BASE_ADDR_NUDGE_STEP) == 0 {
+            write!(
+                io::stderr(),
+                "scaning for new base address... {:.2}% \r",
+                100.0 * (nudge_amount as f32 / MAX_BASE_ADDR_NUDGE as f32)
+            )
+            .unwrap();
+        }
+        // nudge the address in a "zig-zag" kind of way until we manage to read all the way through
+        nudge_amount += BASE_ADDR_NUDGE_STEP;
+        checker.nudge_map_base_addr(nudge_amount);
+        if try_confirm_valid_hp_or_mana(checker) {
+            break;
+        }
+        checker.nudge_map_base_addr(nudge_amount * -2);
+        if try_confirm_valid_hp_or_mana(checker) {
+            break;
+        }
+
+        // reset the base address to its original value after every iteration
+        // so it's left unmodified if things don't work out
+        checker.nudge_map_base_addr(nudge_amount);
+    }
+    write!(io::stderr(), "scanning for new base address... complete\n").unwrap();
+
+    if nudge_amount < MAX_BASE_ADDR_NUDGE {
+        let timestamp = chrono::Local::now().format("%Y%m%d.%H%M%S.map").to_string();
+        win::prompt::info("new base address found", &format!("a new working base address was found at an offset of {:08X}\n\na copy of the ptr.map will be saved to ptr.{}, and the current one will be updated", nudge_amount, timestamp));
+        fs::rename(ptr_map, ptr_map.with_extension(timestamp))
+            .expect("failed to backup existing ptr.map");
+        checker
+            .save_ptr_map(ptr_map)
+            .expect("failed to save updated ptr.map");
+    } else {
+        panic!("ptr.map could not be updated, manual fix required");
+    }
+}
+
+/// Load the pointer map and make sure it works, trying to fix it if it doesn't.
+fn start_memory_checker(process: &Rc<Process>, ptr_map: &Path) -> MemoryChecker {
+    eprintln!("initializing memory checker");
+
+    let mut checker = load_memory_checker(process, ptr_map);
+    match checker.check() {
+        Ok(state) => {
+            // pointer-map seems to work but may have been chance (unlikely) so check for abnormal values.
+            // if abnormal values are found, crash (manually finding the new addresses is required).
+            if suspicious_hp_or_mana(&state) {
+                panic!("current ptr.map did not fail but the values look wrong, manual fix required: {:#?}", state);
+            }
+        }
+        Err(e) => {
+            // pointer-map no longer works, try to fix the base address
+            win::prompt::warn("current ptr.map is invalid", &format!("the current ptr.map does not work, so poe-hacks will try to fix it:\n{e}\n\nDO NOT CHANGE AREA WHILE THIS PROCESS RUNS!\n\nanother alert will show once the process completes (this can take a few minutes)"));
+            fix_base_addr(&mut checker, ptr_map);
+        }
+    }
+
+    checker
+}
+
+/// Load the pointer map, but only if it works as it is, since there's no fixing it while playing
+/// (the game is in the way of prompts, and the player of a scan taking minutes).
+fn try_start_memory_checker(
+    process: &Rc<Process>,
+    ptr_map: &Path,
+) -> Result<MemoryChecker, String> {
+    eprintln!("initializing memory checker");
+
+    let checker = MemoryChecker::load_ptr_map(ptr_map, process.clone()).map_err(|e| {
+        format!(
+            "could not load the pointer map at {}: {}",
+            ptr_map.to_string_lossy(),
+            e
+        )
+    })?;
+    match checker.check() {
+        Ok(state) if suspicious_hp_or_mana(&state) => Err(format!(
+            "the values read with ptr.map look wrong: {:?}",
+            state
+        )),
+        Ok(_) => Ok(checker),
+        Err(e) => Err(format!(
+            "ptr.map does not work anymore (it can be fixed with scan-ptr): {}",
+            e
+        )),
+    }
+}
+
+/// Look for a working base address for the pointer map, unless the current one already works.
+pub fn scan_ptr_map(process: &Rc<Process>, ptr_map: &Path) {
+    let mut checker = load_memory_checker(process, ptr_map);
+    match checker.check() {
+        Ok(state) if !suspicious_hp_or_mana(&state) => {
+            eprintln!("the current ptr.map already works: {:#?}", state);
+        }
+        _ => {
+            eprintln!("do not change area while scanning, this can take a few minutes");
+            fix_base_addr(&mut checker, ptr_map);
+        }
+    }
+}
+
+pub struct Checkers {
+    process: Rc<Process>,
+    ptr_map: PathBuf,
+    pub area: Option<LogChecker>,
+    pub focus: Option<FocusChecker>,
+    pub keyboard: Option<KeyboardChecker>,
+    pub mouse: Option<MouseChecker>,
+    pub player: Option<MemoryChecker>,
+    pub screen: Option<ScreenChecker>,
+    /// What the screen checker is detecting.
+    detectors: Vec<Detector>,
+    /// Whether the log is followed even if no rule needs it, to know which character is playing.
+    follow_log: bool,
+}
+
+impl Checkers {
+    pub fn new(process: Rc<Process>, ptr_map: PathBuf) -> Self {
+        Self {
+            process,
+            ptr_map,
+            area: None,
+            focus: None,
+            keyboard: None,
+            mouse: None,
+            player: None,
+            screen: None,
+            detectors: Vec::new(),
+            follow_log: false,
+        }
+    }
+
+    /// Keep following the log regardless of the rules, to know which character is playing.
+    pub fn follow_log(&mut self) {
+        self.follow_log = true;
+        self.start_log_checker();
+    }
+
+    fn start_log_checker(&mut self) {
+        if self.area.is_none() {
+            eprintln!("initializing log checker");
+            let mut checker = LogChecker::new(Rc::clone(&self.process));
+            checker.init().expect("failed to initialize log checker");
+            self.area = Some(checker);
+        }
+    }
+
+    /// The character playing, as far as the log says (only known while the log is followed).
+    pub fn character(&self) -> Option<&str> {
+        self.area.as_ref().and_then(|checker| checker.character())
+    }
+
+    /// Start the checkers the rules need before running them, fixing the pointer map if needed
+    /// (which may ask the user, or give up entirely).
+    pub fn start(&mut self, actions: &ActionSet) {
+        self.update_with(actions, true);
+    }
+
+    /// Start the checkers the rules need which aren't running yet, and stop the ones they don't
+    /// need anymore. Checkers which keep being needed keep running as they were.
+    ///
+    /// Since the rules are already running, a memory checker which doesn't work is left out with
+    /// a warning rather than stopping everything.
+    pub fn update(&mut self, actions: &ActionSet) {
+        self.update_with(actions, false);
+    }
+
+    fn update_with(&mut self, actions: &ActionSet, starting: bool) {
+        if actions.requires(PreRequirement::Area) {
+            self.start_log_checker();
+        } else if !self.follow_log && self.area.take().is_some() {
+            eprintln!("stopping log checker");
+        }
+
+        match (
+            actions.requires(PreRequirement::Focus),
+            self.focus.is_some(),
+        ) {
+            (true, false) => {
+                eprintln!("initializing focus checker");
+                self.focus = Some(FocusChecker::new(Rc::clone(&self.process)));
+            }
+            (false, true) => {
+                eprintln!("stopping focus checker");
+                self.focus = None;
+            }
+            _ => {}
+        }
+
+        match (
+            actions.requires(PreRequirement::Keyboard),
+            self.keyboard.is_some(),
+        ) {
+            (true, false) => {
+                eprintln!("initializing keyboard checker");
+                self.keyboard = Some(KeyboardChecker::new());
+            }
+            (false, true) => {
+                eprintln!("stopping keyboard checker");
+                self.keyboard = None;
+            }
+            _ => {}
+        }
+
+        match (
+            actions.requires(PreRequirement::Mouse),
+            self.mouse.is_some(),
+        ) {
+            (true, false) => {
+                eprintln!("initializing mouse checker");
+                self.mouse = Some(MouseChecker::new());
+            }
+            (false, true) => {
+                eprintln!("stopping mouse checker");
+                self.mouse = None;
+            }
+            _ => {}
+        }
+
+        match (
+            actions.requires(PreRequirement::Player),
+            self.player.is_some(),
+        ) {
+            (true, false) if starting => {
+                self.player = Some(start_memory_checker(&self.process, &self.ptr_map));
+            }
+            (true, false) => match try_start_memory_checker(&self.process, &self.ptr_map) {
+                Ok(checker) => self.player = Some(checker),
+                Err(e) => eprintln!("warning: life, es and mana checks won't work: {}", e),
+            },
+            (false, true) => {
+                eprintln!("stopping memory checker");
+                self.player = None;
+            }
+            _ => {}
+        }
+
+        let detectors = actions.required_detectors();
+        if detectors != self.detectors {
+            // The old one must be gone before another one captures the screen.
+            self.screen = None;
+            if !detectors.is_empty() {
+                eprintln!("initializing screen checker for {:?}", detectors);
+                self.screen = Some(ScreenChecker::new(self.process.pid, detectors.clone()));
+            } else {
+                eprintln!("stopping screen checker");
+            }
+            self.detectors = detectors;
+        }
+    }
+
+    /// Find out the state of the game, as far as the running checkers can tell.
+    pub fn check(&mut self, journal: &mut Journal) -> GameState {
+        // TODO could skip checkers that are disabled (say, chat screen only needed sometimes)
+        GameState {
+            area: self.area.as_mut().and_then(|checker| {
+                checker
+                    .check()
+                    .inspect_err(|e| {
+                        journal.warn(format_args!("failed to refresh area checker: {e}"))
+                    })
+                    .ok()
+            }),
+            focus: self.focus.as_mut().and_then(|checker| {
+                checker
+                    .check()
+                    .inspect_err(|e| {
+                        journal.warn(format_args!("failed to refresh focus checker: {e}"))
+                    })
+                    .ok()
+            }),
+            keyboard: self.keyboard.as_mut().map(|checker| checker.check()),
+            mouse: self.mouse.as_mut().map(|checker| checker.check()),
+            player: self.player.as_mut().and_then(|checker| {
+                checker
+                    .check()
+                    .inspect_err(|e| {
+                        journal.warn(format_args!("failed to refresh player checker: {e}"))
+                    })
+                    .ok()
+            }),
+            screen: self.screen.as_mut().map(|checker| checker.check()),
+        }
+    }
+
+    /// Remember the state of the checkers which would otherwise be lost on restart.
+    pub fn snapshot(&self, snapshot: &mut Snapshot) {
+        if let Some(checker) = self.area.as_ref() {
+            checker.snapshot(snapshot);
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if let Some(checker) = self.area.as_mut() {
+            checker.restore(snapshot);
+        }
+    }
+}
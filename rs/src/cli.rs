@@ -0,0 +1,323 @@
+//! The command line, along with the defaults for it read from a config file.
+//!
+//! Every option can also be written in the config file (`poe.cfg` next to the executable),
+//! one per line and without the leading dashes, like `tick 20ms` or `no-start-gate`.
+//! Whatever is given on the command line wins over the config file.
+use crate::types::Delay;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const CONFIG_FILE: &str = "poe.cfg";
+
+pub const USAGE: &str = "\
+usage: poe [COMMAND] [FILE] [OPTIONS]
+
+commands:
+  run [FILE]        run the rules in the .key file (poe.key by default), the default command
+  check [FILE]      load the rules and show what's wrong with them, without running them
+  simulate [FILE]   run the rules without sending any input or waiting for a right click
+  scan-ptr          find a working base address for the pointer map and save it
+  capture [DELAY]   save a capture of the game window, optionally after a delay like 3s
+  list-keys         show the names of the keys that can be used in rules
+  help              show this message
+
+options:
+  --profile NAME    use the rules in the profile instead of those of the character playing
+  --dashboard       show a live dashboard instead of the usual messages
+  --process NAME    the name of the game's process (PathOfExile by default)
+  --ptr-map PATH    the pointer map to use (ptr.map next to the executable by default)
+  --tick DELAY      how long to wait between checks (10ms by default)
+  --no-start-gate   start right away instead of waiting for a right click
+  --dry-run         run the rules without sending any input to the game
+
+every option can also be set in poe.cfg next to the executable, like \"tick 20ms\".
+the flags set there can be turned back off with --no-dashboard, --start-gate and --no-dry-run.";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Subcommand {
+    /// Run the rules against the game.
+    Run,
+    /// Load the rules and report what's wrong with them.
+    Check,
+    /// Run the rules against the game without sending any input.
+    Simulate,
+    /// Find a working base address for the pointer map and save it.
+    ScanPtr,
+    /// Save a capture of the game window after the delay (to have time to switch to it).
+    Capture {
+        delay: Delay,
+    },
+    /// Show the names of the keys which can be used in rules.
+    ListKeys,
+    Help,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// The .key file with the rules.
+    pub file: String,
+    pub profile: Option<String>,
+    pub dashboard: bool,
+    /// The name of the game's process.
+    pub process: String,
+    /// The pointer map to use, or else the one next to the executable.
+    pub ptr_map: Option<PathBuf>,
+    /// How long to wait between checks.
+    pub tick: Duration,
+    /// Whether to wait for a right click before starting.
+    pub start_gate: bool,
+    /// Whether to run the rules without sending any input.
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cli {
+    pub command: Subcommand,
+    pub options: Options,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            file: "poe.key".into(),
+            profile: None,
+            dashboard: false,
+            process: "PathOfExile".into(),
+            ptr_map: None,
+            tick: Duration::from_millis(10),
+            start_gate: true,
+            dry_run: false,
+        }
+    }
+}
+
+/// Whether the option is followed by a value, as opposed to being a flag.
+fn takes_value(name: &str) -> bool {
+    matches!(name, "file" | "profile" | "process" | "ptr-map" | "tick")
+}
+
+impl Options {
+    /// Change an option by its name, with its value if it takes one.
+    fn set(&mut self, name: &str, value: Option<&str>) -> Result<(), String> {
+        let value = match (takes_value(name), value) {
+            (true, Some(value)) => value,
+            (true, None) => return Err(format!("option '{}' must be followed by a value", name)),
+            (false, Some(value)) => {
+                return Err(format!(
+                    "option '{}' takes no value, found '{}'",
+                    name, value
+                ))
+            }
+            (false, None) => "",
+        };
+        match name {
+            "file" => self.file = value.to_string(),
+            "profile" => self.profile = Some(value.to_string()),
+            "dashboard" => self.dashboard = true,
+            "no-dashboard" => self.dashboard = false,
+            "process" => self.process = value.to_string(),
+            "ptr-map" => self.ptr_map = Some(value.into()),
+            "tick" => self.tick = value.parse::<Delay>()?.0,
+            "start-gate" => self.start_gate = true,
+            "no-start-gate" => self.start_gate = false,
+            "dry-run" => self.dry_run = true,
+            "no-dry-run" => self.dry_run = false,
+            _ => return Err(format!("found unknown option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// Apply the options written in a config file, one per line.
+    ///
+    /// Empty lines or lines starting with "//" are ignored, like in .key files.
+    pub fn apply_config(&mut self, config: &str) -> Result<(), String> {
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let (name, value) = match line.split_once(char::is_whitespace) {
+                Some((name, value)) => (name, Some(value.trim())),
+                None => (line, None),
+            };
+            self.set(name, value)
+                .map_err(|e| format!("{}:{}: {}", CONFIG_FILE, i + 1, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "run" => Self::Run,
+            "check" => Self::Check,
+            "simulate" => Self::Simulate,
+            "scan-ptr" => Self::ScanPtr,
+            "capture" => Self::Capture {
+                delay: Delay(Duration::ZERO),
+            },
+            "list-keys" => Self::ListKeys,
+            "help" | "-h" | "--help" => Self::Help,
+            _ => return None,
+        })
+    }
+}
+
+impl Cli {
+    /// Parse the arguments (without the program name), starting from the given options.
+    ///
+    /// Without a command, the rules are run, so `poe poe.key --dashboard` keeps working.
+    pub fn parse<I: IntoIterator<Item = String>>(
+        args: I,
+        mut options: Options,
+    ) -> Result<Self, String> {
+        let mut command = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if command.is_none() && positional.is_empty() {
+                if let Some(subcommand) = Subcommand::from_name(&arg) {
+                    command = Some(subcommand);
+                    continue;
+                }
+            }
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = if takes_value(name) { args.next() } else { None };
+                    options.set(name, value.as_deref())?;
+                }
+                // It used to be the only option, before there were dashes.
+                None if arg == "dashboard" => options.dashboard = true,
+                None => positional.push(arg),
+            }
+        }
+
+        let mut command = command.unwrap_or(Subcommand::Run);
+        let mut positional = positional.into_iter();
+        match &mut command {
+            Subcommand::Run | Subcommand::Check | Subcommand::Simulate => {
+                if let Some(file) = positional.next() {
+                    options.file = file;
+                }
+            }
+            Subcommand::Capture { delay } => {
+                if let Some(value) = positional.next() {
+                    *delay = value.parse()?;
+                }
+            }
+            Subcommand::ScanPtr | Subcommand::ListKeys | Subcommand::Help => {}
+        }
+        if let Some(arg) = positional.next() {
+            return Err(format!("found unknown argument '{}'", arg));
+        }
+        if command == Subcommand::Simulate {
+            options.dry_run = true;
+            options.start_gate = false;
+        }
+
+        Ok(Self { command, options })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, String> {
+        Cli::parse(
+            args.split_whitespace().map(String::from),
+            Options::default(),
+        )
+    }
+
+    #[test]
+    fn commands() {
+        let cli = parse("").unwrap();
+        assert_eq!(cli.command, Subcommand::Run);
+        assert_eq!(cli.options, Options::default());
+
+        let cli = parse("mine.key dashboard --profile Alt").unwrap();
+        assert_eq!(cli.command, Subcommand::Run);
+        assert_eq!(cli.options.file, "mine.key");
+        assert!(cli.options.dashboard);
+        assert_eq!(cli.options.profile.as_deref(), Some("Alt"));
+
+        let cli = parse("check --tick 25ms mine.key").unwrap();
+        assert_eq!(cli.command, Subcommand::Check);
+        assert_eq!(cli.options.file, "mine.key");
+        assert_eq!(cli.options.tick, Duration::from_millis(25));
+
+        let cli = parse("simulate").unwrap();
+        assert!(cli.options.dry_run);
+        assert!(!cli.options.start_gate);
+
+        assert_eq!(
+            parse("capture 3s").unwrap().command,
+            Subcommand::Capture {
+                delay: Delay(Duration::from_secs(3))
+            }
+        );
+        assert_eq!(
+            parse("scan-ptr --ptr-map other.map --process Game")
+                .unwrap()
+                .options,
+            Options {
+                ptr_map: Some("other.map".into()),
+                process: "Game".into(),
+                ..Options::default()
+            }
+        );
+        assert_eq!(parse("--help").unwrap().command, Subcommand::Help);
+        // Only the first word can be a command, so files can still be named like one.
+        assert_eq!(parse("run check").unwrap().options.file, "check");
+
+        assert!(parse("list-keys extra").is_err());
+        assert!(parse("a.key b.key").is_err());
+        assert!(parse("--tick").is_err());
+        assert!(parse("--tick soon").is_err());
+        assert!(parse("--loud").is_err());
+        assert!(parse("capture later").is_err());
+    }
+
+    #[test]
+    fn config() {
+        let mut options = Options::default();
+        options
+            .apply_config(
+                "// defaults for the laptop\n\nprocess PathOfExileSteam\ntick 20ms\n  no-start-gate\n",
+            )
+            .unwrap();
+        assert_eq!(options.process, "PathOfExileSteam");
+        assert!(!options.start_gate);
+
+        // The command line wins over the config file.
+        let cli = Cli::parse(vec!["--tick".into(), "5ms".into()], options.clone()).unwrap();
+        assert_eq!(cli.options.tick, Duration::from_millis(5));
+        assert_eq!(cli.options.process, "PathOfExileSteam");
+        assert!(!cli.options.start_gate);
+        let cli = Cli::parse(vec!["--start-gate".into()], options).unwrap();
+        assert!(cli.options.start_gate);
+
+        let mut options = Options::default();
+        options
+            .apply_config(
+                "dry-run
+dashboard",
+            )
+            .unwrap();
+        let cli = Cli::parse(
+            vec!["--no-dry-run".into(), "--no-dashboard".into()],
+            options,
+        );
+        assert!(!cli.as_ref().unwrap().options.dry_run);
+        assert!(!cli.unwrap().options.dashboard);
+
+        let mut options = Options::default();
+        assert_eq!(
+            options.apply_config("tick 1s\ndry-run now"),
+            Err("poe.cfg:2: option 'dry-run' takes no value, found 'now'".into())
+        );
+        assert!(options.apply_config("profile").is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! Follows the game's `Client.txt` log, which says when areas are entered (and by which character).
+//!
+//! The log is never truncated by the game and can grow to gigabytes, so it's read backwards
+//! from the end to find the current area, and then followed forwards as lines are appended.
+use std::fs::{self, File};
+use std::io::{self, BufRead as _, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// How much of the log is read at a time when searching backwards.
+const SCAN_CHUNK: u64 = 16 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogEvent {
+    /// A new area is being generated (entered).
+    Area { town: bool },
+    /// The area finished loading.
+    Transitioned,
+    /// The name of a character which levelled up, which may be the player's or a party member's.
+    Character { name: String },
+    /// The game started and opened the log, so what came before is from another session.
+    Opened,
+    /// The game connected to the login server, which happens when logging in and when going back
+    /// to character select, so another character may be playing from now on.
+    LoggedIn,
+}
+
+/// The characters which levelled up since the player last logged in, from the most recent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Characters(Vec<String>);
+
+/// Follows a log file as it grows, opening it again if it's replaced or truncated.
+pub struct ClientLog {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    /// How far into the file has been read.
+    position: u64,
+    /// When the file that's open was created, to tell if it's been replaced.
+    created: Option<SystemTime>,
+    /// The last line, until it's been fully written.
+    partial: Vec<u8>,
+}
+
+/// Parse the message of a log line, such as `... [INFO Client 123] Generating level 1 area "1_1_town" ...`.
+pub fn parse_line(line: &str) -> Option<LogEvent> {
+    if line.contains("***** LOG FILE OPENING *****") {
+        return Some(LogEvent::Opened);
+    }
+    let msg = line[line.find(']')? + 1..].trim();
+    if msg.starts_with("Generating level") {
+        let mut matcher = msg.match_indices('"');
+        let (start, end) = matcher.next().zip(matcher.next())?;
+        let level = &msg[start.0 + 1..end.0];
+        Some(LogEvent::Area {
+            town: level.ends_with("_town"),
+        })
+    } else if msg.starts_with("[SHADER] Delay: ON") {
+        // and finished loading
+        Some(LogEvent::Transitioned)
+    } else if msg.starts_with("Connected to ") && msg.contains(".login.") {
+        // such as `Connected to lon01.login.pathofexile.com in 47ms.`
+        Some(LogEvent::LoggedIn)
+    } else if msg.starts_with(": ") && msg.contains(") is now level ") {
+        // such as `: Name (Witch) is now level 12`
+        let (name, _) = msg[2..].split_once(" (")?;
+        Some(LogEvent::Character {
+            name: name.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Find the most recent event before `end` that `find` picks something out of, reading backwards
+/// in chunks.
+fn scan_backwards<R: Read + Seek, T>(
+    reader: &mut R,
+    end: u64,
+    mut find: impl FnMut(LogEvent) -> Option<T>,
+) -> io::Result<Option<T>> {
+    let mut end = end;
+    // The start of a line which began in an earlier chunk.
+    let mut tail = Vec::new();
+    while end > 0 {
+        let start = end.saturating_sub(SCAN_CHUNK);
+        let mut chunk = vec![0; (end - start) as usize];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+
+        // Unless this is the start of the file, the first line may begin in the previous chunk.
+        let first_line = match chunk.iter().position(|b| *b == b'\n') {
+            _ if start == 0 => 0,
+            Some(i) => i + 1,
+            None => chunk.len(),
+        };
+        for line in chunk[first_line..].split(|b| *b == b'\n').rev() {
+            if let Some(found) = parse_line(&String::from_utf8_lossy(line)).and_then(&mut find) {
+                return Ok(Some(found));
+            }
+        }
+        chunk.truncate(first_line);
+        tail = chunk;
+        end = start;
+    }
+    Ok(None)
+}
+
+/// Find whether the most recent area entered before `end` is a town.
+pub fn last_area<R: Read + Seek>(reader: &mut R, end: u64) -> io::Result<Option<bool>> {
+    scan_backwards(reader, end, |event| match event {
+        LogEvent::Area { town } => Some(town),
+        _ => None,
+    })
+}
+
+/// Find the characters which levelled up since the player last logged in before `end`. Only the
+/// current login is read, rather than the whole log.
+pub fn session_characters<R: Read + Seek>(reader: &mut R, end: u64) -> io::Result<Characters> {
+    let mut names = Vec::new();
+    scan_backwards(reader, end, |event| match event {
+        LogEvent::Character { name } => {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+            None
+        }
+        LogEvent::Opened | LogEvent::LoggedIn => Some(()),
+        _ => None,
+    })?;
+    Ok(Characters(names))
+}
+
+impl Characters {
+    /// Keep track of the characters as the log is followed.
+    pub fn update(&mut self, event: &LogEvent) {
+        match event {
+            LogEvent::Character { name } if !self.0.contains(name) => {
+                self.0.insert(0, name.clone())
+            }
+            LogEvent::Opened | LogEvent::LoggedIn => self.0.clear(),
+            _ => {}
+        }
+    }
+
+    /// The character playing, once the log leaves no doubt about it.
+    ///
+    /// The game only logs the name of a character when it levels up, and logs party members
+    /// levelling up the same way, so it's only known if a single character levelled up since the
+    /// player logged in.
+    pub fn playing(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [character] => Some(character),
+            _ => None,
+        }
+    }
+}
+
+fn created(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.created()).ok()
+}
+
+impl ClientLog {
+    /// Start following the log from its current end.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        let position = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            created: created(&path),
+            path,
+            reader: Some(BufReader::new(file)),
+            position,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Find whether the most recent area entered before following the log is a town.
+    pub fn last_area(&self) -> io::Result<Option<bool>> {
+        last_area(&mut File::open(&self.path)?, self.position)
+    }
+
+    /// Find the characters which levelled up since logging in, before following the log.
+    pub fn session_characters(&self) -> io::Result<Characters> {
+        session_characters(&mut File::open(&self.path)?, self.position)
+    }
+
+    /// Open the file again from the start if it's been replaced or truncated since.
+    fn reopen_if_rotated(&mut self) {
+        let len = match fs::metadata(&self.path) {
+            Ok(meta) => meta.len(),
+            Err(_) => return,
+        };
+        if self.reader.is_some() && len >= self.position && created(&self.path) == self.created {
+            return;
+        }
+        match File::open(&self.path) {
+            Ok(file) => {
+                eprintln!("note: log file was replaced, reading it from the start");
+                self.reader = Some(BufReader::new(file));
+                self.position = 0;
+                self.created = created(&self.path);
+                self.partial.clear();
+            }
+            Err(e) => eprintln!("warning: could not open log file again: {e}"),
+        }
+    }
+
+    /// Read the events in the lines appended since the last time.
+    pub fn poll(&mut self) -> Vec<LogEvent> {
+        self.reopen_if_rotated();
+        let mut events = Vec::new();
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return events,
+        };
+        loop {
+            match reader.read_until(b'\n', &mut self.partial) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.position += n as u64;
+                    if self.partial.last() != Some(&b'\n') {
+                        // the game is still writing the line
+                        break;
+                    }
+                    events.extend(parse_line(&String::from_utf8_lossy(&self.partial)));
+                    self.partial.clear();
+                }
+                Err(e) => {
+                    eprintln!("warning: failed to read from log file: {e}");
+                    break;
+                }
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write as _};
+
+    fn area(level: &str) -> String {
+        format!(
+            "2024/01/01 12:00:00 123 abc [DEBUG Client 42] Generating level 68 area \"{}\" with seed 1\n",
+            level
+        )
+    }
+
+    fn filler(bytes: usize) -> String {
+        let line = "2024/01/01 12:00:00 123 abc [INFO Client 42] Nothing to see here\n";
+        line.repeat(bytes / line.len() + 1)
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("client-log-{}-{}.txt", name, std::process::id()))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_line(&area("1_1_town")),
+            Some(LogEvent::Area { town: true })
+        );
+        assert_eq!(
+            parse_line(&area("MapWorldsStrand")),
+            Some(LogEvent::Area { town: false })
+        );
+        assert_eq!(
+            parse_line("2024/01/01 12:00:00 123 abc [INFO Client 42] [SHADER] Delay: ON"),
+            Some(LogEvent::Transitioned)
+        );
+        assert_eq!(
+            parse_line(
+                "2024/01/01 12:00:00 123 abc [INFO Client 42] : Some_Name (Witch) is now level 12"
+            ),
+            Some(LogEvent::Character {
+                name: "Some_Name".into()
+            })
+        );
+        assert_eq!(
+            parse_line("2024/01/01 12:00:00 ***** LOG FILE OPENING *****"),
+            Some(LogEvent::Opened)
+        );
+        assert_eq!(
+            parse_line("2024/01/01 12:00:00 123 abc [INFO Client 42] Connected to lon01.login.pathofexile.com in 47ms."),
+            Some(LogEvent::LoggedIn)
+        );
+        assert_eq!(parse_line(&filler(0)), None);
+    }
+
+    #[test]
+    fn scan_backwards() {
+        // the area lines end up far apart and across chunk boundaries
+        let log = format!(
+            "{}{}{}{}",
+            area("1_1_town"),
+            filler(3 * SCAN_CHUNK as usize + 7),
+            area("MapWorldsStrand"),
+            filler(5 * SCAN_CHUNK as usize / 2)
+        );
+        let end = log.len() as u64;
+        assert_eq!(last_area(&mut Cursor::new(&log), end).unwrap(), Some(false));
+
+        let first = area("1_1_town").len() as u64 + 10;
+        assert_eq!(
+            last_area(&mut Cursor::new(&log), first).unwrap(),
+            Some(true)
+        );
+        assert_eq!(
+            last_area(&mut Cursor::new(filler(40_000)), 40_000).unwrap(),
+            None
+        );
+        assert_eq!(last_area(&mut Cursor::new(""), 0).unwrap(), None);
+
+        let opening = "2024/01/01 12:00:00 ***** LOG FILE OPENING *****\n";
+        let log = format!(
+            "[INFO Client 42] : Old (Witch) is now level 2\n{}{}[INFO Client 42] : New (Duelist) is now level 90\n{}[INFO Client 42] : Friend (Ranger) is now level 3\n[INFO Client 42] : New (Duelist) is now level 91\n",
+            opening,
+            filler(SCAN_CHUNK as usize),
+            area("1_1_town")
+        );
+        let playing = |end| {
+            session_characters(&mut Cursor::new(&log), end)
+                .unwrap()
+                .playing()
+                .map(str::to_string)
+        };
+        // The earlier session doesn't count, and neither does what's logged after `end`.
+        let first = log.find("Friend").unwrap() as u64;
+        assert_eq!(playing(first).as_deref(), Some("New"));
+        // A party member levelled up too, so it's no longer known.
+        assert_eq!(playing(log.len() as u64), None);
+        assert_eq!(playing(50).as_deref(), Some("Old"));
+        assert_eq!(playing(log.find(opening).unwrap() as u64 + 60), None);
+    }
+
+    #[test]
+    fn switch_characters() {
+        let login = "2024/01/01 12:00:00 123 abc [INFO Client 42] Connected to lon01.login.pathofexile.com in 47ms.\n";
+        let level = |name: &str, level: u32| {
+            format!(
+                "2024/01/01 12:00:00 123 abc [INFO Client 42] : {} (Witch) is now level {}\n",
+                name, level
+            )
+        };
+        // Playing a hardcore character, then a trade alt, then the hardcore one again.
+        let log = [
+            login.to_string(),
+            level("Hardcore_Guy", 80),
+            area("1_1_town"),
+            login.to_string(),
+            level("Trade_Alt", 40),
+            login.to_string(),
+            area("1_1_town"),
+            level("Hardcore_Guy", 81),
+        ]
+        .concat();
+
+        let mut characters = Characters::default();
+        let mut switches = Vec::new();
+        for line in log.lines() {
+            if let Some(event) = parse_line(line) {
+                characters.update(&event);
+            }
+            let playing = characters.playing().map(str::to_string);
+            if playing.is_some() && switches.last() != Some(&playing) {
+                switches.push(playing);
+            }
+        }
+        assert_eq!(
+            switches,
+            vec![
+                Some("Hardcore_Guy".to_string()),
+                Some("Trade_Alt".to_string()),
+                Some("Hardcore_Guy".to_string()),
+            ]
+        );
+
+        // Starting in the middle of the session only reads back to the last login.
+        let characters = session_characters(&mut Cursor::new(&log), log.len() as u64).unwrap();
+        assert_eq!(characters.playing(), Some("Hardcore_Guy"));
+        let before_last = log.rfind(login).unwrap() as u64;
+        let characters = session_characters(&mut Cursor::new(&log), before_last).unwrap();
+        assert_eq!(characters.playing(), Some("Trade_Alt"));
+    }
+
+    #[test]
+    fn follow_and_rotate() {
+        let path = temp_log("follow");
+        fs::write(&path, format!("{}{}", area("1_1_town"), filler(100))).unwrap();
+        let mut log = ClientLog::open(&path).unwrap();
+        assert_eq!(log.last_area().unwrap(), Some(true));
+        assert_eq!(log.poll(), vec![]);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let line = area("MapWorldsStrand");
+        let (start, end) = line.split_at(20);
+        file.write_all(start.as_bytes()).unwrap();
+        assert_eq!(log.poll(), vec![]);
+        file.write_all(end.as_bytes()).unwrap();
+        file.write_all(b"2024/01/01 12:00:00 123 abc [INFO Client 42] [SHADER] Delay: ON\n")
+            .unwrap();
+        assert_eq!(
+            log.poll(),
+            vec![LogEvent::Area { town: false }, LogEvent::Transitioned]
+        );
+        drop(file);
+
+        // a new, shorter log replaces the old one
+        fs::write(&path, area("2_1_town")).unwrap();
+        assert_eq!(log.poll(), vec![LogEvent::Area { town: true }]);
+        fs::remove_file(&path).unwrap();
+    }
+}
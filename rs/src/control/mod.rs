@@ -0,0 +1,678 @@
+//! An optional server to control poe-hacks from other programs, such as stream deck buttons,
+//! overlays or a remote on the phone.
+//!
+//! * `GET /state` answers with the last game state, as JSON.
+//! * `POST /command` queues the command in the body, such as `disable trade`.
+//! * `GET /events` is a WebSocket streaming the game state and journal events as JSON,
+//!   which also accepts commands as text messages.
+//!
+//! Every request must include the token in the query, as in `/state?token=...`. Without one
+//! configured, a new one is made up every time the server starts. Since any web page open in a
+//! browser can send requests to localhost, those coming from a web page (with an `Origin` header)
+//! are refused unless the page's origin was explicitly allowed.
+use crate::journal::Json;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher as _, Hasher as _};
+use std::io::{self, BufRead as _, BufReader, Error, ErrorKind, Read as _, Write as _};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+mod websocket;
+
+pub use websocket::Frame;
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+// Requests are small, so anything larger (or slower) than this is not worth reading.
+const MAX_REQUEST_LINES: usize = 64;
+const MAX_BODY: usize = 4 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the server listens, configured with the `control` directive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlConfig {
+    pub address: String,
+    pub token: Option<String>,
+    /// The origins of the web pages allowed to send requests, such as `http://localhost:3000`.
+    pub origins: Vec<String>,
+}
+
+/// What other programs can ask for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Enable or disable a group, or every rule sending input if there's no group.
+    SetEnabled {
+        group: Option<String>,
+        enabled: bool,
+    },
+    ToggleGroup {
+        group: String,
+    },
+    /// Load the .key file again.
+    Reload,
+    /// Toggle the rule on or off, as if its preconditions were met.
+    Toggle {
+        rule: String,
+    },
+    /// Run the rule right away, regardless of its preconditions and cooldown.
+    Fire {
+        rule: String,
+    },
+}
+
+/// Sends messages to every WebSocket client connected, forgetting those which are gone.
+#[derive(Clone, Default)]
+pub struct Broadcaster {
+    clients: Arc<Mutex<Vec<Sender<Frame>>>>,
+}
+
+/// What every connection needs from the server.
+#[derive(Clone)]
+struct Shared {
+    token: String,
+    origins: Vec<String>,
+    state: Arc<Mutex<String>>,
+    broadcaster: Broadcaster,
+    commands: Sender<Command>,
+}
+
+pub struct ControlServer {
+    address: SocketAddr,
+    token: String,
+    commands: Receiver<Command>,
+    state: Arc<Mutex<String>>,
+    broadcaster: Broadcaster,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+impl ControlConfig {
+    /// Parse a `control` directive, such as
+    /// `control 0.0.0.0:7878 token hunter2 origin http://localhost:3000`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut config = Self {
+            address: DEFAULT_ADDRESS.to_string(),
+            token: None,
+            origins: Vec::new(),
+        };
+        let mut words = line.split_whitespace().skip(1);
+        while let Some(word) = words.next() {
+            match word {
+                "token" => match words.next() {
+                    Some(token) => config.token = Some(token.to_string()),
+                    None => return Err("control token has no value".into()),
+                },
+                "origin" => match words.next() {
+                    Some(origin) => config
+                        .origins
+                        .push(origin.trim_end_matches('/').to_lowercase()),
+                    None => return Err("control origin has no value".into()),
+                },
+                address if address.parse::<SocketAddr>().is_ok() => {
+                    config.address = address.to_string()
+                }
+                _ => return Err(format!("found unknown control option '{}'", word)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether other machines can connect, rather than only this one.
+    pub fn is_remote(&self) -> bool {
+        self.address
+            .parse::<SocketAddr>()
+            .map_or(true, |address| !address.ip().is_loopback())
+    }
+}
+
+impl Broadcaster {
+    fn add(&self, client: Sender<Frame>) {
+        self.clients.lock().unwrap().push(client);
+    }
+
+    pub fn send(&self, message: &str) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(Frame::Text(message.to_string())).is_ok());
+    }
+}
+
+/// Make up a token which can't be guessed, for when none is configured.
+fn new_token() -> String {
+    // The keys of `RandomState` are random, which is as good as it gets without more dependencies.
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos()),
+            );
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+/// Compare the tokens in a time which doesn't depend on where they differ, so that it can't be
+/// guessed one character at a time.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Request> {
+    let invalid = |what| Error::new(ErrorKind::InvalidData, what);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, target) = match (words.next(), words.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(invalid("invalid request line")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    let mut headers = HashMap::new();
+    for _ in 0..MAX_REQUEST_LINES {
+        line.clear();
+        reader.read_line(&mut line)?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+            None if line.trim_end().is_empty() => break,
+            None => return Err(invalid("invalid header")),
+        }
+    }
+
+    let len = headers
+        .get("content-length")
+        .map_or(Ok(0), |len| len.parse::<usize>())
+        .map_err(|_| invalid("invalid content length"))?;
+    if len > MAX_BODY {
+        return Err(invalid("request body is too large"));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn error_event(message: &str) -> String {
+    Json::object()
+        .with("event", "error")
+        .with("message", message)
+        .to_string()
+}
+
+/// Stream messages to the client, and queue the commands it sends, until either side is done.
+fn serve_websocket(
+    mut stream: TcpStream,
+    mut reader: BufReader<TcpStream>,
+    key: &str,
+    shared: &Shared,
+) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    shared.broadcaster.add(tx.clone());
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key)
+    )?;
+    stream.set_read_timeout(None)?;
+    let _ = tx.send(Frame::Text(shared.state.lock().unwrap().clone()));
+
+    let commands = shared.commands.clone();
+    thread::spawn(move || loop {
+        let reply = match websocket::read_frame(&mut reader) {
+            Ok(Frame::Text(text)) => match text.parse() {
+                Ok(command) => {
+                    let _ = commands.send(command);
+                    continue;
+                }
+                Err(e) => Frame::Text(error_event(&e)),
+            },
+            Ok(Frame::Ping(data)) => Frame::Pong(data),
+            Ok(Frame::Pong(_)) => continue,
+            Ok(Frame::Close) | Err(_) => {
+                let _ = tx.send(Frame::Close);
+                break;
+            }
+        };
+        if tx.send(reply).is_err() {
+            break;
+        }
+    });
+
+    for frame in rx {
+        websocket::write_frame(&mut stream, &frame, None)?;
+        if frame == Frame::Close {
+            break;
+        }
+    }
+    stream.shutdown(Shutdown::Both)
+}
+
+fn handle(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_request(&mut reader)?;
+
+    // Browsers always say where the requests of web pages come from, while other programs don't.
+    if let Some(origin) = request.headers.get("origin") {
+        let origin = origin.trim_end_matches('/').to_lowercase();
+        if !shared.origins.contains(&origin) {
+            return respond(
+                &mut stream,
+                "403 Forbidden",
+                "text/plain",
+                "origin not allowed",
+            );
+        }
+    }
+    let token = request.query.get("token").map_or("", String::as_str);
+    if !same_token(token, &shared.token) {
+        return respond(
+            &mut stream,
+            "401 Unauthorized",
+            "text/plain",
+            "invalid token",
+        );
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/state") => {
+            let state = shared.state.lock().unwrap().clone();
+            respond(&mut stream, "200 OK", "application/json", &state)
+        }
+        ("POST", "/command") => match request.body.parse() {
+            Ok(command) => {
+                let _ = shared.commands.send(command);
+                respond(&mut stream, "202 Accepted", "text/plain", "queued")
+            }
+            Err(e) => respond(&mut stream, "400 Bad Request", "text/plain", &e),
+        },
+        ("GET", "/events") => match request.headers.get("sec-websocket-key") {
+            Some(key) => serve_websocket(stream, reader, key, shared),
+            None => respond(
+                &mut stream,
+                "426 Upgrade Required",
+                "text/plain",
+                "events are only sent over a websocket",
+            ),
+        },
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found"),
+    }
+}
+
+impl ControlServer {
+    /// Start listening in the background, with a new token if none is configured.
+    pub fn start(config: &ControlConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(&config.address)?;
+        let address = listener.local_addr()?;
+        let (commands_tx, commands) = mpsc::channel();
+        let state = Arc::new(Mutex::new("null".to_string()));
+        let broadcaster = Broadcaster::default();
+        let token = config.token.clone().unwrap_or_else(new_token);
+
+        let shared = Shared {
+            token: token.clone(),
+            origins: config.origins.clone(),
+            state: Arc::clone(&state),
+            broadcaster: broadcaster.clone(),
+            commands: commands_tx,
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream, &shared);
+                });
+            }
+        });
+
+        Ok(Self {
+            address,
+            token,
+            commands,
+            state,
+            broadcaster,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The token every request must include, made up if none was configured.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// The commands received since the last time.
+    pub fn commands(&self) -> Vec<Command> {
+        self.commands.try_iter().collect()
+    }
+
+    /// Update the state answered to requests, and send it to every client.
+    pub fn set_state(&self, state: String) {
+        self.broadcaster.send(&state);
+        *self.state.lock().unwrap() = state;
+    }
+
+    /// Something to send further messages to every client, such as journal events.
+    pub fn broadcaster(&self) -> Broadcaster {
+        self.broadcaster.clone()
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let (word, rest) = text.split_once(' ').unwrap_or((text, ""));
+        let rest = rest.trim();
+        let group = || match rest.to_lowercase().as_str() {
+            "" => None,
+            group => Some(group.to_string()),
+        };
+        match (word.to_lowercase().as_str(), rest.is_empty()) {
+            ("enable", _) => Ok(Self::SetEnabled {
+                group: group(),
+                enabled: true,
+            }),
+            ("disable", _) => Ok(Self::SetEnabled {
+                group: group(),
+                enabled: false,
+            }),
+            ("toggle-group", false) => Ok(Self::ToggleGroup {
+                group: rest.to_lowercase(),
+            }),
+            ("reload", true) => Ok(Self::Reload),
+            ("toggle", false) => Ok(Self::Toggle {
+                rule: rest.to_string(),
+            }),
+            ("fire", false) => Ok(Self::Fire {
+                rule: rest.to_string(),
+            }),
+            ("toggle-group" | "toggle" | "fire", true) => {
+                Err(format!("command '{}' needs a group or rule", word))
+            }
+            _ => Err(format!("found unknown command '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SetEnabled { group, enabled } => {
+                f.write_str(if *enabled { "enable" } else { "disable" })?;
+                match group {
+                    Some(group) => write!(f, " {}", group),
+                    None => Ok(()),
+                }
+            }
+            Self::ToggleGroup { group } => write!(f, "toggle-group {}", group),
+            Self::Reload => write!(f, "reload"),
+            Self::Toggle { rule } => write!(f, "toggle {}", rule),
+            Self::Fire { rule } => write!(f, "fire {}", rule),
+        }
+    }
+}
+
+impl fmt::Display for ControlConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "control {}", self.address)?;
+        if self.token.is_some() {
+            // Not worth leaking into the console.
+            write!(f, " token ***")?;
+        }
+        for origin in self.origins.iter() {
+            write!(f, " origin {}", origin)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    fn start() -> ControlServer {
+        ControlServer::start(
+            &ControlConfig::parse("control 127.0.0.1:0 token secret origin http://Deck.local/")
+                .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn http(server: &ControlServer, request: &str) -> String {
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// Wait for the commands to arrive, since they're received in the background.
+    fn commands(server: &ControlServer) -> Vec<Command> {
+        let start = Instant::now();
+        loop {
+            let commands = server.commands();
+            if !commands.is_empty() || start.elapsed() > Duration::from_secs(2) {
+                return commands;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn parse_commands() {
+        let parse = |text: &str| text.parse::<Command>();
+        assert_eq!(
+            parse("disable Trade"),
+            Ok(Command::SetEnabled {
+                group: Some("trade".into()),
+                enabled: false
+            })
+        );
+        assert_eq!(
+            parse("enable"),
+            Ok(Command::SetEnabled {
+                group: None,
+                enabled: true
+            })
+        );
+        assert_eq!(parse(" reload "), Ok(Command::Reload));
+        assert_eq!(
+            parse("fire on key F1 do flask 1"),
+            Ok(Command::Fire {
+                rule: "on key F1 do flask 1".into()
+            })
+        );
+        assert!(parse("toggle").is_err());
+        assert!(parse("reload now").is_err());
+        assert!(parse("explode").is_err());
+        for text in [
+            "disable trade",
+            "enable",
+            "toggle-group flasks",
+            "toggle on key F1 toggle click left",
+        ]
+        .iter()
+        {
+            assert_eq!(parse(text).unwrap().to_string(), *text);
+        }
+
+        let config = ControlConfig::parse("control").unwrap();
+        assert_eq!(config.address, DEFAULT_ADDRESS);
+        assert!(!config.is_remote());
+        assert!(ControlConfig::parse("control 0.0.0.0:80")
+            .unwrap()
+            .is_remote());
+        assert!(ControlConfig::parse("control token").is_err());
+        assert!(ControlConfig::parse("control origin").is_err());
+        assert!(ControlConfig::parse("control localhost").is_err());
+    }
+
+    #[test]
+    fn http_requests() {
+        let server = start();
+        let response = http(&server, "GET /state?token=secret HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nnull"));
+
+        server.set_state(r#"{"event":"state"}"#.into());
+        let response = http(&server, "GET /state?token=secret HTTP/1.1\r\n\r\n");
+        assert!(response.ends_with(r#"{"event":"state"}"#));
+        assert!(http(&server, "GET /state HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401"));
+        assert!(
+            http(&server, "GET /state?token=secreT HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401")
+        );
+        assert!(!response.contains("Access-Control-Allow-Origin"));
+
+        // Web pages can only send requests from the origins allowed.
+        let response = http(
+            &server,
+            "GET /state?token=secret HTTP/1.1\r\nOrigin: http://deck.local\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let response = http(
+            &server,
+            "POST /command?token=secret HTTP/1.1\r\nOrigin: https://evil.example\r\n\
+             Content-Type: text/plain\r\nContent-Length: 7\r\n\r\ndisable",
+        );
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(server.commands().is_empty());
+        assert!(
+            http(&server, "GET /nope?token=secret HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404")
+        );
+
+        let response = http(
+            &server,
+            "POST /command?token=secret HTTP/1.1\r\nContent-Length: 13\r\n\r\ndisable trade",
+        );
+        assert!(response.starts_with("HTTP/1.1 202"));
+        assert_eq!(
+            commands(&server),
+            vec![Command::SetEnabled {
+                group: Some("trade".into()),
+                enabled: false
+            }]
+        );
+        let response = http(
+            &server,
+            "POST /command?token=secret HTTP/1.1\r\nContent-Length: 7\r\n\r\nexplode",
+        );
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secreT", "secret"));
+        assert!(!same_token("", "secret"));
+
+        // Without a token configured, one is made up rather than letting everything through.
+        let server =
+            ControlServer::start(&ControlConfig::parse("control 127.0.0.1:0").unwrap()).unwrap();
+        assert_eq!(server.token().len(), 32);
+        assert_ne!(server.token(), new_token());
+        assert!(http(&server, "GET /state HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 401"));
+        let request = format!("GET /state?token={} HTTP/1.1\r\n\r\n", server.token());
+        assert!(http(&server, &request).starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn websocket_events() {
+        let server = start();
+        let response = http(
+            &server,
+            "GET /events?token=secret HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Origin: https://evil.example\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        server.set_state(r#"{"event":"state"}"#.into());
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        stream
+            .write_all(
+                b"GET /events?token=secret HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        // The current state comes first, and then whatever is sent.
+        let text = |text: &str| Frame::Text(text.into());
+        let read = |reader: &mut BufReader<TcpStream>| websocket::read_frame(reader).unwrap();
+        assert_eq!(read(&mut reader), text(r#"{"event":"state"}"#));
+        server.broadcaster().send("hello");
+        assert_eq!(read(&mut reader), text("hello"));
+
+        let mask = Some([1, 2, 3, 4]);
+        websocket::write_frame(&mut stream, &text("fire on key F1 do flask 1"), mask).unwrap();
+        assert_eq!(
+            commands(&server),
+            vec![Command::Fire {
+                rule: "on key F1 do flask 1".into()
+            }]
+        );
+        websocket::write_frame(&mut stream, &text("explode"), mask).unwrap();
+        assert_eq!(
+            read(&mut reader),
+            text(r#"{"event":"error","message":"found unknown command 'explode'"}"#)
+        );
+        websocket::write_frame(&mut stream, &Frame::Ping(vec![1]), mask).unwrap();
+        assert_eq!(read(&mut reader), Frame::Pong(vec![1]));
+        websocket::write_frame(&mut stream, &Frame::Close, mask).unwrap();
+        assert_eq!(read(&mut reader), Frame::Close);
+    }
+}
@@ -0,0 +1,216 @@
+//! Just enough of WebSocket (RFC 6455) to exchange text messages with local clients.
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Commands are short, so anything larger than this is not something to read.
+const MAX_PAYLOAD: u64 = 64 * 1024;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close,
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+            *h = h.wrapping_add(*x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The value of the `Sec-WebSocket-Accept` header answering the client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(
+        format!("{}{}", key.trim(), HANDSHAKE_GUID).as_bytes(),
+    ))
+}
+
+/// Read a single frame, unmasking it if needed.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Frame> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_PAYLOAD {
+        return Err(Error::new(ErrorKind::InvalidData, "frame is too large"));
+    }
+    if !fin {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "fragmented frames are not supported",
+        ));
+    }
+
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    match opcode {
+        OPCODE_TEXT => String::from_utf8(payload)
+            .map(Frame::Text)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "text frame is not utf-8")),
+        OPCODE_CLOSE => Ok(Frame::Close),
+        OPCODE_PING => Ok(Frame::Ping(payload)),
+        OPCODE_PONG => Ok(Frame::Pong(payload)),
+        _ => Err(Error::new(ErrorKind::InvalidData, "unsupported frame")),
+    }
+}
+
+/// Write a single frame, masked with `mask` if given (which only clients do).
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    frame: &Frame,
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let (opcode, payload) = match frame {
+        Frame::Text(text) => (OPCODE_TEXT, text.as_bytes()),
+        Frame::Ping(data) => (OPCODE_PING, data.as_slice()),
+        Frame::Pong(data) => (OPCODE_PONG, data.as_slice()),
+        Frame::Close => (OPCODE_CLOSE, &[][..]),
+    };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn handshake() {
+        // The example in the RFC.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn frames() {
+        let text = Frame::Text("disable trade".into());
+        let long = Frame::Text("x".repeat(300));
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &text, Some([1, 2, 3, 4])).unwrap();
+        write_frame(&mut buffer, &long, None).unwrap();
+        write_frame(&mut buffer, &Frame::Ping(vec![7]), None).unwrap();
+        write_frame(&mut buffer, &Frame::Close, Some([9, 9, 9, 9])).unwrap();
+
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader).unwrap(), text);
+        assert_eq!(read_frame(&mut reader).unwrap(), long);
+        assert_eq!(read_frame(&mut reader).unwrap(), Frame::Ping(vec![7]));
+        assert_eq!(read_frame(&mut reader).unwrap(), Frame::Close);
+        assert!(read_frame(&mut reader).is_err());
+    }
+}
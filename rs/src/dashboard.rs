@@ -0,0 +1,203 @@
+//! The live dashboard shown on the console instead of the usual messages.
+//!
+//! The state is gathered into a `Dashboard` every so often and rendered into plain lines of text,
+//! so drawing them on the console is the only part that depends on the system.
+use crate::types::Opened;
+use std::time::Duration;
+
+const BAR_WIDTH: usize = 20;
+
+/// A value out of a maximum, such as the player's life.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Meter {
+    pub current: i32,
+    pub max: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleStatus {
+    pub rule: String,
+    /// How long until the rule can run again.
+    pub cooldown: Duration,
+    /// How long until the rule runs, if it's winding up.
+    pub windup: Option<Duration>,
+    /// Whether the rule is toggled on, if it's toggled at all.
+    pub toggled: Option<bool>,
+    /// How long ago the rule last ran, if it did.
+    pub last_triggered: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckerStatus {
+    pub name: &'static str,
+    pub healthy: bool,
+}
+
+/// Everything shown on the dashboard. Parts which aren't being checked are `None`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dashboard {
+    pub life: Option<Meter>,
+    pub energy_shield: Option<Meter>,
+    pub mana: Option<Meter>,
+    pub in_town: Option<bool>,
+    pub chat: Option<Opened>,
+    pub focus: Option<bool>,
+    pub checkers: Vec<CheckerStatus>,
+    pub rules: Vec<RuleStatus>,
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f32())
+}
+
+fn bar(name: &str, meter: Option<Meter>) -> String {
+    match meter {
+        Some(Meter { current, max }) => {
+            let filled = if max > 0 {
+                (current.clamp(0, max) as usize * BAR_WIDTH) / max as usize
+            } else {
+                0
+            };
+            format!(
+                "{:<5}[{}{}] {}/{}",
+                name,
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                current,
+                max
+            )
+        }
+        None => format!("{:<5}[{}] ?", name, " ".repeat(BAR_WIDTH)),
+    }
+}
+
+fn or_unknown<T>(value: Option<T>, show: impl Fn(T) -> &'static str) -> &'static str {
+    value.map_or("?", show)
+}
+
+impl RuleStatus {
+    fn render(&self) -> String {
+        let state = match self.windup {
+            Some(windup) => format!("windup {}", seconds(windup)),
+            None if self.cooldown > Duration::ZERO => format!("wait {}", seconds(self.cooldown)),
+            None => "ready".to_string(),
+        };
+        let toggled = match self.toggled {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "-",
+        };
+        let last = self
+            .last_triggered
+            .map_or_else(|| "never".to_string(), seconds);
+        format!("{:<12} {:<3} {:>7}  {}", state, toggled, last, self.rule)
+    }
+}
+
+impl Dashboard {
+    /// Render the dashboard as lines no wider than `width` characters.
+    pub fn render(&self, width: usize) -> Vec<String> {
+        let mut lines = vec![
+            bar("life", self.life),
+            bar("es", self.energy_shield),
+            bar("mana", self.mana),
+            format!(
+                "area {}  chat {}  focus {}",
+                or_unknown(self.in_town, |town| if town { "town" } else { "map" }),
+                or_unknown(self.chat, |chat| match chat {
+                    Opened::Open => "opened",
+                    Opened::Closed => "closed",
+                }),
+                or_unknown(self.focus, |focus| if focus { "yes" } else { "no" }),
+            ),
+        ];
+        if !self.checkers.is_empty() {
+            lines.push(format!(
+                "checks {}",
+                self.checkers
+                    .iter()
+                    .map(|c| format!("{} {}", c.name, if c.healthy { "ok" } else { "FAILED" }))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+            ));
+        }
+        lines.push(String::new());
+        lines.push(format!("{:<12} {:<3} {:>7}  rule", "state", "tgl", "last"));
+        lines.extend(self.rules.iter().map(RuleStatus::render));
+
+        for line in lines.iter_mut() {
+            if let Some((i, _)) = line.char_indices().nth(width) {
+                line.truncate(i);
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let dashboard = Dashboard {
+            life: Some(Meter {
+                current: 1500,
+                max: 2000,
+            }),
+            energy_shield: Some(Meter { current: 0, max: 0 }),
+            mana: None,
+            in_town: Some(false),
+            chat: Some(Opened::Closed),
+            focus: None,
+            checkers: vec![
+                CheckerStatus {
+                    name: "ptr.map",
+                    healthy: true,
+                },
+                CheckerStatus {
+                    name: "screen",
+                    healthy: false,
+                },
+            ],
+            rules: vec![
+                RuleStatus {
+                    rule: "on life 70% do flask 1 every 1500ms".into(),
+                    cooldown: Duration::from_millis(1250),
+                    windup: None,
+                    toggled: None,
+                    last_triggered: Some(Duration::from_millis(250)),
+                },
+                RuleStatus {
+                    rule: "on key mouse5 toggle click left every 150ms silent".into(),
+                    cooldown: Duration::ZERO,
+                    windup: None,
+                    toggled: Some(true),
+                    last_triggered: None,
+                },
+                RuleStatus {
+                    rule: "on key W do key R after 500ms".into(),
+                    cooldown: Duration::ZERO,
+                    windup: Some(Duration::from_millis(300)),
+                    toggled: Some(false),
+                    last_triggered: Some(Duration::from_secs(62)),
+                },
+            ],
+        };
+        assert_eq!(
+            dashboard.render(60).join("\n"),
+            "\
+life [###############-----] 1500/2000
+es   [--------------------] 0/0
+mana [                    ] ?
+area map  chat closed  focus ?
+checks ptr.map ok  screen FAILED
+
+state        tgl    last  rule
+wait 1.2s    -      0.2s  on life 70% do flask 1 every 1500m
+ready        on    never  on key mouse5 toggle click left ev
+windup 0.3s  off   62.0s  on key W do key R after 500ms"
+        );
+        assert_eq!(Dashboard::default().render(4)[3], "area");
+    }
+}
@@ -0,0 +1,150 @@
+use crate::image::Rect;
+
+// Every UI region is measured on a 1920x1080 client area.
+const REFERENCE_HEIGHT: f32 = 1080.0;
+
+// Below this aspect ratio (4:3) the side panels would no longer fit
+// side by side when scaled by height, so the UI shrinks to fit the width.
+const NARROW_ASPECT: f32 = 4.0 / 3.0;
+const NARROW_REFERENCE_WIDTH: f32 = REFERENCE_HEIGHT * NARROW_ASPECT;
+
+// Past 21:9 (such as on 32:9 screens) the UI stops spreading out, and is laid out
+// in a 21:9 area in the middle of the client area instead. Screens sold as 21:9 are
+// a bit wider than that (3440x1440 is 43:18), hence the rounding up.
+const ULTRAWIDE_ASPECT: f32 = 2.4;
+
+/// Which side of the client area a UI element sticks to as the width changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    Left,
+    Center,
+    Right,
+}
+
+/// A region of the game's UI, as measured on the reference 1920x1080 client area.
+///
+/// `x` is relative to the anchor (so it's negative for center and right anchors).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UiRegion {
+    pub anchor: Anchor,
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The different ways the game lays out its UI depending on the aspect ratio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// Narrower than 4:3. The UI is scaled by width.
+    Narrow,
+    /// 4:3 to 21:9. The UI is scaled by height, and wider screens leave more room
+    /// between the anchored panels.
+    Standard,
+    /// Wider than 21:9. The UI is scaled by height, and anchored to the sides of a 21:9 area
+    /// in the middle rather than to the sides of the client area.
+    Ultrawide,
+}
+
+/// Maps regions of the UI to screen coordinates for a given game window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    client: Rect,
+    /// The part of the client area the UI is laid out in.
+    ui: Rect,
+    profile: Profile,
+    scale: f32,
+}
+
+impl Layout {
+    /// Create the layout for a window with the given client area (in screen coordinates).
+    pub fn new(client: Rect) -> Self {
+        let width = client.width as f32;
+        let height = client.height.max(1) as f32;
+        let aspect = width / height;
+
+        let (profile, scale) = if aspect < NARROW_ASPECT {
+            (Profile::Narrow, width / NARROW_REFERENCE_WIDTH)
+        } else if aspect <= ULTRAWIDE_ASPECT {
+            (Profile::Standard, height / REFERENCE_HEIGHT)
+        } else {
+            (Profile::Ultrawide, height / REFERENCE_HEIGHT)
+        };
+        let ui = match profile {
+            Profile::Ultrawide => {
+                let ui_width = (height * ULTRAWIDE_ASPECT).round() as usize;
+                Rect {
+                    left: client.left + (client.width - ui_width) as isize / 2,
+                    width: ui_width,
+                    ..client.clone()
+                }
+            }
+            Profile::Narrow | Profile::Standard => client.clone(),
+        };
+
+        Self {
+            client,
+            ui,
+            profile,
+            scale,
+        }
+    }
+
+    pub fn client(&self) -> &Rect {
+        &self.client
+    }
+
+    /// Whether there's nothing to look at, as with a minimized window.
+    pub fn is_empty(&self) -> bool {
+        self.client.width == 0 || self.client.height == 0
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Scale a length measured on the reference client area.
+    pub fn scaled(&self, length: f32) -> f32 {
+        length * self.scale
+    }
+
+    /// Map a point measured on the reference client area (relative to the anchor) to the screen.
+    pub fn point(&self, anchor: Anchor, x: f32, y: f32) -> (isize, isize) {
+        let origin = match anchor {
+            Anchor::Left => self.ui.left as f32,
+            Anchor::Center => self.ui.left as f32 + self.ui.width as f32 / 2.0,
+            Anchor::Right => self.ui.right() as f32,
+        };
+        (
+            (origin + self.scaled(x)).round() as isize,
+            (self.ui.top as f32 + self.scaled(y)).round() as isize,
+        )
+    }
+
+    /// Map a region measured on the reference client area to the screen,
+    /// clamped so that it never goes beyond the client area.
+    ///
+    /// There's no region at all if the client area is empty (such as when the game is minimized).
+    pub fn place(&self, region: &UiRegion) -> Option<Rect> {
+        if self.is_empty() {
+            return None;
+        }
+        let (left, top) = self.point(region.anchor, region.x as f32, region.y as f32);
+        let left = left.clamp(self.client.left, self.client.right() - 1);
+        let top = top.clamp(self.client.top, self.client.bottom() - 1);
+        let width = (self.scaled(region.width as f32).round() as usize)
+            .clamp(1, (self.client.right() - left) as usize);
+        let height = (self.scaled(region.height as f32).round() as usize)
+            .clamp(1, (self.client.bottom() - top) as usize);
+        Some(Rect {
+            left,
+            top,
+            width,
+            height,
+        })
+    }
+}
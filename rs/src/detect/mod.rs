@@ -0,0 +1,396 @@
+//! Detectors for the state of the game's UI, which work on screenshots of the game window.
+//!
+//! Screenshots saved with `poe capture` (or `do screenshot`) contain the verdict of every
+//! detector at the time of the capture, so they can be used to test the detectors offline.
+mod layout;
+
+pub use layout::{Anchor, Layout, Profile, UiRegion};
+
+use crate::image::{Rect, Screenshot};
+use crate::types::Opened;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Captures of a single detector's region have this text key, and can't be detected on again.
+pub const CROP_TEXT_KEY: &str = "crop";
+
+const CHAT_BORDER_THICKNESS: usize = 2;
+const CHAT_REGION: UiRegion = UiRegion {
+    anchor: Anchor::Left,
+    x: 0,
+    y: 756,
+    width: CHAT_BORDER_THICKNESS,
+    height: 54,
+};
+const CHAT_BORDER_COLOR: (u8, u8, u8) = (136, 98, 59);
+const CHAT_CHECK_HEIGHT: usize = 32;
+
+// The panel headers share the same golden trim.
+const PANEL_HEADER_COLOR: (u8, u8, u8) = (154, 126, 85);
+const PANEL_HEADER_TOLERANCE: u8 = 12;
+const PANEL_HEADER_MIN_PCT: usize = 30;
+
+const INVENTORY_REGION: UiRegion = UiRegion {
+    anchor: Anchor::Right,
+    x: -634,
+    y: 97,
+    width: 614,
+    height: 10,
+};
+const STASH_REGION: UiRegion = UiRegion {
+    anchor: Anchor::Left,
+    x: 19,
+    y: 97,
+    width: 614,
+    height: 10,
+};
+const MAP_DEVICE_REGION: UiRegion = UiRegion {
+    anchor: Anchor::Center,
+    x: -384,
+    y: 162,
+    width: 768,
+    height: 10,
+};
+
+// The "resurrect" panel darkens the whole screen and sits on its center, framed by the same
+// golden trim as the panel headers. Loading screens and dark areas are as dark, but unframed.
+const DEATH_REGION: UiRegion = UiRegion {
+    anchor: Anchor::Center,
+    x: -192,
+    y: 216,
+    width: 384,
+    height: 108,
+};
+// Of everything but the trim, which also leaves out most of the text.
+const DEATH_MAX_BRIGHTNESS: usize = 40;
+// How much of a row the trim must cover to be an edge of the frame, and how many edges it takes.
+const DEATH_EDGE_MIN_PCT: usize = 60;
+const DEATH_MIN_EDGES: usize = 2;
+
+/// A screen-based condition which can be detected from a region of the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Detector {
+    Chat,
+    Inventory,
+    Stash,
+    MapDevice,
+    Death,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ScreenState {
+    pub chat_open: Opened,
+    pub inventory_open: Opened,
+    pub stash_open: Opened,
+    pub map_device_open: Opened,
+    pub dead: bool,
+}
+
+fn similar(a: (u8, u8, u8), b: (u8, u8, u8), tolerance: u8) -> bool {
+    a.0.abs_diff(b.0) <= tolerance
+        && a.1.abs_diff(b.1) <= tolerance
+        && a.2.abs_diff(b.2) <= tolerance
+}
+
+fn check_chat(screenshot: &Screenshot) -> Opened {
+    let mut longest_run = 0;
+    for color in screenshot.colors() {
+        if color == CHAT_BORDER_COLOR {
+            longest_run += 1;
+            if longest_run >= CHAT_CHECK_HEIGHT * CHAT_BORDER_THICKNESS {
+                return Opened::Open;
+            }
+        } else {
+            longest_run = 0;
+        }
+    }
+    Opened::Closed
+}
+
+fn check_panel_header(screenshot: &Screenshot) -> Opened {
+    let (mut matching, mut total) = (0, 0);
+    for color in screenshot.colors() {
+        total += 1;
+        if similar(color, PANEL_HEADER_COLOR, PANEL_HEADER_TOLERANCE) {
+            matching += 1;
+        }
+    }
+    if total != 0 && matching * 100 >= total * PANEL_HEADER_MIN_PCT {
+        Opened::Open
+    } else {
+        Opened::Closed
+    }
+}
+
+fn check_death(screenshot: &Screenshot) -> bool {
+    let region = &screenshot.region;
+    let (mut brightness, mut total, mut edges) = (0, 0, 0);
+    // The trim is a few pixels thick, and every row of it is part of the same edge.
+    let mut in_edge = false;
+    for y in 0..region.height {
+        let mut trim = 0;
+        for x in 0..region.width {
+            let color = screenshot.color(x, y);
+            if similar(color, PANEL_HEADER_COLOR, PANEL_HEADER_TOLERANCE) {
+                trim += 1;
+            } else {
+                brightness += (color.0 as usize + color.1 as usize + color.2 as usize) / 3;
+                total += 1;
+            }
+        }
+        let is_edge = trim * 100 >= region.width * DEATH_EDGE_MIN_PCT;
+        if is_edge && !in_edge {
+            edges += 1;
+        }
+        in_edge = is_edge;
+    }
+    edges >= DEATH_MIN_EDGES && total != 0 && brightness / total <= DEATH_MAX_BRIGHTNESS
+}
+
+impl Detector {
+    pub const ALL: [Detector; 5] = [
+        Detector::Chat,
+        Detector::Inventory,
+        Detector::Stash,
+        Detector::MapDevice,
+        Detector::Death,
+    ];
+
+    /// The region of the screen this detector needs to look at, if there's anything to look at.
+    pub fn region(&self, layout: &Layout) -> Option<Rect> {
+        match self {
+            Self::Chat => {
+                // The border doesn't get any thicker at higher resolutions.
+                let mut region = layout.place(&CHAT_REGION)?;
+                region.width = CHAT_BORDER_THICKNESS.min(region.width);
+                Some(region)
+            }
+            Self::Inventory => layout.place(&INVENTORY_REGION),
+            Self::Stash => layout.place(&STASH_REGION),
+            Self::MapDevice => layout.place(&MAP_DEVICE_REGION),
+            Self::Death => layout.place(&DEATH_REGION),
+        }
+    }
+
+    /// Run the detector on a screenshot of its region and store the verdict.
+    pub fn detect(&self, screenshot: &Screenshot, state: &mut ScreenState) {
+        match self {
+            Self::Chat => state.chat_open = check_chat(screenshot),
+            Self::Inventory => state.inventory_open = check_panel_header(screenshot),
+            Self::Stash => state.stash_open = check_panel_header(screenshot),
+            Self::MapDevice => state.map_device_open = check_panel_header(screenshot),
+            Self::Death => state.dead = check_death(screenshot),
+        }
+    }
+}
+
+impl ScreenState {
+    /// The verdict of the given detector, in the same words the rules use.
+    pub fn verdict(&self, detector: Detector) -> String {
+        match detector {
+            Detector::Chat => self.chat_open.to_string(),
+            Detector::Inventory => self.inventory_open.to_string(),
+            Detector::Stash => self.stash_open.to_string(),
+            Detector::MapDevice => self.map_device_open.to_string(),
+            Detector::Death => if self.dead { "dead" } else { "alive" }.to_string(),
+        }
+    }
+}
+
+/// Run the detectors on a screenshot of the whole client area of the game window.
+///
+/// Nothing is detected on an empty screenshot, so every verdict is left as the default one.
+pub fn detect_all(screenshot: &Screenshot, detectors: &[Detector]) -> ScreenState {
+    let layout = Layout::new(screenshot.region.clone());
+    let mut state = ScreenState::default();
+    for detector in detectors {
+        if let Some(region) = detector.region(&layout) {
+            detector.detect(&screenshot.crop(&region), &mut state);
+        }
+    }
+    state
+}
+
+/// Save a screenshot of the whole client area along with the verdict of every detector.
+pub fn save_capture<P: AsRef<Path>>(screenshot: &Screenshot, path: P) -> io::Result<ScreenState> {
+    let state = detect_all(screenshot, &Detector::ALL);
+    let text = Detector::ALL
+        .iter()
+        .map(|detector| (detector.to_string(), state.verdict(*detector)))
+        .collect::<Vec<_>>();
+    screenshot.save_png(path, &text)?;
+    Ok(state)
+}
+
+/// Save only the region of the client area a detector looks at, along with its verdict.
+pub fn save_detector_capture<P: AsRef<Path>>(
+    screenshot: &Screenshot,
+    detector: Detector,
+    path: P,
+) -> io::Result<ScreenState> {
+    let state = detect_all(screenshot, &[detector]);
+    let region = detector
+        .region(&Layout::new(screenshot.region.clone()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the capture is empty"))?;
+    let text = vec![
+        (detector.to_string(), state.verdict(detector)),
+        (CROP_TEXT_KEY.to_string(), detector.to_string()),
+    ];
+    screenshot.crop(&region).save_png(path, &text)?;
+    Ok(state)
+}
+
+impl FromStr for Detector {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        Ok(match word {
+            "chat" => Self::Chat,
+            "inventory" => Self::Inventory,
+            "stash" => Self::Stash,
+            "device" => Self::MapDevice,
+            "death" => Self::Death,
+            _ => return Err("detector can only be chat, inventory, stash, device or death"),
+        })
+    }
+}
+
+impl fmt::Display for Detector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Chat => "chat",
+            Self::Inventory => "inventory",
+            Self::Stash => "stash",
+            Self::MapDevice => "device",
+            Self::Death => "death",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_1080p() -> Vec<u8> {
+        vec![0; 1920 * 1080 * 3]
+    }
+
+    fn fill(rgb: &mut [u8], region: &Rect, color: (u8, u8, u8)) {
+        for y in region.top..region.bottom() {
+            for x in region.left..region.right() {
+                let i = (y as usize * 1920 + x as usize) * 3;
+                rgb[i..i + 3].copy_from_slice(&[color.0, color.1, color.2]);
+            }
+        }
+    }
+
+    fn client() -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            width: 1920,
+            height: 1080,
+        }
+    }
+
+    #[test]
+    fn chat_closed() {
+        let screenshot = Screenshot::from_rgb(client(), &blank_1080p());
+        let state = detect_all(&screenshot, &[Detector::Chat]);
+        assert_eq!(state.chat_open, Opened::Closed);
+    }
+
+    #[test]
+    fn chat_open() {
+        let mut rgb = blank_1080p();
+        let region = Detector::Chat.region(&Layout::new(client())).unwrap();
+        fill(&mut rgb, &region, CHAT_BORDER_COLOR);
+        let screenshot = Screenshot::from_rgb(client(), &rgb);
+        let state = detect_all(&screenshot, &[Detector::Chat]);
+        assert_eq!(state.chat_open, Opened::Open);
+    }
+
+    #[test]
+    fn death() {
+        let dead =
+            |rgb: &[u8]| detect_all(&Screenshot::from_rgb(client(), rgb), &[Detector::Death]).dead;
+        // A loading screen is just as dark, but has no resurrect panel.
+        let mut rgb = blank_1080p();
+        assert!(!dead(&rgb));
+
+        let region = Detector::Death.region(&Layout::new(client())).unwrap();
+        fill(&mut rgb, &region, (12, 10, 8));
+        let edge = |top| Rect {
+            top,
+            height: 2,
+            ..region.clone()
+        };
+        fill(&mut rgb, &edge(region.top + 10), PANEL_HEADER_COLOR);
+        assert!(!dead(&rgb));
+        fill(&mut rgb, &edge(region.bottom() - 12), PANEL_HEADER_COLOR);
+        assert!(dead(&rgb));
+
+        // Nor is a bright screen with the same trim.
+        fill(&mut rgb, &edge(region.top + 40), (200, 200, 200));
+        fill(&mut rgb, &edge(region.top + 50), (200, 200, 200));
+        fill(
+            &mut rgb,
+            &Rect {
+                height: 30,
+                ..edge(region.top + 60)
+            },
+            (200, 200, 200),
+        );
+        assert!(!dead(&rgb));
+    }
+
+    #[test]
+    fn regions_follow_anchors() {
+        let wide = Layout::new(Rect {
+            left: -3440,
+            top: 0,
+            width: 3440,
+            height: 1440,
+        });
+        assert_eq!(wide.profile(), Profile::Standard);
+        let inventory = Detector::Inventory.region(&wide).unwrap();
+        assert_eq!(inventory.right(), -26);
+        let stash = Detector::Stash.region(&wide).unwrap();
+        assert_eq!(stash.left, -3440 + 25);
+
+        // On 32:9 the panels stick to the sides of a 21:9 area in the middle.
+        let super_wide = Layout::new(Rect {
+            left: 0,
+            top: 0,
+            width: 5120,
+            height: 1440,
+        });
+        assert_eq!(super_wide.profile(), Profile::Ultrawide);
+        let stash = Detector::Stash.region(&super_wide).unwrap();
+        assert_eq!(stash.left, (5120 - 3456) / 2 + 25);
+        let inventory = Detector::Inventory.region(&super_wide).unwrap();
+        assert_eq!(inventory.right(), 5120 - (5120 - 3456) / 2 - 26);
+    }
+
+    #[test]
+    fn minimized() {
+        let empty = Rect {
+            left: -32000,
+            top: -32000,
+            width: 0,
+            height: 0,
+        };
+        let layout = Layout::new(empty.clone());
+        assert!(layout.is_empty());
+        assert!(Detector::ALL
+            .iter()
+            .all(|detector| detector.region(&layout).is_none()));
+        let screenshot = Screenshot::from_rgb(empty, &[]);
+        assert_eq!(
+            detect_all(&screenshot, &Detector::ALL),
+            ScreenState::default()
+        );
+    }
+}
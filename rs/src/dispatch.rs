@@ -0,0 +1,649 @@
+//! The single place through which actions send their input.
+//!
+//! Actions describe what to press, and the [`Dispatcher`] decides how long to hold it
+//! and how long to wait in between, following the configured [`Timing`].
+use crate::timing::{Distribution, Rng, Timing};
+use crate::types::Vk;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+pub const VK_LBUTTON: u16 = 0x01;
+pub const VK_RBUTTON: u16 = 0x02;
+pub const VK_MBUTTON: u16 = 0x04;
+pub const VK_RETURN: u16 = 0x0D;
+pub const VK_SHIFT: u16 = 0x10;
+pub const VK_CONTROL: u16 = 0x11;
+pub const VK_MENU: u16 = 0x12;
+
+/// Something which can deliver input, be it the operating system or a test recorder.
+///
+/// Mouse buttons are identified by their Virtual Key Code.
+pub trait InputSink {
+    /// Press (`down`) or release a key or mouse button.
+    fn send(&mut self, vk: u16, down: bool);
+
+    /// Press or release the key for a character, regardless of the keyboard layout.
+    fn send_char(&mut self, c: char, down: bool);
+
+    /// Find the key (and modifiers to hold) needed to type the character.
+    fn scan(&self, c: char) -> Option<(u16, Vec<u16>)>;
+
+    /// Move the cursor to the given point on the screen.
+    fn move_to(&mut self, x: isize, y: isize);
+
+    /// Where the cursor is on the screen, if known.
+    fn cursor(&self) -> Option<(isize, isize)>;
+
+    /// Scroll the mouse wheel up (positive) or down (negative) by some notches.
+    fn scroll(&mut self, amount: isize);
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// Input which would have been sent, had it not been a dry run.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Intent {
+    /// A key or mouse button pressed while holding the modifiers.
+    Press {
+        modifiers: Vec<u16>,
+        vk: u16,
+    },
+    /// A mouse button clicked while holding the modifiers, after moving the cursor if given.
+    Click {
+        modifiers: Vec<u16>,
+        button: u16,
+        at: Option<(isize, isize)>,
+    },
+    Hold(u16),
+    Release(u16),
+    Move(isize, isize),
+    Scroll(isize),
+    Type(String),
+    /// Not input as such, but rules disconnecting must not do so in a dry run either.
+    Disconnect,
+}
+
+pub struct Dispatcher<S: InputSink> {
+    sink: S,
+    rng: Rng,
+    timing: Timing,
+    /// Whether an input was already sent for the current action, so the next one needs a gap.
+    sent: bool,
+    /// Whether input is recorded instead of reaching the sink, to see what rules would do without
+    /// them actually doing it.
+    dry_run: bool,
+    /// The input recorded during a dry run, since it was last taken.
+    intents: Vec<Intent>,
+}
+
+/// Keys held down until dropped, so that they are released even if the action fails midway.
+pub struct Held<'d, S: InputSink> {
+    dispatcher: &'d mut Dispatcher<S>,
+    vks: Vec<u16>,
+}
+
+impl<S: InputSink> Dispatcher<S> {
+    pub fn new(sink: S, rng: Rng) -> Self {
+        Self {
+            sink,
+            rng,
+            timing: Timing::default(),
+            sent: false,
+            dry_run: false,
+            intents: Vec::new(),
+        }
+    }
+
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// During a dry run, record the input instead of sending it.
+    ///
+    /// Returns whether it was recorded, in which case nothing must be sent.
+    pub fn record(&mut self, intent: Intent) -> bool {
+        if self.dry_run {
+            self.intents.push(intent);
+        }
+        self.dry_run
+    }
+
+    /// The input recorded during a dry run since the last time it was taken.
+    pub fn take_intents(&mut self) -> Vec<Intent> {
+        std::mem::take(&mut self.intents)
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Start sending the input for a new action, using its timing.
+    pub fn begin(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.sent = false;
+    }
+
+    fn wait(&mut self, distribution: Option<Distribution>) {
+        let duration = distribution
+            .unwrap_or(Distribution::ZERO)
+            .sample(&mut self.rng);
+        if !duration.is_zero() && !self.dry_run {
+            self.sink.sleep(duration);
+        }
+    }
+
+    /// How much longer than its cooldown the current action should wait.
+    pub fn jitter(&mut self) -> Duration {
+        self.timing
+            .jitter
+            .unwrap_or(Distribution::ZERO)
+            .sample(&mut self.rng)
+    }
+
+    /// Wait between the previous input of the action and the next one.
+    fn next_input(&mut self) {
+        if self.sent {
+            self.wait(self.timing.gap);
+        }
+        self.sent = true;
+    }
+
+    pub fn hold(&mut self, vk: u16) {
+        if self.record(Intent::Hold(vk)) {
+            return;
+        }
+        self.next_input();
+        self.sink.send(vk, true);
+    }
+
+    pub fn release(&mut self, vk: u16) {
+        if self.record(Intent::Release(vk)) {
+            return;
+        }
+        self.sink.send(vk, false);
+    }
+
+    /// Press (hold down and then release) a key or mouse button.
+    pub fn press(&mut self, vk: u16) {
+        self.press_with(&[], vk);
+    }
+
+    fn press_without_modifiers(&mut self, vk: u16) {
+        self.hold(vk);
+        self.wait(self.timing.hold);
+        self.release(vk);
+    }
+
+    /// Hold down the keys until the returned guard is dropped.
+    pub fn hold_all(&mut self, vks: &[u16]) -> Held<'_, S> {
+        vks.iter().for_each(|vk| self.hold(*vk));
+        Held {
+            dispatcher: self,
+            vks: vks.to_vec(),
+        }
+    }
+
+    /// Like `press` but holding the modifiers while doing so.
+    pub fn press_with(&mut self, modifiers: &[u16], vk: u16) {
+        let intent = Intent::Press {
+            modifiers: modifiers.to_vec(),
+            vk,
+        };
+        if self.record(intent) {
+            return;
+        }
+        self.hold_all(modifiers).press_without_modifiers(vk);
+    }
+
+    pub fn move_to(&mut self, (x, y): (isize, isize)) {
+        if self.record(Intent::Move(x, y)) {
+            return;
+        }
+        self.next_input();
+        self.sink.move_to(x, y);
+    }
+
+    pub fn scroll(&mut self, amount: isize) {
+        if self.record(Intent::Scroll(amount)) {
+            return;
+        }
+        self.next_input();
+        self.sink.scroll(amount);
+    }
+
+    /// Hold the left mouse button at one point and release it at another.
+    pub fn drag(&mut self, from: (isize, isize), to: (isize, isize)) {
+        self.move_to(from);
+        let mut held = self.hold_all(&[VK_LBUTTON]);
+        let hold = held.timing.hold;
+        held.wait(hold);
+        held.move_to(to);
+    }
+
+    /// Click a mouse button while holding the modifiers, optionally somewhere else.
+    ///
+    /// If `restore` is set, the cursor is moved back to where it was afterwards.
+    pub fn click(
+        &mut self,
+        modifiers: &[u16],
+        button: u16,
+        at: Option<(isize, isize)>,
+        restore: bool,
+    ) {
+        let intent = Intent::Click {
+            modifiers: modifiers.to_vec(),
+            button,
+            at,
+        };
+        if self.record(intent) {
+            return;
+        }
+        let previous = if restore { self.sink.cursor() } else { None };
+        if let Some(at) = at {
+            self.move_to(at);
+        }
+        self.press_with(modifiers, button);
+        if let Some(previous) = previous {
+            self.move_to(previous);
+        }
+    }
+
+    /// Press (hold down and then release) the key for a character.
+    pub fn press_char(&mut self, c: char) {
+        if self.record(Intent::Type(c.to_string())) {
+            return;
+        }
+        self.next_input();
+        self.sink.send_char(c, true);
+        self.wait(self.timing.hold);
+        self.sink.send_char(c, false);
+    }
+
+    /// Type a string using the keyboard.
+    ///
+    /// Characters are sent as they are, so any text can be typed regardless of the layout.
+    /// Chat commands (starting with `/`) are typed with the actual keys where possible instead,
+    /// since the game only recognizes some of them when typed that way.
+    pub fn type_string(&mut self, string: &str) {
+        if self.record(Intent::Type(string.to_string())) {
+            return;
+        }
+        let command = string.starts_with('/');
+        for c in string.chars() {
+            match self.sink.scan(c) {
+                Some((vk, modifiers)) if command && c.is_ascii() => self.press_with(&modifiers, vk),
+                _ => self.press_char(c),
+            }
+        }
+    }
+}
+
+/// Write the keys like they're written in rules, such as `ctrl+shift+d`.
+fn write_keys(f: &mut fmt::Formatter<'_>, modifiers: &[u16], vk: u16) -> fmt::Result {
+    for modifier in modifiers.iter() {
+        write!(f, "{}+", Vk(*modifier))?;
+    }
+    write!(f, "{}", Vk(vk))
+}
+
+impl fmt::Display for Intent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Press { modifiers, vk } => {
+                f.write_str("press ")?;
+                write_keys(f, modifiers, *vk)
+            }
+            Self::Click {
+                modifiers,
+                button,
+                at,
+            } => {
+                f.write_str("click ")?;
+                write_keys(f, modifiers, *button)?;
+                match at {
+                    Some((x, y)) => write!(f, " at {},{}", x, y),
+                    None => Ok(()),
+                }
+            }
+            Self::Hold(vk) => write!(f, "hold {}", Vk(*vk)),
+            Self::Release(vk) => write!(f, "release {}", Vk(*vk)),
+            Self::Move(x, y) => write!(f, "move to {},{}", x, y),
+            Self::Scroll(amount) => write!(f, "scroll {}", amount),
+            Self::Type(text) => write!(f, "type {:?}", text),
+            Self::Disconnect => f.write_str("disconnect"),
+        }
+    }
+}
+
+impl<S: InputSink> Deref for Held<'_, S> {
+    type Target = Dispatcher<S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.dispatcher
+    }
+}
+
+impl<S: InputSink> DerefMut for Held<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.dispatcher
+    }
+}
+
+impl<S: InputSink> Drop for Held<'_, S> {
+    fn drop(&mut self) {
+        for vk in self.vks.iter().rev() {
+            self.dispatcher.release(*vk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Input {
+        Down(u16),
+        Up(u16),
+        CharDown(char),
+        CharUp(char),
+        Move(isize, isize),
+        Scroll(isize),
+        Sleep(Duration),
+    }
+
+    /// Records the input instead of sending it.
+    #[derive(Default)]
+    struct Recorder(Vec<Input>);
+
+    /// Fails while sending the given key.
+    struct Failing(Recorder, u16);
+
+    impl InputSink for Failing {
+        fn send(&mut self, vk: u16, down: bool) {
+            self.0.send(vk, down);
+            if down && vk == self.1 {
+                panic!("failed to send key");
+            }
+        }
+
+        fn send_char(&mut self, c: char, down: bool) {
+            self.0.send_char(c, down);
+        }
+
+        fn scan(&self, c: char) -> Option<(u16, Vec<u16>)> {
+            self.0.scan(c)
+        }
+
+        fn move_to(&mut self, x: isize, y: isize) {
+            self.0.move_to(x, y);
+        }
+
+        fn cursor(&self) -> Option<(isize, isize)> {
+            self.0.cursor()
+        }
+
+        fn scroll(&mut self, amount: isize) {
+            self.0.scroll(amount);
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.0.sleep(duration);
+        }
+    }
+
+    impl InputSink for Recorder {
+        fn send(&mut self, vk: u16, down: bool) {
+            self.0
+                .push(if down { Input::Down(vk) } else { Input::Up(vk) });
+        }
+
+        fn send_char(&mut self, c: char, down: bool) {
+            self.0.push(if down {
+                Input::CharDown(c)
+            } else {
+                Input::CharUp(c)
+            });
+        }
+
+        fn scan(&self, c: char) -> Option<(u16, Vec<u16>)> {
+            match c {
+                'a'..='z' => Some((c.to_ascii_uppercase() as u16, vec![])),
+                'A'..='Z' => Some((c as u16, vec![VK_SHIFT])),
+                '/' => Some((0xBF, vec![])),
+                ' ' => Some((0x20, vec![])),
+                _ => None,
+            }
+        }
+
+        fn move_to(&mut self, x: isize, y: isize) {
+            self.0.push(Input::Move(x, y));
+        }
+
+        fn cursor(&self) -> Option<(isize, isize)> {
+            self.0.iter().rev().find_map(|input| match input {
+                Input::Move(x, y) => Some((*x, *y)),
+                _ => None,
+            })
+        }
+
+        fn scroll(&mut self, amount: isize) {
+            self.0.push(Input::Scroll(amount));
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.0.push(Input::Sleep(duration));
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn no_timing_sends_back_to_back() {
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(Timing::default());
+        dispatcher.press_with(&[VK_SHIFT], 0x42);
+        dispatcher.press_char('a');
+        assert_eq!(
+            dispatcher.sink().0,
+            vec![
+                Input::Down(VK_SHIFT),
+                Input::Down(0x42),
+                Input::Up(0x42),
+                Input::Up(VK_SHIFT),
+                Input::CharDown('a'),
+                Input::CharUp('a'),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_is_typed_as_characters() {
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(Timing::default());
+        dispatcher.type_string("Hé 日");
+        let typed = dispatcher
+            .sink()
+            .0
+            .iter()
+            .filter_map(|input| match input {
+                Input::CharDown(c) => Some(*c),
+                Input::CharUp(_) => None,
+                _ => panic!("unexpected {:?}", input),
+            })
+            .collect::<String>();
+        assert_eq!(typed, "Hé 日");
+    }
+
+    #[test]
+    fn commands_use_keys() {
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(Timing::default());
+        dispatcher.type_string("/Hi ü");
+        assert_eq!(
+            dispatcher.sink().0,
+            vec![
+                Input::Down(0xBF),
+                Input::Up(0xBF),
+                Input::Down(VK_SHIFT),
+                Input::Down(0x48),
+                Input::Up(0x48),
+                Input::Up(VK_SHIFT),
+                Input::Down(0x49),
+                Input::Up(0x49),
+                Input::Down(0x20),
+                Input::Up(0x20),
+                // not on the keyboard, so it's sent as a character
+                Input::CharDown('ü'),
+                Input::CharUp('ü'),
+            ]
+        );
+    }
+
+    #[test]
+    fn modifiers_released_on_failure() {
+        let mut dispatcher = Dispatcher::new(Failing(Recorder::default(), 0x44), Rng::new(0));
+        dispatcher.begin(Timing::default());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            dispatcher.press_with(&[VK_CONTROL, VK_SHIFT], 0x44);
+        }));
+        assert!(result.is_err());
+        assert_eq!(
+            dispatcher.sink().0 .0,
+            vec![
+                Input::Down(VK_CONTROL),
+                Input::Down(VK_SHIFT),
+                Input::Down(0x44),
+                Input::Up(VK_SHIFT),
+                Input::Up(VK_CONTROL),
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_records_instead() {
+        let mut timing = Timing::default();
+        timing.set("hold", "50ms").unwrap();
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.set_dry_run(true);
+        dispatcher.begin(timing);
+        dispatcher.click(&[VK_SHIFT], VK_LBUTTON, Some((1, 2)), true);
+        dispatcher.press_with(&[VK_CONTROL], 0x31);
+        dispatcher.type_string("/hi");
+        dispatcher.drag((3, 4), (5, 6));
+        assert_eq!(dispatcher.sink().0, vec![]);
+
+        let intents = dispatcher.take_intents();
+        assert_eq!(
+            intents.iter().map(Intent::to_string).collect::<Vec<_>>(),
+            vec![
+                "click shift+lmb at 1,2",
+                "press ctrl+1",
+                "type \"/hi\"",
+                "move to 3,4",
+                "hold lmb",
+                "move to 5,6",
+                "release lmb",
+            ]
+        );
+        assert!(dispatcher.take_intents().is_empty());
+
+        dispatcher.set_dry_run(false);
+        assert!(!dispatcher.record(Intent::Disconnect));
+        dispatcher.press(0x31);
+        assert_eq!(
+            dispatcher.sink().0,
+            vec![Input::Down(0x31), Input::Sleep(ms(50)), Input::Up(0x31)]
+        );
+        assert!(dispatcher.take_intents().is_empty());
+    }
+
+    #[test]
+    fn drag() {
+        let mut timing = Timing::default();
+        timing.set("hold", "50ms").unwrap();
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(timing);
+        dispatcher.drag((10, 20), (-30, 40));
+        assert_eq!(
+            dispatcher.sink().0,
+            vec![
+                Input::Move(10, 20),
+                Input::Down(VK_LBUTTON),
+                Input::Sleep(ms(50)),
+                Input::Move(-30, 40),
+                Input::Up(VK_LBUTTON),
+            ]
+        );
+    }
+
+    #[test]
+    fn click_and_restore() {
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(Timing::default());
+        dispatcher.move_to((5, 5));
+        dispatcher.click(&[VK_CONTROL], VK_LBUTTON, Some((100, 200)), true);
+        assert_eq!(
+            dispatcher.sink().0[1..],
+            [
+                Input::Move(100, 200),
+                Input::Down(VK_CONTROL),
+                Input::Down(VK_LBUTTON),
+                Input::Up(VK_LBUTTON),
+                Input::Up(VK_CONTROL),
+                Input::Move(5, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn hold_and_gap() {
+        let mut timing = Timing::default();
+        timing.set("hold", "30ms").unwrap();
+        timing.set("gap", "10ms").unwrap();
+        let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(0));
+        dispatcher.begin(timing);
+        dispatcher.press_with(&[VK_CONTROL], VK_RETURN);
+        assert_eq!(
+            dispatcher.sink().0,
+            vec![
+                Input::Down(VK_CONTROL),
+                Input::Sleep(ms(10)),
+                Input::Down(VK_RETURN),
+                Input::Sleep(ms(30)),
+                Input::Up(VK_RETURN),
+                Input::Up(VK_CONTROL),
+            ]
+        );
+
+        // A new action shouldn't wait before its first input.
+        dispatcher.begin(timing);
+        dispatcher.press(VK_LBUTTON);
+        assert_eq!(dispatcher.sink().0[6], Input::Down(VK_LBUTTON));
+    }
+
+    #[test]
+    fn deterministic_under_seed() {
+        let mut timing = Timing::default();
+        timing.set("hold", "20-40ms").unwrap();
+        timing.set("gap", "30ms~5ms").unwrap();
+        timing.set("jitter", "0-100ms").unwrap();
+        let run = |seed| {
+            let mut dispatcher = Dispatcher::new(Recorder::default(), Rng::new(seed));
+            dispatcher.begin(timing);
+            dispatcher.type_string("hello");
+            let jitter = dispatcher.jitter();
+            (dispatcher.sink.0, jitter)
+        };
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+}
@@ -0,0 +1,293 @@
+//! Maps the cells of the inventory and stash grids to screen coordinates.
+//!
+//! Cells are counted from 1, starting at the top-left corner, as `col,row`.
+use crate::detect::{Anchor, Layout};
+use std::fmt;
+use std::str::FromStr;
+
+/// Where a grid is, as measured on the reference 1920x1080 client area.
+struct GridRegion {
+    anchor: Anchor,
+    x: f32,
+    y: f32,
+    cols: usize,
+    rows: usize,
+    cell: f32,
+}
+
+const INVENTORY_GRID: GridRegion = GridRegion {
+    anchor: Anchor::Right,
+    x: -649.0,
+    y: 586.0,
+    cols: 12,
+    rows: 5,
+    cell: 52.6,
+};
+const STASH_GRID: GridRegion = GridRegion {
+    anchor: Anchor::Left,
+    x: 15.0,
+    y: 126.0,
+    cols: 12,
+    rows: 12,
+    cell: 632.0 / 12.0,
+};
+// Quad tabs take the same room as normal tabs, with cells half as large.
+const QUAD_STASH_GRID: GridRegion = GridRegion {
+    cols: 24,
+    rows: 24,
+    cell: 632.0 / 24.0,
+    ..STASH_GRID
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grid {
+    Inventory,
+    Stash,
+    QuadStash,
+}
+
+/// A cell of a grid, counting from 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub col: usize,
+    pub row: usize,
+}
+
+/// A single cell of a specific grid, such as where a currency item is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    pub grid: Grid,
+    pub cell: Cell,
+}
+
+/// An inclusive range of columns or rows, such as `11-12` (or a single one, such as `12`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span(pub usize, pub usize);
+
+/// Which cells of a grid to go through.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Selection {
+    /// The rectangle of cells from the first to the last, or the whole grid.
+    pub cells: Option<(Cell, Cell)>,
+    /// The columns to leave out, such as those where items are kept.
+    pub except_cols: Option<Span>,
+}
+
+impl Grid {
+    fn region(&self) -> &'static GridRegion {
+        match self {
+            Self::Inventory => &INVENTORY_GRID,
+            Self::Stash => &STASH_GRID,
+            Self::QuadStash => &QUAD_STASH_GRID,
+        }
+    }
+
+    pub fn cols(&self) -> usize {
+        self.region().cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.region().rows
+    }
+
+    pub fn contains(&self, cell: Cell) -> bool {
+        (1..=self.cols()).contains(&cell.col) && (1..=self.rows()).contains(&cell.row)
+    }
+
+    /// The point on the screen at the center of the cell.
+    pub fn center(&self, layout: &Layout, cell: Cell) -> (isize, isize) {
+        let region = self.region();
+        layout.point(
+            region.anchor,
+            region.x + (cell.col as f32 - 0.5) * region.cell,
+            region.y + (cell.row as f32 - 0.5) * region.cell,
+        )
+    }
+
+    /// The selected cells, column by column (which is how items are usually picked up).
+    pub fn select(&self, selection: &Selection) -> Vec<Cell> {
+        let (first, last) = selection.cells.unwrap_or((
+            Cell { col: 1, row: 1 },
+            Cell {
+                col: self.cols(),
+                row: self.rows(),
+            },
+        ));
+        let mut cells = Vec::new();
+        for col in first.col..=last.col.min(self.cols()) {
+            if selection.except_cols.is_some_and(|span| span.contains(col)) {
+                continue;
+            }
+            for row in first.row..=last.row.min(self.rows()) {
+                cells.push(Cell { col, row });
+            }
+        }
+        cells
+    }
+}
+
+impl Cell {
+    /// Parse a rectangle of cells given by its corners, such as `1,1-6,5` (or a single cell).
+    pub fn parse_range(word: &str) -> Result<(Cell, Cell), &'static str> {
+        let (first, last) = word.split_once('-').unwrap_or((word, word));
+        let (first, last) = (first.parse::<Cell>()?, last.parse::<Cell>()?);
+        if first.col > last.col || first.row > last.row {
+            return Err("cells must go from the top-left corner to the bottom-right one");
+        }
+        Ok((first, last))
+    }
+}
+
+impl Span {
+    pub fn contains(&self, n: usize) -> bool {
+        self.0 <= n && n <= self.1
+    }
+}
+
+impl FromStr for Grid {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        Ok(match word {
+            "inventory" => Self::Inventory,
+            "stash" => Self::Stash,
+            "quad-stash" => Self::QuadStash,
+            _ => return Err("grid can only be inventory, stash or quad-stash"),
+        })
+    }
+}
+
+impl FromStr for Cell {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        let (col, row) = word
+            .split_once(',')
+            .ok_or("cell must be given as COL,ROW")?;
+        match (col.parse(), row.parse()) {
+            (Ok(col), Ok(row)) if col > 0 && row > 0 => Ok(Self { col, row }),
+            _ => Err("cell column and row must be numbers starting at 1"),
+        }
+    }
+}
+
+impl FromStr for Span {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        let (first, last) = word.split_once('-').unwrap_or((word, word));
+        match (first.parse(), last.parse()) {
+            (Ok(first), Ok(last)) if 0 < first && first <= last => Ok(Self(first, last)),
+            _ => Err("range must be given as FIRST-LAST, starting at 1"),
+        }
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Inventory => "inventory",
+            Self::Stash => "stash",
+            Self::QuadStash => "quad-stash",
+        })
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.col, self.row)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.grid, self.cell)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == self.1 {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{}-{}", self.0, self.1)
+        }
+    }
+}
+
+/// Displays as written in rules, such as `all except cols 11-12` or `cells 1,1-6,5`.
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cells {
+            Some((first, last)) if first == last => write!(f, "cells {}", first)?,
+            Some((first, last)) => write!(f, "cells {}-{}", first, last)?,
+            None => f.write_str("all")?,
+        }
+        if let Some(span) = self.except_cols {
+            write!(f, " except cols {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Rect;
+
+    fn layout(width: usize, height: usize) -> Layout {
+        Layout::new(Rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        })
+    }
+
+    #[test]
+    fn centers() {
+        let hd = layout(1920, 1080);
+        let first = Cell { col: 1, row: 1 };
+        assert_eq!(Grid::Inventory.center(&hd, first), (1297, 612));
+        assert_eq!(Grid::Stash.center(&hd, first), (41, 152));
+        assert_eq!(Grid::QuadStash.center(&hd, first), (28, 139));
+
+        // the inventory sticks to the right, so only its size changes with the height
+        let wide = layout(3440, 1440);
+        let (x, y) = Grid::Inventory.center(&wide, Cell { col: 12, row: 5 });
+        assert_eq!((3440 - x, y), (59, 1097));
+    }
+
+    #[test]
+    fn select() {
+        let selection = Selection {
+            cells: None,
+            except_cols: Some(Span(11, 12)),
+        };
+        let cells = Grid::Inventory.select(&selection);
+        assert_eq!(cells.len(), 50);
+        assert_eq!(cells[1], Cell { col: 1, row: 2 });
+        assert_eq!(cells.last(), Some(&Cell { col: 10, row: 5 }));
+
+        let selection = Selection {
+            cells: Some((Cell { col: 23, row: 2 }, Cell { col: 30, row: 3 })),
+            except_cols: None,
+        };
+        assert_eq!(Grid::QuadStash.select(&selection).len(), 4);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("quad-stash".parse(), Ok(Grid::QuadStash));
+        assert_eq!("12,5".parse(), Ok(Cell { col: 12, row: 5 }));
+        assert_eq!("11-12".parse(), Ok(Span(11, 12)));
+        assert_eq!("3".parse(), Ok(Span(3, 3)));
+        assert!("0,1".parse::<Cell>().is_err());
+        assert!("12-11".parse::<Span>().is_err());
+        assert_eq!(
+            Cell::parse_range("1,1-6,5"),
+            Ok((Cell { col: 1, row: 1 }, Cell { col: 6, row: 5 }))
+        );
+        assert!(Cell::parse_range("6,5-1,1").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+// Text chunks written by poe-hacks are prefixed so they can be told apart from others.
+const PNG_TEXT_PREFIX: &str = "poe-hacks:";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub left: isize,
+    pub top: isize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The colors in a region of the screen.
+///
+/// The colors are stored in the same layout `GetDIBits` uses,
+/// so that the buffer can be filled directly from a capture.
+#[derive(Clone)]
+pub struct Screenshot {
+    pub region: Rect,
+    row_size: usize,
+    colors: Box<[u8]>,
+}
+
+pub struct ScreenshotIter<'s> {
+    screenshot: &'s Screenshot,
+    y_idx: usize,
+    x_cnt: usize,
+    i: usize,
+}
+
+impl Rect {
+    pub fn right(&self) -> isize {
+        self.left + self.width as isize
+    }
+
+    pub fn bottom(&self) -> isize {
+        self.top + self.height as isize
+    }
+
+    /// Return `true` if both rectangles share at least one pixel.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.left < other.right()
+            && other.left < self.right()
+            && self.top < other.bottom()
+            && other.top < self.bottom()
+    }
+
+    /// Return `true` if `other` fits entirely within this rectangle.
+    pub fn contains(&self, other: &Rect) -> bool {
+        self.left <= other.left
+            && self.top <= other.top
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// The smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let left = self.left.min(other.left);
+        let top = self.top.min(other.top);
+        Rect {
+            left,
+            top,
+            width: (self.right().max(other.right()) - left) as usize,
+            height: (self.bottom().max(other.bottom()) - top) as usize,
+        }
+    }
+}
+
+impl Screenshot {
+    pub(crate) fn new(region: Rect) -> Self {
+        // https://learn.microsoft.com/en-us/windows/win32/api/wingdi/nf-wingdi-getdibits
+        // The scan lines must be aligned on a DWORD except for RLE compressed bitmaps.
+        // Else "exit code: 0xc0000374, STATUS_HEAP_CORRUPTION" will occur,
+        // because `GetDIBits` will attempt to write outside the designated buffer.
+        let row_byte_count = region.width * 3; // RGB, 1 byte per
+        let row_size = row_byte_count.div_ceil(4) * 4; // DWORD, 4 bytes
+        let size = region.height * row_size;
+        Self {
+            region,
+            row_size,
+            colors: vec![0; size].into_boxed_slice(),
+        }
+    }
+
+    /// Create a screenshot of the given region from tightly-packed `(r, g, b)` rows.
+    ///
+    /// Panics if there are not exactly enough bytes to fill the region.
+    pub fn from_rgb(region: Rect, rgb: &[u8]) -> Self {
+        assert_eq!(
+            rgb.len(),
+            region.width * region.height * 3,
+            "rgb data does not match the region size"
+        );
+        let mut screenshot = Self::new(region);
+        let row_byte_count = screenshot.region.width * 3;
+        if row_byte_count != 0 {
+            for (y, row) in rgb.chunks_exact(row_byte_count).enumerate() {
+                let start = y * screenshot.row_size;
+                for (dst, src) in screenshot.colors[start..start + row_byte_count]
+                    .chunks_exact_mut(3)
+                    .zip(row.chunks_exact(3))
+                {
+                    dst.copy_from_slice(&[src[2], src[1], src[0]]);
+                }
+            }
+        }
+        screenshot
+    }
+
+    /// Copy the colors out as tightly-packed `(r, g, b)` rows.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.region.width * self.region.height * 3);
+        for (r, g, b) in self.colors() {
+            rgb.extend_from_slice(&[r, g, b]);
+        }
+        rgb
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.colors
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let i = y * self.row_size + x * 3;
+        (self.colors[i + 2], self.colors[i + 1], self.colors[i])
+    }
+
+    /// Copy the part of the screenshot covered by `region` (in screen coordinates).
+    ///
+    /// Panics if the region is not contained within the screenshot.
+    pub fn crop(&self, region: &Rect) -> Screenshot {
+        assert!(self.region.contains(region), "crop region out of bounds");
+        let mut cropped = Screenshot::new(region.clone());
+        let x_offset = (region.left - self.region.left) as usize * 3;
+        let y_offset = (region.top - self.region.top) as usize;
+        let row_byte_count = region.width * 3;
+        for y in 0..region.height {
+            let src = (y_offset + y) * self.row_size + x_offset;
+            let dst = y * cropped.row_size;
+            cropped.colors[dst..dst + row_byte_count]
+                .copy_from_slice(&self.colors[src..src + row_byte_count]);
+        }
+        cropped
+    }
+
+    pub fn colors(&self) -> ScreenshotIter<'_> {
+        ScreenshotIter {
+            screenshot: self,
+            y_idx: 0,
+            x_cnt: 0,
+            i: 0,
+        }
+    }
+
+    /// Save the screenshot as a PNG file, along with `poe-hacks:`-prefixed text chunks.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, text: &[(String, String)]) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder =
+            png::Encoder::new(writer, self.region.width as u32, self.region.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (key, value) in text {
+            encoder
+                .add_text_chunk(format!("{}{}", PNG_TEXT_PREFIX, key), value.clone())
+                .map_err(io::Error::other)?;
+        }
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer
+            .write_image_data(&self.to_rgb())
+            .map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+
+    /// Load a screenshot from a PNG file, along with its `poe-hacks:`-prefixed text chunks.
+    ///
+    /// The screenshot's region will start at the origin.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<(Self, Vec<(String, String)>)> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+        let channels = match frame.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "indexed png was not expanded",
+                ))
+            }
+        };
+
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for row in buffer[..frame.buffer_size()].chunks_exact(frame.line_size) {
+            for pixel in row[..width * channels].chunks_exact(channels) {
+                match channels {
+                    1 | 2 => rgb.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]),
+                    _ => rgb.extend_from_slice(&pixel[..3]),
+                }
+            }
+        }
+
+        let text = reader
+            .info()
+            .uncompressed_latin1_text
+            .iter()
+            .filter_map(|chunk| {
+                chunk
+                    .keyword
+                    .strip_prefix(PNG_TEXT_PREFIX)
+                    .map(|key| (key.to_string(), chunk.text.clone()))
+            })
+            .collect();
+
+        let region = Rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        };
+        Ok((Self::from_rgb(region, &rgb), text))
+    }
+}
+
+impl<'s> Iterator for ScreenshotIter<'s> {
+    type Item = (u8, u8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let i = self.i;
+        if i == self.screenshot.colors.len() || self.screenshot.region.width == 0 {
+            return None;
+        }
+
+        self.x_cnt += 1;
+        if self.x_cnt == self.screenshot.region.width {
+            self.x_cnt = 0;
+            self.y_idx += self.screenshot.row_size;
+            self.i = self.y_idx;
+        } else {
+            self.i += 3;
+        }
+
+        Some((
+            self.screenshot.colors[i + 2],
+            self.screenshot.colors[i + 1],
+            self.screenshot.colors[i],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(width: usize, height: usize) -> Rect {
+        Rect {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn rgb_roundtrip() {
+        // 5 pixels wide so that rows need padding.
+        let rgb = (0..5 * 2 * 3).map(|i| i as u8).collect::<Vec<_>>();
+        let screenshot = Screenshot::from_rgb(region(5, 2), &rgb);
+        assert_eq!(screenshot.to_rgb(), rgb);
+        assert_eq!(screenshot.color(1, 1), (18, 19, 20));
+    }
+
+    #[test]
+    fn crop() {
+        let rgb = (0..4 * 4 * 3).map(|i| i as u8).collect::<Vec<_>>();
+        let screenshot = Screenshot::from_rgb(region(4, 4), &rgb);
+        let cropped = screenshot.crop(&Rect {
+            left: 1,
+            top: 2,
+            width: 2,
+            height: 1,
+        });
+        assert_eq!(cropped.to_rgb(), vec![27, 28, 29, 30, 31, 32]);
+    }
+
+    #[test]
+    fn png_roundtrip() {
+        let rgb = (0..3 * 3 * 3).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let screenshot = Screenshot::from_rgb(region(3, 3), &rgb);
+        let path = std::env::temp_dir().join(format!("poe-hacks-{}.png", std::process::id()));
+        screenshot
+            .save_png(&path, &[("chat".to_string(), "opened".to_string())])
+            .unwrap();
+        let (loaded, text) = Screenshot::load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.to_rgb(), rgb);
+        assert_eq!(text, vec![("chat".to_string(), "opened".to_string())]);
+    }
+}
@@ -0,0 +1,482 @@
+//! Edge detection for key (and mouse button) events, as recorded by the input hooks.
+//!
+//! Polling whether a key is down misses quick taps that happen between checks,
+//! so instead every event is recorded as it happens and replayed on every check.
+use crate::types::Modifiers;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Two presses of the same key closer than this count as a double-tap.
+pub const DOUBLE_TAP_WINDOW: Duration = Duration::from_millis(300);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEventKind {
+    Down,
+    Up,
+    /// The key is still down and the system is auto-repeating it.
+    Repeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub vk: u16,
+    pub kind: KeyEventKind,
+    pub time: Instant,
+}
+
+/// Anything that can produce the key events which happened since it was last polled.
+pub trait KeyEventSource {
+    fn poll(&mut self) -> Vec<KeyEvent>;
+}
+
+/// What needs to happen to a key for a rule to trigger.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyTrigger {
+    /// The key is currently down.
+    Down,
+    /// The key went down since the last check.
+    Pressed,
+    /// The key went up since the last check.
+    Released,
+    /// The key has been down for at least this long.
+    Held(Duration),
+    /// The key was pressed twice in quick succession since the last check.
+    DoubleTap,
+}
+
+#[derive(Clone, Debug, Default)]
+struct KeyTrack {
+    down_since: Option<Instant>,
+    /// The modifiers which were held when the key was last pressed.
+    press_modifiers: Modifiers,
+    last_press: Option<Instant>,
+    pressed: bool,
+    released: bool,
+    double_tapped: bool,
+}
+
+/// Remembers the state of every key seen so far, and what happened to them on the last update.
+#[derive(Clone, Debug)]
+pub struct KeyTracker {
+    keys: HashMap<u16, KeyTrack>,
+    /// The modifier keys held down, each side on its own, since releasing one side's
+    /// doesn't release the modifier while the other side's is still held.
+    modifier_keys: HashSet<u16>,
+    now: Instant,
+}
+
+impl KeyTracker {
+    pub fn new() -> Self {
+        Self {
+            keys: HashMap::new(),
+            modifier_keys: HashSet::new(),
+            now: Instant::now(),
+        }
+    }
+
+    /// The modifiers currently held, on either side.
+    fn modifiers(&self) -> Modifiers {
+        self.modifier_keys
+            .iter()
+            .filter_map(|vk| Modifiers::of_vk(*vk))
+            .fold(Modifiers::NONE, |held, modifier| held.with(modifier))
+    }
+
+    /// Forget what happened on the previous update and apply the new events.
+    pub fn update(&mut self, source: &mut dyn KeyEventSource, now: Instant) {
+        self.now = now;
+        for track in self.keys.values_mut() {
+            track.pressed = false;
+            track.released = false;
+            track.double_tapped = false;
+        }
+
+        for event in source.poll() {
+            let held = self.modifiers();
+            let track = self.keys.entry(event.vk).or_default();
+            match event.kind {
+                KeyEventKind::Down => {
+                    track.press_modifiers = held;
+                    if Modifiers::of_vk(event.vk).is_some() {
+                        self.modifier_keys.insert(event.vk);
+                    }
+                    // Missing the up event (say, because it happened on another window)
+                    // shouldn't prevent the next press from being detected.
+                    track.down_since = Some(event.time);
+                    track.pressed = true;
+                    match track.last_press {
+                        Some(last) if event.time.duration_since(last) <= DOUBLE_TAP_WINDOW => {
+                            track.double_tapped = true;
+                            // A third press shouldn't count as another double-tap.
+                            track.last_press = None;
+                        }
+                        _ => track.last_press = Some(event.time),
+                    }
+                }
+                KeyEventKind::Up => {
+                    track.down_since = None;
+                    track.released = true;
+                    match Modifiers::of_vk(event.vk) {
+                        // The key without a side (say, shift rather than left shift) could be
+                        // standing for either side, so both are let go.
+                        Some(modifier) if Modifiers::is_sideless(event.vk) => self
+                            .modifier_keys
+                            .retain(|vk| Modifiers::of_vk(*vk) != Some(modifier)),
+                        Some(_) => {
+                            self.modifier_keys.remove(&event.vk);
+                        }
+                        None => {}
+                    }
+                }
+                KeyEventKind::Repeat => {
+                    track.down_since.get_or_insert(event.time);
+                }
+            }
+        }
+    }
+
+    /// Check if the key did what the trigger needs as of the last update.
+    pub fn check(&self, vk: u16, trigger: KeyTrigger) -> bool {
+        self.check_chord(vk, Modifiers::NONE, trigger)
+    }
+
+    /// Like `check`, but exactly the given modifiers must be held too (unless there are none).
+    ///
+    /// For triggers that happen when the key is pressed or released, it's the modifiers held
+    /// at the moment the key was pressed that count.
+    ///
+    /// A modifier without a side (say, ctrl rather than left ctrl) matches either side's key,
+    /// since that's all the hooks report.
+    pub fn check_chord(&self, vk: u16, modifiers: Modifiers, trigger: KeyTrigger) -> bool {
+        if Modifiers::is_sideless(vk) {
+            let modifier = Modifiers::of_vk(vk);
+            self.keys
+                .iter()
+                .filter(|(key, _)| Modifiers::of_vk(**key) == modifier)
+                .any(|(_, track)| self.check_track(track, modifiers, trigger))
+        } else {
+            self.keys
+                .get(&vk)
+                .is_some_and(|track| self.check_track(track, modifiers, trigger))
+        }
+    }
+
+    fn check_track(&self, track: &KeyTrack, modifiers: Modifiers, trigger: KeyTrigger) -> bool {
+        let (triggered, held) = match trigger {
+            KeyTrigger::Down => (track.down_since.is_some(), self.modifiers()),
+            KeyTrigger::Pressed => (track.pressed, track.press_modifiers),
+            KeyTrigger::Released => (track.released, track.press_modifiers),
+            KeyTrigger::Held(duration) => (
+                track
+                    .down_since
+                    .is_some_and(|since| self.now.saturating_duration_since(since) >= duration),
+                self.modifiers(),
+            ),
+            KeyTrigger::DoubleTap => (track.double_tapped, track.press_modifiers),
+        };
+        triggered && (modifiers.is_empty() || modifiers == held)
+    }
+}
+
+impl Default for KeyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for KeyTrigger {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        Ok(match word {
+            "down" => Self::Down,
+            "pressed" => Self::Pressed,
+            "released" => Self::Released,
+            "double-tap" => Self::DoubleTap,
+            _ => return Err("key trigger can only be down, pressed, released, held or double-tap"),
+        })
+    }
+}
+
+impl fmt::Display for KeyTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Down => f.write_str("down"),
+            Self::Pressed => f.write_str("pressed"),
+            Self::Released => f.write_str("released"),
+            Self::Held(duration) => write!(f, "held {}ms", duration.as_millis()),
+            Self::DoubleTap => f.write_str("double-tap"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: u16 = 0x51;
+
+    struct FakeSource(Vec<KeyEvent>);
+
+    impl KeyEventSource for FakeSource {
+        fn poll(&mut self) -> Vec<KeyEvent> {
+            std::mem::take(&mut self.0)
+        }
+    }
+
+    fn event(kind: KeyEventKind, time: Instant) -> KeyEvent {
+        KeyEvent {
+            vk: KEY,
+            kind,
+            time,
+        }
+    }
+
+    fn update(tracker: &mut KeyTracker, events: Vec<KeyEvent>, now: Instant) {
+        tracker.update(&mut FakeSource(events), now);
+    }
+
+    #[test]
+    fn quick_tap_between_updates() {
+        let start = Instant::now();
+        let mut tracker = KeyTracker::new();
+        update(
+            &mut tracker,
+            vec![
+                event(KeyEventKind::Down, start),
+                event(KeyEventKind::Up, start + Duration::from_millis(2)),
+            ],
+            start + Duration::from_millis(10),
+        );
+        assert!(tracker.check(KEY, KeyTrigger::Pressed));
+        assert!(tracker.check(KEY, KeyTrigger::Released));
+        assert!(!tracker.check(KEY, KeyTrigger::Down));
+
+        update(&mut tracker, vec![], start + Duration::from_millis(20));
+        assert!(!tracker.check(KEY, KeyTrigger::Pressed));
+        assert!(!tracker.check(KEY, KeyTrigger::Released));
+    }
+
+    #[test]
+    fn held() {
+        let start = Instant::now();
+        let held = KeyTrigger::Held(Duration::from_millis(300));
+        let mut tracker = KeyTracker::new();
+        update(
+            &mut tracker,
+            vec![event(KeyEventKind::Down, start)],
+            start + Duration::from_millis(10),
+        );
+        assert!(tracker.check(KEY, KeyTrigger::Down));
+        assert!(!tracker.check(KEY, held));
+
+        update(
+            &mut tracker,
+            vec![event(
+                KeyEventKind::Repeat,
+                start + Duration::from_millis(250),
+            )],
+            start + Duration::from_millis(310),
+        );
+        assert!(!tracker.check(KEY, KeyTrigger::Pressed));
+        assert!(tracker.check(KEY, held));
+
+        update(
+            &mut tracker,
+            vec![event(KeyEventKind::Up, start + Duration::from_millis(320))],
+            start + Duration::from_millis(330),
+        );
+        assert!(!tracker.check(KEY, held));
+    }
+
+    #[test]
+    fn double_tap() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut tracker = KeyTracker::new();
+        update(
+            &mut tracker,
+            vec![
+                event(KeyEventKind::Down, start),
+                event(KeyEventKind::Up, start + ms(50)),
+            ],
+            start + ms(60),
+        );
+        assert!(!tracker.check(KEY, KeyTrigger::DoubleTap));
+
+        update(
+            &mut tracker,
+            vec![
+                event(KeyEventKind::Down, start + ms(150)),
+                event(KeyEventKind::Up, start + ms(200)),
+            ],
+            start + ms(210),
+        );
+        assert!(tracker.check(KEY, KeyTrigger::DoubleTap));
+
+        // A third tap starts over.
+        update(
+            &mut tracker,
+            vec![event(KeyEventKind::Down, start + ms(250))],
+            start + ms(260),
+        );
+        assert!(!tracker.check(KEY, KeyTrigger::DoubleTap));
+    }
+
+    #[test]
+    fn chords_match_exactly() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let key = |vk, kind, millis| KeyEvent {
+            vk,
+            kind,
+            time: start + ms(millis),
+        };
+        let ctrl = Modifiers::CTRL;
+        let ctrl_shift = Modifiers::CTRL.with(Modifiers::SHIFT);
+        let mut tracker = KeyTracker::new();
+
+        // A quick ctrl+shift+D, all within the same update.
+        update(
+            &mut tracker,
+            vec![
+                key(0xA2, KeyEventKind::Down, 0),
+                key(0xA0, KeyEventKind::Down, 5),
+                key(KEY, KeyEventKind::Down, 10),
+                key(KEY, KeyEventKind::Up, 15),
+                key(0xA0, KeyEventKind::Up, 20),
+                key(0xA2, KeyEventKind::Up, 25),
+            ],
+            start + ms(30),
+        );
+        assert!(tracker.check_chord(KEY, ctrl_shift, KeyTrigger::Pressed));
+        assert!(!tracker.check_chord(KEY, ctrl, KeyTrigger::Pressed));
+        // Without modifiers, any will do.
+        assert!(tracker.check_chord(KEY, Modifiers::NONE, KeyTrigger::Pressed));
+
+        update(
+            &mut tracker,
+            vec![
+                key(0x11, KeyEventKind::Down, 100),
+                key(KEY, KeyEventKind::Down, 110),
+            ],
+            start + ms(120),
+        );
+        assert!(tracker.check_chord(KEY, ctrl, KeyTrigger::Down));
+        assert!(!tracker.check_chord(KEY, ctrl_shift, KeyTrigger::Down));
+    }
+
+    #[test]
+    fn modifier_sides() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let key = |vk, kind, millis| KeyEvent {
+            vk,
+            kind,
+            time: start + ms(millis),
+        };
+        let mut tracker = KeyTracker::new();
+
+        // Right ctrl is still held after letting go of left ctrl.
+        update(
+            &mut tracker,
+            vec![
+                key(0xA2, KeyEventKind::Down, 0),
+                key(0xA3, KeyEventKind::Down, 5),
+                key(0xA2, KeyEventKind::Up, 10),
+                key(KEY, KeyEventKind::Down, 15),
+            ],
+            start + ms(20),
+        );
+        assert!(tracker.check_chord(KEY, Modifiers::CTRL, KeyTrigger::Pressed));
+        assert!(tracker.check_chord(KEY, Modifiers::CTRL, KeyTrigger::Down));
+
+        update(
+            &mut tracker,
+            vec![
+                key(KEY, KeyEventKind::Up, 30),
+                key(0xA3, KeyEventKind::Up, 35),
+                key(KEY, KeyEventKind::Down, 40),
+            ],
+            start + ms(45),
+        );
+        assert!(!tracker.check_chord(KEY, Modifiers::CTRL, KeyTrigger::Down));
+
+        // Letting go of ctrl without a side lets go of both.
+        update(
+            &mut tracker,
+            vec![
+                key(0xA2, KeyEventKind::Down, 50),
+                key(0xA3, KeyEventKind::Down, 55),
+                key(0x11, KeyEventKind::Up, 60),
+            ],
+            start + ms(65),
+        );
+        assert!(!tracker.check_chord(KEY, Modifiers::CTRL, KeyTrigger::Down));
+    }
+
+    #[test]
+    fn sideless_modifiers() {
+        const CTRL: u16 = 0x11;
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let key = |vk, kind, millis| KeyEvent {
+            vk,
+            kind,
+            time: start + ms(millis),
+        };
+        let held = KeyTrigger::Held(ms(300));
+        let mut tracker = KeyTracker::new();
+
+        // The hooks only ever report left or right ctrl.
+        update(
+            &mut tracker,
+            vec![key(0xA2, KeyEventKind::Down, 0)],
+            start + ms(10),
+        );
+        assert!(tracker.check(CTRL, KeyTrigger::Pressed));
+        assert!(tracker.check(CTRL, KeyTrigger::Down));
+        assert!(!tracker.check(CTRL, held));
+
+        update(&mut tracker, vec![], start + ms(310));
+        assert!(tracker.check(CTRL, held));
+        assert!(!tracker.check(CTRL, KeyTrigger::Pressed));
+
+        update(
+            &mut tracker,
+            vec![
+                key(0xA2, KeyEventKind::Up, 320),
+                key(0xA2, KeyEventKind::Down, 400),
+                key(0xA2, KeyEventKind::Up, 450),
+                key(0xA2, KeyEventKind::Down, 500),
+                key(0xA2, KeyEventKind::Up, 550),
+            ],
+            start + ms(560),
+        );
+        assert!(tracker.check(CTRL, KeyTrigger::Released));
+        assert!(tracker.check(CTRL, KeyTrigger::DoubleTap));
+        assert!(!tracker.check(CTRL, KeyTrigger::Down));
+        // The other modifiers aren't mixed up with it.
+        assert!(!tracker.check(0x10, KeyTrigger::Released));
+    }
+
+    #[test]
+    fn slow_taps_are_not_double() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut tracker = KeyTracker::new();
+        update(
+            &mut tracker,
+            vec![
+                event(KeyEventKind::Down, start),
+                event(KeyEventKind::Up, start + ms(50)),
+                event(KeyEventKind::Down, start + ms(500)),
+            ],
+            start + ms(510),
+        );
+        assert!(tracker.check(KEY, KeyTrigger::Pressed));
+        assert!(!tracker.check(KEY, KeyTrigger::DoubleTap));
+    }
+}
@@ -0,0 +1,260 @@
+//! Parses the text the game copies to the clipboard when pressing ctrl+c over an item.
+//!
+//! The text is made of sections separated by dashes. The first one has the rarity and name,
+//! and the rest are either properties (`Item Level: 84`), mods, or flavour text.
+use std::fmt;
+use std::str::FromStr;
+
+const SECTION_SEPARATOR: &str = "--------";
+
+/// Sections made of a single line like these describe the item, they're not mods.
+const ITEM_FLAGS: &[&str] = &["corrupted", "unidentified", "mirrored", "split"];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Item {
+    pub rarity: String,
+    /// The name and base type (only the base type for normal and magic items).
+    pub name: Vec<String>,
+    pub mods: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Number {
+    Any,
+    AtLeast(f64),
+    Exactly(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Number(Number),
+}
+
+/// A mod to look for, such as `+# to maximum life` or `25+% increased movement speed`.
+///
+/// `#` stands for any number, and a number followed by `+` for that number or more.
+/// Letter case and annotations such as `(crafted)` are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModPattern {
+    source: String,
+    tokens: Vec<Token>,
+}
+
+fn is_property(line: &str) -> bool {
+    line.ends_with(':') || line.contains(": ")
+}
+
+/// Split a line into runs of text and numbers, calling `number` to make sense of the latter.
+fn tokenize(
+    line: &str,
+    mut number: impl FnMut(&str, &mut std::str::Chars) -> Number,
+) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = line.chars();
+    let mut rest = line;
+    while let Some(c) = chars.clone().next() {
+        if c == '#' || c.is_ascii_digit() {
+            if !text.is_empty() {
+                tokens.push(Token::Text(std::mem::take(&mut text)));
+            }
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '#'))
+                .unwrap_or(rest.len());
+            chars = rest[len..].chars();
+            tokens.push(Token::Number(number(&rest[..len], &mut chars)));
+        } else {
+            text.extend(c.to_lowercase());
+            chars.next();
+        }
+        rest = chars.as_str();
+    }
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    tokens
+}
+
+impl Item {
+    /// Parse the text copied from an item, if it is one.
+    pub fn parse(text: &str) -> Option<Item> {
+        let mut sections = text.split(SECTION_SEPARATOR).map(|section| {
+            section
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+        });
+
+        let header = sections.next()?;
+        let rarity = header
+            .iter()
+            .find_map(|line| line.strip_prefix("Rarity: "))?
+            .to_string();
+        let name = header
+            .iter()
+            .filter(|line| !is_property(line))
+            .map(|line| line.to_string())
+            .collect();
+
+        let mods = sections
+            .filter(|section| !section.iter().any(|line| is_property(line)))
+            .filter(|section| {
+                !matches!(section.as_slice(), [line]
+                    if ITEM_FLAGS.contains(&line.to_lowercase().as_str()) || line.ends_with(" Item"))
+            })
+            .flatten()
+            .map(|line| line.to_string())
+            .collect();
+
+        Some(Item { rarity, name, mods })
+    }
+
+    pub fn has_mod(&self, pattern: &ModPattern) -> bool {
+        self.mods.iter().any(|line| pattern.matches(line))
+    }
+}
+
+impl Number {
+    fn accepts(&self, n: f64) -> bool {
+        match *self {
+            Self::Any => true,
+            Self::AtLeast(min) => n >= min,
+            Self::Exactly(value) => n == value,
+        }
+    }
+}
+
+impl ModPattern {
+    /// Whether the line of an item's mod matches the pattern in its entirety.
+    pub fn matches(&self, line: &str) -> bool {
+        // Strip annotations like `(implicit)` or `(crafted)`.
+        let line = match line
+            .trim_end()
+            .strip_suffix(')')
+            .and_then(|l| l.rsplit_once(" ("))
+        {
+            Some((line, annotation)) if annotation.chars().all(char::is_alphabetic) => line,
+            _ => line.trim_end(),
+        };
+        let tokens = tokenize(line, |number, _| {
+            number.parse().map_or(Number::Any, Number::Exactly)
+        });
+
+        tokens.len() == self.tokens.len()
+            && self
+                .tokens
+                .iter()
+                .zip(tokens.iter())
+                .all(|(pattern, token)| match (pattern, token) {
+                    (Token::Text(a), Token::Text(b)) => a == b,
+                    (Token::Number(pattern), Token::Number(Number::Exactly(n))) => {
+                        pattern.accepts(*n)
+                    }
+                    _ => false,
+                })
+    }
+}
+
+impl FromStr for ModPattern {
+    type Err = &'static str;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let source = source.trim();
+        if source.is_empty() {
+            return Err("mod pattern is empty");
+        }
+        let mut invalid = false;
+        let tokens = tokenize(source, |number, rest| {
+            let at_least = rest.as_str().starts_with('+');
+            if at_least {
+                rest.next();
+            }
+            match (number, number.parse()) {
+                ("#", _) => Number::Any,
+                (_, Ok(n)) if at_least => Number::AtLeast(n),
+                (_, Ok(n)) => Number::Exactly(n),
+                (_, Err(_)) => {
+                    invalid = true;
+                    Number::Any
+                }
+            }
+        });
+        if invalid {
+            return Err("mod pattern has an invalid number, use # for any number");
+        }
+        Ok(Self {
+            source: source.to_string(),
+            tokens,
+        })
+    }
+}
+
+impl fmt::Display for ModPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOTS: &str = "Item Class: Boots
+Rarity: Magic
+Fleet Slink Boots of the Lightning
+--------
+Evasion Rating: 246
+--------
+Requirements:
+Level: 69
+Dex: 120
+--------
+Sockets: G-G-R
+--------
+Item Level: 84
+--------
++12% to Cold Resistance (implicit)
+--------
+25% increased Movement Speed
++38% to Lightning Resistance (crafted)
+--------
+Corrupted
+";
+
+    fn pattern(source: &str) -> ModPattern {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_item() {
+        let item = Item::parse(BOOTS).unwrap();
+        assert_eq!(item.rarity, "Magic");
+        assert_eq!(item.name, vec!["Fleet Slink Boots of the Lightning"]);
+        assert_eq!(
+            item.mods,
+            vec![
+                "+12% to Cold Resistance (implicit)",
+                "25% increased Movement Speed",
+                "+38% to Lightning Resistance (crafted)",
+            ]
+        );
+        assert_eq!(Item::parse("not an item"), None);
+    }
+
+    #[test]
+    fn match_mods() {
+        let item = Item::parse(BOOTS).unwrap();
+        assert!(item.has_mod(&pattern("#% increased movement speed")));
+        assert!(item.has_mod(&pattern("25+% increased movement speed")));
+        assert!(!item.has_mod(&pattern("30+% increased movement speed")));
+        assert!(item.has_mod(&pattern("+38% to lightning resistance")));
+        assert!(item.has_mod(&pattern("+#% to cold resistance")));
+        assert!(!item.has_mod(&pattern("increased movement speed")));
+        assert!(!item.has_mod(&pattern("+#% to fire resistance")));
+        assert!(pattern("Adds # to # Cold Damage").matches("Adds 10 to 21 Cold Damage"));
+        assert!("".parse::<ModPattern>().is_err());
+        assert!("1.2.3% more".parse::<ModPattern>().is_err());
+    }
+}
@@ -0,0 +1,376 @@
+//! A journal of what happened while running, to find out after the fact why a rule (didn't) run.
+//!
+//! Every line of the journal file is a JSON object with the time and kind of event, such as
+//! `{"time":"2024-01-01T12:00:00.000+01:00","event":"trigger","rule":"on life 50% do key 1",...}`.
+//! Once the file grows too large, it's renamed (to `.1`, `.2` and so on) and a new one is started.
+//!
+//! The journal also decides what is shown on the console, depending on the verbosity.
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const DEFAULT_MAX_SIZE: u64 = 8 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 3;
+
+/// How much is shown on the console. The journal file always has everything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only warnings.
+    Quiet,
+    /// Warnings and the rules that run, except silent rules.
+    #[default]
+    Normal,
+    /// Everything, including silent rules.
+    Verbose,
+}
+
+/// A JSON value, only as much as is needed to write the journal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Object(Vec<(String, Json)>),
+}
+
+type Forward = Box<dyn Fn(&str)>;
+
+pub struct Journal {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+    /// How many of the old files to keep around after rotating.
+    keep: usize,
+    verbosity: Verbosity,
+    /// Where else every event is sent, such as to control clients.
+    forward: Option<Forward>,
+}
+
+impl Json {
+    pub fn object() -> Self {
+        Self::Object(Vec::new())
+    }
+
+    /// Add a field to the object (this does nothing to other values).
+    pub fn with<V: Into<Json>>(mut self, key: &str, value: V) -> Self {
+        if let Self::Object(fields) = &mut self {
+            fields.push((key.to_string(), value.into()));
+        }
+        self
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(value: i32) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(value: u64) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(value) if !value.is_finite() => f.write_str("null"),
+            Self::Number(value) if value.fract() == 0.0 && value.abs() < 1e15 => {
+                write!(f, "{}", *value as i64)
+            }
+            Self::Number(value) => write!(f, "{}", value),
+            Self::String(value) => write_string(f, value),
+            Self::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+/// The path of an old journal file, such as `poe.key.journal.2`.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{}", n));
+    path.into()
+}
+
+impl Journal {
+    /// A journal that only shows events on the console.
+    pub fn console(verbosity: Verbosity) -> Self {
+        Self {
+            path: None,
+            file: None,
+            size: 0,
+            max_size: DEFAULT_MAX_SIZE,
+            keep: DEFAULT_KEEP,
+            verbosity,
+            forward: None,
+        }
+    }
+
+    /// Append to the journal file, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P, verbosity: Verbosity) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            path: Some(path),
+            file: Some(file),
+            ..Self::console(verbosity)
+        })
+    }
+
+    /// Change how large the file can grow before rotating, and how many old files to keep.
+    pub fn set_rotation(&mut self, max_size: u64, keep: usize) {
+        self.max_size = max_size;
+        self.keep = keep;
+    }
+
+    /// Send every event recorded from now on elsewhere too, even if there's no journal file.
+    pub fn forward<F: Fn(&str) + 'static>(&mut self, forward: F) {
+        self.forward = Some(Box::new(forward));
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Whether messages of the given verbosity are shown on the console.
+    pub fn shows(&self, verbosity: Verbosity) -> bool {
+        verbosity <= self.verbosity
+    }
+
+    /// Show a message on the console, if the verbosity allows.
+    pub fn note(&self, verbosity: Verbosity, message: fmt::Arguments) {
+        if self.shows(verbosity) {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Show a warning on the console, and record it.
+    pub fn warn(&mut self, message: fmt::Arguments) {
+        let message = message.to_string();
+        eprintln!("warning: {}", message);
+        self.record("warning", Json::object().with("message", message));
+    }
+
+    /// Record an event in the journal file, with the fields of the object.
+    pub fn record(&mut self, event: &str, fields: Json) {
+        if self.file.is_none() && self.forward.is_none() {
+            return;
+        }
+        let mut line = Json::object()
+            .with(
+                "time",
+                chrono::Local::now()
+                    .format("%Y-%m-%dT%H:%M:%S%.3f%:z")
+                    .to_string(),
+            )
+            .with("event", event);
+        if let (Json::Object(line), Json::Object(fields)) = (&mut line, fields) {
+            line.extend(fields);
+        }
+        let line = line.to_string();
+        if let Some(forward) = self.forward.as_ref() {
+            forward(&line);
+        }
+        if self.file.is_none() {
+            return;
+        }
+        let line = line + "\n";
+
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            if let Err(e) = self.rotate() {
+                eprintln!("warning: failed to rotate journal, it won't be written anymore: {e}");
+                self.file = None;
+                return;
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            match file.write_all(line.as_bytes()) {
+                Ok(()) => self.size += line.len() as u64,
+                Err(e) => {
+                    eprintln!("warning: failed to write journal, it won't be written anymore: {e}");
+                    self.file = None;
+                }
+            }
+        }
+    }
+
+    /// Move the current file out of the way (along with the older ones) and start a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.file = None;
+        if self.keep > 0 {
+            for n in (1..self.keep).rev() {
+                let old = rotated(path, n);
+                if old.exists() {
+                    fs::rename(old, rotated(path, n + 1))?;
+                }
+            }
+            fs::rename(path, rotated(path, 1))?;
+        }
+        self.file = Some(File::create(path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        match word {
+            "quiet" => Ok(Self::Quiet),
+            "normal" => Ok(Self::Normal),
+            "verbose" => Ok(Self::Verbose),
+            _ => Err("verbosity can only be quiet, normal or verbose"),
+        }
+    }
+}
+
+impl fmt::Display for Verbosity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Quiet => "quiet",
+            Self::Normal => "normal",
+            Self::Verbose => "verbose",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let json = Json::object()
+            .with("rule", "on key F1 do type \"hi\"\\o/")
+            .with("ok", true)
+            .with("hp", 1200)
+            .with("latency", 1.5)
+            .with("town", None::<bool>)
+            .with("area", Json::object().with("town", Some(false)));
+        assert_eq!(
+            json.to_string(),
+            r#"{"rule":"on key F1 do type \"hi\"\\o/","ok":true,"hp":1200,"latency":1.5,"town":null,"area":{"town":false}}"#
+        );
+        assert_eq!(Json::from("a\nb\u{1}").to_string(), r#""a\nb\u0001""#);
+    }
+
+    #[test]
+    fn rotate() {
+        let path = std::env::temp_dir().join(format!("journal-{}.jsonl", std::process::id()));
+        let mut journal = Journal::open(&path, Verbosity::Quiet).unwrap();
+        journal.set_rotation(200, 2);
+        for i in 0..10 {
+            journal.record("trigger", Json::object().with("n", i));
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        assert!(current.lines().count() > 0);
+        assert!(current
+            .lines()
+            .all(|line| line.starts_with(r#"{"time":""#) && line.contains(r#""event":"trigger""#)));
+        assert!(current.ends_with("\"n\":9}\n"));
+        assert!(fs::metadata(&path).unwrap().len() <= 200);
+        assert!(rotated(&path, 1).exists());
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+
+        for path in [path.clone(), rotated(&path, 1), rotated(&path, 2)].iter() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn forward() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let forwarded = Rc::new(RefCell::new(Vec::new()));
+        let mut journal = Journal::console(Verbosity::Quiet);
+        journal.record("lost", Json::object());
+        let sink = Rc::clone(&forwarded);
+        journal.forward(move |line| sink.borrow_mut().push(line.to_string()));
+        journal.record("command", Json::object().with("command", "reload"));
+        let forwarded = forwarded.borrow();
+        assert_eq!(forwarded.len(), 1);
+        assert!(forwarded[0].ends_with(r#""event":"command","command":"reload"}"#));
+    }
+
+    #[test]
+    fn verbosity() {
+        let journal = Journal::console("quiet".parse().unwrap());
+        assert!(!journal.shows(Verbosity::Normal));
+        assert!(Journal::console(Verbosity::default()).shows(Verbosity::Normal));
+        assert!(!Journal::console(Verbosity::Normal).shows(Verbosity::Verbose));
+        assert!("loud".parse::<Verbosity>().is_err());
+    }
+}
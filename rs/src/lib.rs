@@ -1,5 +1,18 @@
-#![cfg(windows)]
-
+#[cfg(windows)]
 pub mod checker;
+pub mod cli;
+pub mod client_log;
+pub mod control;
+pub mod dashboard;
+pub mod detect;
+pub mod dispatch;
+pub mod grid;
+pub mod image;
+pub mod input;
+pub mod item;
+pub mod journal;
+pub mod snapshot;
+pub mod timing;
 pub mod types;
+#[cfg(windows)]
 pub mod win;
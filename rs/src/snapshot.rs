@@ -0,0 +1,169 @@
+//! A small text file to remember the runtime state across restarts.
+//!
+//! Every line is a key followed by its value, such as `town true`. The same key can appear
+//! several times. The first two lines say when the snapshot was saved and for which game process,
+//! so that stale state can be told apart.
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    saved: SystemTime,
+    pid: u32,
+    entries: Vec<(String, String)>,
+}
+
+/// Milliseconds since the Unix epoch, which is how points in time are stored.
+pub fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_millis() as u64)
+}
+
+pub fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+impl Snapshot {
+    /// Start a new snapshot of the state for the game process with the given ID.
+    pub fn new(pid: u32) -> Self {
+        Self {
+            saved: SystemTime::now(),
+            pid,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push<V: fmt::Display>(&mut self, key: &str, value: V) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    /// The first value for the key.
+    pub fn get<'s>(&'s self, key: &'s str) -> Option<&'s str> {
+        self.get_all(key).next()
+    }
+
+    /// Every value for the key, in the order they were pushed.
+    pub fn get_all<'s>(&'s self, key: &'s str) -> impl Iterator<Item = &'s str> + 's {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// How long ago the snapshot was saved.
+    pub fn age(&self) -> Duration {
+        self.saved.elapsed().unwrap_or_default()
+    }
+
+    /// The ID of the game process the snapshot was saved for.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Save the snapshot, replacing the file only once it's been fully written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut text = format!("saved {}\npid {}\n", to_millis(self.saved), self.pid);
+        for (key, value) in self.entries.iter() {
+            text.push_str(&format!("{} {}\n", key, value));
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text
+            .lines()
+            .map(|line| line.split_once(' ').unwrap_or((line, "")));
+
+        let invalid = |what| Error::new(ErrorKind::InvalidData, what);
+        let saved = match lines.next() {
+            Some(("saved", millis)) => from_millis(
+                millis
+                    .parse()
+                    .map_err(|_| invalid("invalid time in snapshot"))?,
+            ),
+            _ => {
+                return Err(invalid(
+                    "snapshot does not start with the time it was saved",
+                ))
+            }
+        };
+        let pid = match lines.next() {
+            Some(("pid", pid)) => pid
+                .parse()
+                .map_err(|_| invalid("invalid process id in snapshot"))?,
+            _ => return Err(invalid("snapshot has no process id")),
+        };
+        Ok(Self {
+            saved,
+            pid,
+            entries: lines
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    /// Load the snapshot, unless it doesn't exist, is older than `max_age`, or was saved for
+    /// another game process (since none of it applies to a game started anew).
+    pub fn load_fresh<P: AsRef<Path>>(path: P, max_age: Duration, pid: u32) -> Option<Self> {
+        match Self::load(path) {
+            Ok(snapshot) if snapshot.age() > max_age => {
+                eprintln!("note: ignoring saved state because it's too old");
+                None
+            }
+            Ok(snapshot) if snapshot.pid() != pid => {
+                eprintln!("note: ignoring saved state because the game was restarted");
+                None
+            }
+            Ok(snapshot) => Some(snapshot),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                eprintln!("warning: could not load saved state: {e}");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("snapshot-{}.state", std::process::id()));
+        let mut snapshot = Snapshot::new(1234);
+        snapshot.push("town", true);
+        snapshot.push("disabled", "flasks");
+        snapshot.push("disabled", "trade");
+        snapshot.push("toggled", "on key F1 toggle click left every 150ms");
+        snapshot.save(&path).unwrap();
+
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded.pid(), 1234);
+        assert_eq!(loaded.get("town"), Some("true"));
+        assert_eq!(
+            loaded.get_all("disabled").collect::<Vec<_>>(),
+            vec!["flasks", "trade"]
+        );
+        assert_eq!(
+            loaded.get("toggled"),
+            Some("on key F1 toggle click left every 150ms")
+        );
+        assert_eq!(loaded.get("missing"), None);
+        assert!(Snapshot::load_fresh(&path, Duration::from_secs(60), 1234).is_some());
+        assert!(Snapshot::load_fresh(&path, Duration::from_secs(60), 4321).is_none());
+
+        fs::write(&path, "saved 0\npid 1234\ntown true\n").unwrap();
+        assert!(Snapshot::load_fresh(&path, Duration::from_secs(60), 1234).is_none());
+        fs::write(&path, "town true\n").unwrap();
+        assert!(Snapshot::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Timing model used to make injected input look less machine-perfect.
+//!
+//! Every duration is drawn from a [`Distribution`], using a small seeded random number
+//! generator so that the exact same timings can be reproduced (which tests rely on).
+use crate::types::Delay;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A xorshift64* pseudo-random number generator.
+///
+/// Not suitable for anything but jittering input.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+/// How a duration is picked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Always the same duration, such as `30ms`.
+    Fixed(Duration),
+    /// Any duration within the range, such as `20-40ms`.
+    Uniform(Duration, Duration),
+    /// Usually close to the mean, such as `30ms~5ms` (mean and standard deviation).
+    Normal { mean: Duration, deviation: Duration },
+}
+
+/// The distributions for each kind of delay, if configured.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timing {
+    /// How long keys and buttons are held down before being released.
+    pub hold: Option<Distribution>,
+    /// How long to wait between consecutive inputs of the same action.
+    pub gap: Option<Distribution>,
+    /// How much longer than its cooldown an action should wait before running again.
+    pub jitter: Option<Distribution>,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero, or it would stay zero forever.
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    /// Create a generator with a different seed every run.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+        Self::new(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number within `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from the standard normal distribution.
+    pub fn next_normal(&mut self) -> f64 {
+        // Box-Muller transform. `1 - x` avoids taking the logarithm of zero.
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+}
+
+impl Distribution {
+    pub const ZERO: Self = Self::Fixed(Duration::ZERO);
+
+    pub fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Self::Fixed(duration) => duration,
+            Self::Uniform(low, high) => low + (high - low).mul_f64(rng.next_f64()),
+            Self::Normal { mean, deviation } => {
+                // Outliers are clamped so that a single unlucky sample can't stall an action.
+                let z = rng.next_normal().clamp(-3.0, 3.0);
+                let millis = mean.as_secs_f64() * 1000.0 + z * deviation.as_secs_f64() * 1000.0;
+                Duration::from_micros((millis.max(0.0) * 1000.0) as u64)
+            }
+        }
+    }
+}
+
+impl Timing {
+    /// Use the distributions from `fallback` for whichever ones are not configured.
+    pub fn or(&self, fallback: &Timing) -> Timing {
+        Timing {
+            hold: self.hold.or(fallback.hold),
+            gap: self.gap.or(fallback.gap),
+            jitter: self.jitter.or(fallback.jitter),
+        }
+    }
+
+    /// Configure the distribution with the given name (hold, gap or jitter).
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let distribution = Some(value.parse()?);
+        match name {
+            "hold" => self.hold = distribution,
+            "gap" => self.gap = distribution,
+            "jitter" => self.jitter = distribution,
+            _ => return Err(format!("found unknown timing '{}'", name)),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Timing::default()
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        if let Some((mean, deviation)) = word.split_once('~') {
+            Ok(Self::Normal {
+                mean: mean.parse::<Delay>()?.0,
+                deviation: deviation.parse::<Delay>()?.0,
+            })
+        } else if let Some((low, high)) = word.split_once('-') {
+            let high = high.parse::<Delay>()?.0;
+            // Allow writing `20-40ms` instead of `20ms-40ms`.
+            let low = match low.parse::<Delay>() {
+                Ok(low) => low.0,
+                Err(_) => {
+                    let unit = word.trim_start_matches(|c: char| c != 's' && c != 'm');
+                    format!("{}{}", low, unit).parse::<Delay>()?.0
+                }
+            };
+            if low > high {
+                return Err(format!("found backwards range '{}'", word));
+            }
+            Ok(Self::Uniform(low, high))
+        } else {
+            Ok(Self::Fixed(word.parse::<Delay>()?.0))
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Fixed(duration) => write!(f, "{}", Delay(duration)),
+            Self::Uniform(low, high) => write!(f, "{}-{}", Delay(low), Delay(high)),
+            Self::Normal { mean, deviation } => write!(f, "{}~{}", Delay(mean), Delay(deviation)),
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        for (name, distribution) in [
+            ("hold", self.hold),
+            ("gap", self.gap),
+            ("jitter", self.jitter),
+        ] {
+            if let Some(distribution) = distribution {
+                write!(f, "{}{} {}", sep, name, distribution)?;
+                sep = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse_distributions() {
+        assert_eq!("30ms".parse(), Ok(Distribution::Fixed(ms(30))));
+        assert_eq!("20-40ms".parse(), Ok(Distribution::Uniform(ms(20), ms(40))));
+        assert_eq!(
+            "1s-2s".parse(),
+            Ok(Distribution::Uniform(ms(1000), ms(2000)))
+        );
+        assert_eq!(
+            "30ms~5ms".parse(),
+            Ok(Distribution::Normal {
+                mean: ms(30),
+                deviation: ms(5)
+            })
+        );
+        assert!("40-20ms".parse::<Distribution>().is_err());
+        assert!("fast".parse::<Distribution>().is_err());
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        for word in ["30ms", "20ms-40ms", "30ms~5ms"] {
+            let distribution = word.parse::<Distribution>().unwrap();
+            assert_eq!(distribution.to_string(), word);
+        }
+
+        let mut timing = Timing::default();
+        timing.set("gap", "20-40ms").unwrap();
+        timing.set("hold", "30ms").unwrap();
+        assert_eq!(timing.to_string(), "hold 30ms gap 20ms-40ms");
+    }
+
+    #[test]
+    fn same_seed_same_samples() {
+        let distribution = Distribution::Normal {
+            mean: ms(30),
+            deviation: ms(5),
+        };
+        let sample = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..10)
+                .map(|_| distribution.sample(&mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sample(7), sample(7));
+        assert_ne!(sample(7), sample(8));
+    }
+
+    #[test]
+    fn samples_within_bounds() {
+        let mut rng = Rng::new(1);
+        let uniform = Distribution::Uniform(ms(20), ms(40));
+        let normal = Distribution::Normal {
+            mean: ms(10),
+            deviation: ms(10),
+        };
+        for _ in 0..1000 {
+            let sample = uniform.sample(&mut rng);
+            assert!(ms(20) <= sample && sample <= ms(40));
+            assert!(normal.sample(&mut rng) <= ms(40));
+        }
+    }
+
+    #[test]
+    fn fallback() {
+        let mut global = Timing::default();
+        global.set("hold", "30ms").unwrap();
+        global.set("gap", "10ms").unwrap();
+        let mut rule = Timing::default();
+        rule.set("gap", "50ms").unwrap();
+
+        let timing = rule.or(&global);
+        assert_eq!(timing.hold, Some(Distribution::Fixed(ms(30))));
+        assert_eq!(timing.gap, Some(Distribution::Fixed(ms(50))));
+        assert_eq!(timing.jitter, None);
+    }
+}
@@ -9,10 +9,10 @@ impl FromStr for Delay {
     type Err = String;
 
     fn from_str(word: &str) -> Result<Self, Self::Err> {
-        let (number, factor) = if word.ends_with("ms") {
-            (&word[..word.len() - 2], 1)
-        } else if word.ends_with("s") {
-            (&word[..word.len() - 1], 1000)
+        let (number, factor) = if let Some(number) = word.strip_suffix("ms") {
+            (number, 1)
+        } else if let Some(number) = word.strip_suffix('s') {
+            (number, 1000)
         } else if word == "0" {
             (word, 0)
         } else {
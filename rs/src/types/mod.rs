@@ -1,13 +1,19 @@
 mod delay;
 mod direction;
+mod modifiers;
+#[cfg(windows)]
 mod mouse_button;
 mod opened;
+mod position;
 mod value;
 mod vk;
 
 pub use delay::Delay;
 pub use direction::Direction;
+pub use modifiers::Modifiers;
+#[cfg(windows)]
 pub use mouse_button::MouseButton;
 pub use opened::Opened;
+pub use position::Position;
 pub use value::Value;
 pub use vk::Vk;
@@ -0,0 +1,120 @@
+use std::fmt;
+
+const VK_SHIFT: u16 = 0x10;
+const VK_CONTROL: u16 = 0x11;
+const VK_MENU: u16 = 0x12;
+
+/// The modifier keys (ctrl, shift and alt) which must be held along with a key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const CTRL: Self = Self(1);
+    pub const SHIFT: Self = Self(2);
+    pub const ALT: Self = Self(4);
+
+    // In the order they're displayed and pressed.
+    const ALL: [(Self, &'static str, u16); 3] = [
+        (Self::CTRL, "ctrl", VK_CONTROL),
+        (Self::SHIFT, "shift", VK_SHIFT),
+        (Self::ALT, "alt", VK_MENU),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(&self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn without(&self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// The modifier a Virtual Key Code belongs to, regardless of the side of the keyboard.
+    pub fn of_vk(vk: u16) -> Option<Self> {
+        match vk {
+            0x10 | 0xA0 | 0xA1 => Some(Self::SHIFT),
+            0x11 | 0xA2 | 0xA3 => Some(Self::CTRL),
+            0x12 | 0xA4 | 0xA5 => Some(Self::ALT),
+            _ => None,
+        }
+    }
+
+    /// Whether the Virtual Key Code is a modifier's without saying which side of the keyboard.
+    pub fn is_sideless(vk: u16) -> bool {
+        matches!(vk, VK_SHIFT | VK_CONTROL | VK_MENU)
+    }
+
+    /// The Virtual Key Codes to press for these modifiers.
+    pub fn vks(&self) -> Vec<u16> {
+        Self::ALL
+            .iter()
+            .filter(|(modifier, _, _)| self.contains(*modifier))
+            .map(|(_, _, vk)| *vk)
+            .collect()
+    }
+
+    /// Split a chord such as `ctrl+shift+d` into its modifiers and the remaining key (`d`).
+    pub fn split(word: &str) -> Result<(Self, &str), String> {
+        let mut modifiers = Self::NONE;
+        let mut rest = word;
+        while let Some((name, key)) = rest.split_once('+') {
+            let modifier = match name {
+                "ctrl" | "control" => Self::CTRL,
+                "shift" => Self::SHIFT,
+                "alt" => Self::ALT,
+                _ => return Err(format!("found unknown modifier '{}' in '{}'", name, word)),
+            };
+            if modifiers.contains(modifier) {
+                return Err(format!("found repeated modifier '{}' in '{}'", name, word));
+            }
+            modifiers = modifiers.with(modifier);
+            rest = key;
+        }
+        Ok((modifiers, rest))
+    }
+}
+
+/// Displays as the prefix of a chord, such as `ctrl+shift+` (or nothing without modifiers).
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name, _) in Self::ALL.iter() {
+            if self.contains(*modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split() {
+        assert_eq!(Modifiers::split("d"), Ok((Modifiers::NONE, "d")));
+        assert_eq!(
+            Modifiers::split("shift+ctrl+d"),
+            Ok((Modifiers::CTRL.with(Modifiers::SHIFT), "d"))
+        );
+        assert_eq!(Modifiers::split("alt+1"), Ok((Modifiers::ALT, "1")));
+        assert!(Modifiers::split("ctrl+ctrl+d").is_err());
+        assert!(Modifiers::split("meta+d").is_err());
+    }
+
+    #[test]
+    fn display() {
+        let modifiers = Modifiers::ALT.with(Modifiers::CTRL);
+        assert_eq!(modifiers.to_string(), "ctrl+alt+");
+        assert_eq!(modifiers.vks(), vec![VK_CONTROL, VK_MENU]);
+        assert_eq!(Modifiers::NONE.to_string(), "");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::dispatch;
 use crate::win;
 use std::fmt;
 use std::str::FromStr;
@@ -5,6 +6,17 @@ use std::str::FromStr;
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MouseButton(pub win::mouse::Button);
 
+impl MouseButton {
+    /// The Virtual Key Code of the button, as used by the input dispatcher.
+    pub fn vk(&self) -> u16 {
+        match self.0 {
+            win::mouse::Button::Left => dispatch::VK_LBUTTON,
+            win::mouse::Button::Right => dispatch::VK_RBUTTON,
+            win::mouse::Button::Middle => dispatch::VK_MBUTTON,
+        }
+    }
+}
+
 impl FromStr for MouseButton {
     type Err = &'static str;
 
@@ -0,0 +1,50 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A point on the screen, or within the game window's client area.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    pub x: isize,
+    pub y: isize,
+    /// Whether the point is relative to the game window instead of the screen.
+    pub window: bool,
+}
+
+impl Position {
+    /// Turn the position into screen coordinates, given where the game window's client area is.
+    pub fn on_screen(&self, window_origin: (isize, isize)) -> (isize, isize) {
+        if self.window {
+            (window_origin.0 + self.x, window_origin.1 + self.y)
+        } else {
+            (self.x, self.y)
+        }
+    }
+}
+
+/// Parses the `X,Y` coordinates alone (the position is relative to the screen).
+impl FromStr for Position {
+    type Err = &'static str;
+
+    fn from_str(word: &str) -> Result<Self, Self::Err> {
+        let (x, y) = word
+            .split_once(',')
+            .ok_or("position must be given as X,Y")?;
+        match (x.parse(), y.parse()) {
+            (Ok(x), Ok(y)) => Ok(Self {
+                x,
+                y,
+                window: false,
+            }),
+            _ => Err("position coordinates must be whole numbers"),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.window {
+            f.write_str("window ")?;
+        }
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
@@ -1,36 +1,182 @@
+#[cfg(windows)]
 use crate::win;
 use std::fmt;
 use std::str::FromStr;
-use winapi::um::winuser::VK_F1;
+
+const VK_F1: u16 = 0x70;
+const VK_F24: u16 = 0x87;
+
+/// Named Virtual Key Codes. When a code has several names, the first one is used for display.
+///
+/// # References
+///
+/// https://learn.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
+const NAMES: &[(&str, u16)] = &[
+    ("lmb", 0x01),
+    ("rmb", 0x02),
+    ("cancel", 0x03),
+    ("mmb", 0x04),
+    ("mouse4", 0x05),
+    ("mouse5", 0x06),
+    ("backspace", 0x08),
+    ("tab", 0x09),
+    ("clear", 0x0C),
+    ("enter", 0x0D),
+    ("return", 0x0D),
+    ("shift", 0x10),
+    ("ctrl", 0x11),
+    ("control", 0x11),
+    ("alt", 0x12),
+    ("pause", 0x13),
+    ("capslock", 0x14),
+    ("esc", 0x1B),
+    ("escape", 0x1B),
+    ("space", 0x20),
+    ("pageup", 0x21),
+    ("pagedown", 0x22),
+    ("end", 0x23),
+    ("home", 0x24),
+    ("left", 0x25),
+    ("up", 0x26),
+    ("right", 0x27),
+    ("down", 0x28),
+    ("printscreen", 0x2C),
+    ("insert", 0x2D),
+    ("delete", 0x2E),
+    ("del", 0x2E),
+    ("lwin", 0x5B),
+    ("rwin", 0x5C),
+    ("apps", 0x5D),
+    ("numpad0", 0x60),
+    ("numpad1", 0x61),
+    ("numpad2", 0x62),
+    ("numpad3", 0x63),
+    ("numpad4", 0x64),
+    ("numpad5", 0x65),
+    ("numpad6", 0x66),
+    ("numpad7", 0x67),
+    ("numpad8", 0x68),
+    ("numpad9", 0x69),
+    ("multiply", 0x6A),
+    ("add", 0x6B),
+    ("separator", 0x6C),
+    ("subtract", 0x6D),
+    ("decimal", 0x6E),
+    ("divide", 0x6F),
+    ("numlock", 0x90),
+    ("scrolllock", 0x91),
+    ("lshift", 0xA0),
+    ("rshift", 0xA1),
+    ("lctrl", 0xA2),
+    ("rctrl", 0xA3),
+    ("lalt", 0xA4),
+    ("ralt", 0xA5),
+    ("semicolon", 0xBA),
+    ("equals", 0xBB),
+    ("comma", 0xBC),
+    ("minus", 0xBD),
+    ("period", 0xBE),
+    ("slash", 0xBF),
+    ("backtick", 0xC0),
+    ("lbracket", 0xDB),
+    ("backslash", 0xDC),
+    ("rbracket", 0xDD),
+    ("quote", 0xDE),
+    ("oem8", 0xDF),
+    ("oem102", 0xE2),
+];
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vk(pub u16);
 
+impl Vk {
+    /// The name of the key, if it has one (letters, digits and function keys aside).
+    pub fn name(&self) -> Option<&'static str> {
+        NAMES
+            .iter()
+            .find(|(_, vk)| *vk == self.0)
+            .map(|(name, _)| *name)
+    }
+
+    /// Every key with a name, in the order they're written (so aliases follow each other).
+    pub fn names() -> impl Iterator<Item = (&'static str, Vk)> {
+        NAMES.iter().map(|(name, vk)| (*name, Vk(*vk)))
+    }
+}
+
 impl FromStr for Vk {
     type Err = &'static str;
 
     fn from_str(word: &str) -> Result<Self, Self::Err> {
-        if word.starts_with("0x") {
-            u16::from_str_radix(&word[2..], 16)
+        if let Some(hex) = word.strip_prefix("0x") {
+            return u16::from_str_radix(hex, 16)
                 .map(Self)
-                .map_err(|_| "got invalid hex virtual key code")
-        } else if word.len() != 1 {
-            if word.starts_with("f") {
-                match word[1..].parse::<u8>() {
-                    Ok(n) => Ok(Self(((VK_F1 - 1) + n as i32) as u16)),
-                    Err(_) => Err("invalid integer value for fn key"),
-                }
-            } else {
-                Err("cannot map more than one character to a virtual key code unless it's a fn key")
-            }
-        } else {
-            Ok(Self(win::keyboard::get_vk(word.as_bytes()[0])))
+                .map_err(|_| "got invalid hex virtual key code");
+        }
+        if let Some((_, vk)) = NAMES.iter().find(|(name, _)| *name == word) {
+            return Ok(Self(*vk));
+        }
+
+        let mut chars = word.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphanumeric() => Ok(Self(c.to_ascii_uppercase() as u16)),
+            #[cfg(windows)]
+            (Some(c), None) if c.is_ascii() => Ok(Self(win::keyboard::get_vk(c as u8))),
+            (Some(_), None) => Err("cannot map the character to a virtual key code, use its name"),
+            _ => match word.strip_prefix('f').map(str::parse::<u16>) {
+                Some(Ok(n)) if (1..=VK_F24 - VK_F1 + 1).contains(&n) => Ok(Self(VK_F1 - 1 + n)),
+                Some(_) => Err("invalid integer value for fn key"),
+                None => Err("found unknown key name"),
+            },
         }
     }
 }
 
 impl fmt::Display for Vk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "0x{:x}", self.0)
+        match (self.name(), self.0) {
+            (Some(name), _) => f.write_str(name),
+            (None, vk @ 0x30..=0x39) | (None, vk @ 0x41..=0x5A) => {
+                write!(f, "{}", vk as u8 as char)
+            }
+            (None, vk @ VK_F1..=VK_F24) => write!(f, "F{}", vk - VK_F1 + 1),
+            (None, vk) => write!(f, "0x{:02X}", vk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vk(word: &str) -> u16 {
+        word.parse::<Vk>().unwrap().0
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(vk("z"), 0x5A);
+        assert_eq!(vk("6"), 0x36);
+        assert_eq!(vk("f11"), 0x7A);
+        assert_eq!(vk("0x2"), 0x02);
+        assert_eq!(vk("ctrl"), 0x11);
+        assert_eq!(vk("control"), 0x11);
+        assert_eq!(vk("mouse5"), 0x06);
+        assert_eq!(vk("numpad7"), 0x67);
+        assert_eq!(vk("slash"), 0xBF);
+        assert!("f99".parse::<Vk>().is_err());
+        assert!("banana".parse::<Vk>().is_err());
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        for code in 0..=0xFF {
+            let display = Vk(code).to_string();
+            assert_eq!(vk(&display.to_lowercase()), code, "{}", display);
+        }
+        assert_eq!(Vk(0x11).to_string(), "ctrl");
+        assert_eq!(Vk(0x5A).to_string(), "Z");
+        assert_eq!(Vk(0x7A).to_string(), "F11");
+        assert_eq!(Vk(0xE7).to_string(), "0xE7");
     }
 }
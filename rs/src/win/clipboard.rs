@@ -1,7 +1,27 @@
 #![cfg(windows)]
 
 use winapi::um::winbase::{GlobalLock, GlobalUnlock};
-use winapi::um::winuser::{CloseClipboard, GetClipboardData, OpenClipboard, CF_TEXT};
+use winapi::um::winuser::{
+    CloseClipboard, EmptyClipboard, GetClipboardData, OpenClipboard, CF_TEXT,
+};
+
+/// Empty the clipboard, so that what's copied next can't be mistaken for what was there before.
+pub fn clear() -> Result<(), &'static str> {
+    unsafe {
+        if OpenClipboard(std::ptr::null_mut()) == 0 {
+            return Err("failed to open clipboard");
+        }
+
+        let emptied = EmptyClipboard() != 0;
+        CloseClipboard();
+
+        if emptied {
+            Ok(())
+        } else {
+            Err("failed to empty clipboard")
+        }
+    }
+}
 
 pub fn get() -> Result<String, &'static str> {
     unsafe {
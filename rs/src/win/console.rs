@@ -1,10 +1,16 @@
 #![cfg(windows)]
 
 use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+use winapi::um::consoleapi::SetConsoleCtrlHandler;
 
 use winapi::um::wincon::{
     FillConsoleOutputCharacterA, GetConsoleScreenBufferInfo, GetConsoleWindow,
-    SetConsoleCursorPosition,
+    SetConsoleCursorPosition, CTRL_BREAK_EVENT, CTRL_C_EVENT,
 };
 
 use winapi::um::winuser::{
@@ -20,6 +26,46 @@ use winapi::um::wincontypes::COORD;
 
 // TODO better error handling
 
+// Windows only waits a few seconds after the console is closed before killing the process.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(3);
+
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static EXIT_DONE: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "system" fn handle_ctrl(ctrl_type: DWORD) -> BOOL {
+    EXIT_REQUESTED.store(true, Ordering::SeqCst);
+    if ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT {
+        // The main thread will exit on its own once it notices.
+        return TRUE;
+    }
+    // The process is killed as soon as this returns, so give the main thread time to clean up.
+    let start = Instant::now();
+    while !EXIT_DONE.load(Ordering::SeqCst) && start.elapsed() < EXIT_TIMEOUT {
+        sleep(Duration::from_millis(10));
+    }
+    FALSE
+}
+
+/// Instead of terminating right away on ctrl+c or when the console is closed,
+/// let the program notice it should exit with `exit_requested`.
+///
+/// # References
+///
+/// https://learn.microsoft.com/en-us/windows/console/setconsolectrlhandler
+pub fn catch_exit() -> bool {
+    unsafe { SetConsoleCtrlHandler(Some(handle_ctrl), TRUE) != 0 }
+}
+
+/// Whether the user asked the program to exit (after `catch_exit`).
+pub fn exit_requested() -> bool {
+    EXIT_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Let the console close once the program is done cleaning up after `exit_requested`.
+pub fn exit_done() {
+    EXIT_DONE.store(true, Ordering::SeqCst);
+}
+
 /// Stick the console window to the top of the screen.
 /// Returns whether there was a console to stick or not.
 ///
@@ -1,18 +1,110 @@
+use super::screen;
+use crate::input::{KeyEvent, KeyEventKind};
+use crate::types::Modifiers;
 use std::ptr;
 use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::thread::JoinHandle;
+use std::time::Instant;
+use winapi::shared::minwindef::HIWORD;
 use winapi::um::processthreadsapi::GetCurrentThreadId;
 use winapi::um::winuser::{
     CallNextHookEx, PostThreadMessageA, SetWindowsHookExA, UnhookWindowsHookEx,
-    GET_WHEEL_DELTA_WPARAM, MSLLHOOKSTRUCT, WHEEL_DELTA, WH_MOUSE_LL, WM_MOUSEWHEEL, WM_QUIT,
+    GET_WHEEL_DELTA_WPARAM, HOOKPROC, KBDLLHOOKSTRUCT, LLKHF_INJECTED, LLMHF_INJECTED,
+    MSLLHOOKSTRUCT, VK_LBUTTON, VK_MBUTTON, VK_RBUTTON, VK_XBUTTON1, VK_XBUTTON2, WHEEL_DELTA,
+    WH_KEYBOARD_LL, WH_MOUSE_LL, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP,
+    WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEWHEEL, WM_QUIT, WM_RBUTTONDOWN, WM_RBUTTONUP,
+    WM_SYSKEYDOWN, WM_SYSKEYUP, WM_XBUTTONDOWN, WM_XBUTTONUP, XBUTTON1,
 };
 use winapi::um::winuser::{DispatchMessageA, GetMessageA, TranslateMessage, LPMSG, MSG};
 
-static HOOK_THREAD_HANDLE: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
-static HOOK_THREAD_ID: AtomicU32 = AtomicU32::new(0);
+/// A thread owning a low-level hook, shared by everyone who installed it.
+struct HookThread {
+    handle: Mutex<Option<JoinHandle<()>>>,
+    id: AtomicU32,
+    users: AtomicUsize,
+}
+
+static MOUSE_HOOK: HookThread = HookThread::new();
+static KEYBOARD_HOOK: HookThread = HookThread::new();
 static WHEEL_UP_COUNT: AtomicUsize = AtomicUsize::new(0);
 static WHEEL_DOWN_COUNT: AtomicUsize = AtomicUsize::new(0);
+static KEY_EVENTS: Mutex<Vec<KeyEvent>> = Mutex::new(Vec::new());
+// Low-level hooks don't tell apart presses and repeats, so keep track of what's down.
+static KEYS_DOWN: KeySet = KeySet::new();
+static CONSUMED_KEYS: KeySet = KeySet::new();
+// Keys whose press was swallowed, so that their release is swallowed too (and only then).
+static SWALLOWED_KEYS: KeySet = KeySet::new();
+// The process which must be in the foreground for keys to be consumed, or 0 to never consume.
+static CONSUME_FOR_PID: AtomicU32 = AtomicU32::new(0);
+
+/// A set of Virtual Key Codes that can be shared with the hook callbacks.
+struct KeySet([AtomicU32; 8]);
+
+impl KeySet {
+    const fn new() -> Self {
+        Self([const { AtomicU32::new(0) }; 8])
+    }
+
+    fn slot(&self, vk: u16) -> (&AtomicU32, u32) {
+        (&self.0[(vk as usize / 32) % self.0.len()], 1 << (vk % 32))
+    }
+
+    /// Add or remove the key, returning whether it was in the set before.
+    fn set(&self, vk: u16, present: bool) -> bool {
+        let (slot, bit) = self.slot(vk);
+        let prev = if present {
+            slot.fetch_or(bit, Ordering::SeqCst)
+        } else {
+            slot.fetch_and(!bit, Ordering::SeqCst)
+        };
+        prev & bit != 0
+    }
+
+    fn contains(&self, vk: u16) -> bool {
+        let (slot, bit) = self.slot(vk);
+        slot.load(Ordering::SeqCst) & bit != 0
+    }
+
+    fn clear(&self) {
+        self.0
+            .iter()
+            .for_each(|slot| slot.store(0, Ordering::SeqCst));
+    }
+}
+
+fn push_key_event(vk: u16, kind: KeyEventKind) {
+    KEY_EVENTS.lock().unwrap().push(KeyEvent {
+        vk,
+        kind,
+        time: Instant::now(),
+    });
+}
+
+/// Whether the key is consumed, counting ctrl, shift or alt without a side as either side's key.
+fn is_consumed(vk: u16) -> bool {
+    CONSUMED_KEYS.contains(vk)
+        || Modifiers::of_vk(vk)
+            .is_some_and(|modifier| modifier.vks().iter().any(|vk| CONSUMED_KEYS.contains(*vk)))
+}
+
+/// Decide whether the event should be hidden from every other application (including the game).
+fn swallow(vk: u16, kind: KeyEventKind) -> bool {
+    match kind {
+        KeyEventKind::Down => {
+            let pid = CONSUME_FOR_PID.load(Ordering::SeqCst);
+            // Never swallow keys meant for other windows.
+            let consume = pid != 0
+                && is_consumed(vk)
+                && screen::get_foreground_pid().is_ok_and(|fg| fg == pid);
+            SWALLOWED_KEYS.set(vk, consume);
+            consume
+        }
+        KeyEventKind::Repeat => SWALLOWED_KEYS.contains(vk),
+        // Swallowing a release whose press went through would leave the key stuck.
+        KeyEventKind::Up => SWALLOWED_KEYS.set(vk, false),
+    }
+}
 
 unsafe extern "system" fn mouse_hook_proc(code: i32, wparam: usize, lparam: isize) -> isize {
     if code < 0 {
@@ -21,84 +113,200 @@ unsafe extern "system" fn mouse_hook_proc(code: i32, wparam: usize, lparam: isiz
 
     let hook = &*(lparam as *const MSLLHOOKSTRUCT);
 
-    if wparam as u32 == WM_MOUSEWHEEL {
-        // https://learn.microsoft.com/en-us/windows/win32/inputdev/wm-mousewheel
-        let delta = GET_WHEEL_DELTA_WPARAM(hook.mouseData as _) / WHEEL_DELTA;
-        if delta > 0 {
-            WHEEL_UP_COUNT.fetch_add(delta as _, Ordering::SeqCst);
-        } else if delta < 0 {
-            WHEEL_DOWN_COUNT.fetch_add(-delta as _, Ordering::SeqCst);
+    let xbutton = || {
+        if HIWORD(hook.mouseData) == XBUTTON1 {
+            VK_XBUTTON1
+        } else {
+            VK_XBUTTON2
+        }
+    };
+    let button = match wparam as u32 {
+        WM_MOUSEWHEEL => {
+            // https://learn.microsoft.com/en-us/windows/win32/inputdev/wm-mousewheel
+            let delta = GET_WHEEL_DELTA_WPARAM(hook.mouseData as _) / WHEEL_DELTA;
+            if delta > 0 {
+                WHEEL_UP_COUNT.fetch_add(delta as _, Ordering::SeqCst);
+            } else if delta < 0 {
+                WHEEL_DOWN_COUNT.fetch_add(-delta as _, Ordering::SeqCst);
+            }
+            None
+        }
+        WM_LBUTTONDOWN => Some((VK_LBUTTON, KeyEventKind::Down)),
+        WM_LBUTTONUP => Some((VK_LBUTTON, KeyEventKind::Up)),
+        WM_RBUTTONDOWN => Some((VK_RBUTTON, KeyEventKind::Down)),
+        WM_RBUTTONUP => Some((VK_RBUTTON, KeyEventKind::Up)),
+        WM_MBUTTONDOWN => Some((VK_MBUTTON, KeyEventKind::Down)),
+        WM_MBUTTONUP => Some((VK_MBUTTON, KeyEventKind::Up)),
+        WM_XBUTTONDOWN => Some((xbutton(), KeyEventKind::Down)),
+        WM_XBUTTONUP => Some((xbutton(), KeyEventKind::Up)),
+        _ => None,
+    };
+    // Our own clicks shouldn't trigger rules.
+    if let Some((vk, kind)) = button {
+        if hook.flags & LLMHF_INJECTED == 0 && KEYBOARD_HOOK.users.load(Ordering::SeqCst) > 0 {
+            push_key_event(vk as u16, kind);
+            if swallow(vk as u16, kind) {
+                return 1;
+            }
         }
     }
     CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
 }
 
-// TODO in theory, a keyboard hook would be even better (= faster, more reliable) than using GetKeyState!
-// Currently the mouse hook is used to "emulate" "is wheel being used" behaviour.
-pub fn install_mouse_hook() {
-    let mut hook_handle = HOOK_THREAD_HANDLE.lock().unwrap();
-    if hook_handle.is_some() {
-        return;
+unsafe extern "system" fn keyboard_hook_proc(code: i32, wparam: usize, lparam: isize) -> isize {
+    if code < 0 {
+        return CallNextHookEx(ptr::null_mut(), code, wparam, lparam);
     }
 
-    *hook_handle = Some(std::thread::spawn(|| {
-        unsafe {
-            // I've tried several things.
-            //
-            // Registering a hook without pumping messages (akin to `Application.Run` in .NET)
-            // causes extreme input lag and no events are really processed (presumably Windows
-            // times out if a hook doesn't respond which it can't if there's no message loop).
-            //
-            // One thing's for sure: the same thread that installs the hook should pump messages.
-            // `GetMessage` seems to never return (some say the application needs a window, which
-            // we don't really have). We want it to return so we can eventually uninstall the
-            // hook though, so `PeekMessage` can be used for that instead.
-            //
-            // Unfortunately this peek must be called repeatedly which would burn a fair amount
-            // of CPU, even with `yield_now` (a small sleep causes noticeable input lag too).
-            //
-            // However, it seems `PostQuitMessage` works wonders within the hook callback.
-            // Although this does mean there needs to be another event to "wake up" the callback.
-            // Thankfully `PostThreadMessage` exists to post messages to other threads.
-            // Unfortunately, Rust doesn't really let you access a thread's ID from its handle,
-            // so we have to store and use it ourselves.
-            let tid = GetCurrentThreadId();
-            HOOK_THREAD_ID.store(tid, Ordering::SeqCst);
-
-            // Some use `LoadLibraryA(b"User32".as_ptr() as _);` but it seems to work fine without?
-            let user32 = ptr::null_mut();
-            let hhook = SetWindowsHookExA(WH_MOUSE_LL, Some(mouse_hook_proc), user32, 0);
-            let mut msg: MSG = std::mem::zeroed();
-            while HOOK_THREAD_ID.load(Ordering::SeqCst) == tid {
-                if GetMessageA(&mut msg as LPMSG, ptr::null_mut(), 0, 0) > 0 {
-                    // There never seems to be any message but we should do this anyway
-                    TranslateMessage(&mut msg as LPMSG);
-                    DispatchMessageA(&mut msg as LPMSG);
+    let hook = &*(lparam as *const KBDLLHOOKSTRUCT);
+
+    // Our own key presses shouldn't trigger rules.
+    if hook.flags & LLKHF_INJECTED == 0 {
+        let vk = hook.vkCode as u16;
+        let kind = match wparam as u32 {
+            WM_KEYDOWN | WM_SYSKEYDOWN if KEYS_DOWN.set(vk, true) => Some(KeyEventKind::Repeat),
+            WM_KEYDOWN | WM_SYSKEYDOWN => Some(KeyEventKind::Down),
+            WM_KEYUP | WM_SYSKEYUP => {
+                KEYS_DOWN.set(vk, false);
+                Some(KeyEventKind::Up)
+            }
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            push_key_event(vk, kind);
+            if swallow(vk, kind) {
+                return 1;
+            }
+        }
+    }
+    CallNextHookEx(ptr::null_mut(), code, wparam, lparam)
+}
+
+impl HookThread {
+    const fn new() -> Self {
+        Self {
+            handle: Mutex::new(None),
+            id: AtomicU32::new(0),
+            users: AtomicUsize::new(0),
+        }
+    }
+
+    fn install(&'static self, id: i32, proc: HOOKPROC) {
+        let mut hook_handle = self.handle.lock().unwrap();
+        self.users.fetch_add(1, Ordering::SeqCst);
+        if hook_handle.is_some() {
+            return;
+        }
+
+        *hook_handle = Some(std::thread::spawn(move || {
+            unsafe {
+                // I've tried several things.
+                //
+                // Registering a hook without pumping messages (akin to `Application.Run` in .NET)
+                // causes extreme input lag and no events are really processed (presumably Windows
+                // times out if a hook doesn't respond which it can't if there's no message loop).
+                //
+                // One thing's for sure: the same thread that installs the hook should pump messages.
+                // `GetMessage` seems to never return (some say the application needs a window, which
+                // we don't really have). We want it to return so we can eventually uninstall the
+                // hook though, so `PeekMessage` can be used for that instead.
+                //
+                // Unfortunately this peek must be called repeatedly which would burn a fair amount
+                // of CPU, even with `yield_now` (a small sleep causes noticeable input lag too).
+                //
+                // However, it seems `PostQuitMessage` works wonders within the hook callback.
+                // Although this does mean there needs to be another event to "wake up" the callback.
+                // Thankfully `PostThreadMessage` exists to post messages to other threads.
+                // Unfortunately, Rust doesn't really let you access a thread's ID from its handle,
+                // so we have to store and use it ourselves.
+                let tid = GetCurrentThreadId();
+                self.id.store(tid, Ordering::SeqCst);
+
+                // Some use `LoadLibraryA(b"User32".as_ptr() as _);` but it seems to work fine without?
+                let user32 = ptr::null_mut();
+                let hhook = SetWindowsHookExA(id, proc, user32, 0);
+                let mut msg: MSG = std::mem::zeroed();
+                while self.id.load(Ordering::SeqCst) == tid {
+                    if GetMessageA(&mut msg as LPMSG, ptr::null_mut(), 0, 0) > 0 {
+                        // There never seems to be any message but we should do this anyway
+                        TranslateMessage(&mut msg as LPMSG);
+                        DispatchMessageA(&mut msg as LPMSG);
+                    }
                 }
+                let _result = msg.wParam as i32;
+                let _success = UnhookWindowsHookEx(hhook);
             }
-            let _result = msg.wParam as i32;
-            let _success = UnhookWindowsHookEx(hhook);
+        }));
+
+        // Wait until the ID is set before releasing the handle lock.
+        // `uninstall` needs the ID in order to quit properly.
+        while self.id.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
         }
-    }));
+    }
+
+    fn uninstall(&self) {
+        let mut hook_handle = self.handle.lock().unwrap();
+        if hook_handle.is_none() || self.users.fetch_sub(1, Ordering::SeqCst) > 1 {
+            return;
+        }
+        let hook_handle = hook_handle.take().unwrap();
 
-    // Wait until HOOK_THREAD_ID is set before releasing the HOOK_THREAD_HANDLE lock.
-    // `uninstall_mouse_hook` needs the ID in order to quit properly.
-    while HOOK_THREAD_ID.load(Ordering::SeqCst) == 0 {
-        std::thread::yield_now();
+        let tid = self.id.swap(0, Ordering::SeqCst);
+        unsafe {
+            PostThreadMessageA(tid, WM_QUIT, 0, 0);
+        }
+        hook_handle.join().unwrap();
     }
 }
 
+/// Install the mouse hook, unless it's already installed.
+///
+/// Every call should be paired with a call to `uninstall_mouse_hook`.
+pub fn install_mouse_hook() {
+    MOUSE_HOOK.install(WH_MOUSE_LL, Some(mouse_hook_proc));
+}
+
 pub fn uninstall_mouse_hook() {
-    let hook_handle = match HOOK_THREAD_HANDLE.lock().unwrap().take() {
-        Some(handle) => handle,
-        None => return,
-    };
+    MOUSE_HOOK.uninstall();
+}
 
-    let tid = HOOK_THREAD_ID.swap(0, Ordering::SeqCst);
-    unsafe {
-        PostThreadMessageA(tid, WM_QUIT, 0, 0);
+/// Install the keyboard hook, along with the mouse hook to catch mouse buttons.
+///
+/// Every call should be paired with a call to `uninstall_keyboard_hook`.
+pub fn install_keyboard_hook() {
+    KEYBOARD_HOOK.install(WH_KEYBOARD_LL, Some(keyboard_hook_proc));
+    install_mouse_hook();
+}
+
+pub fn uninstall_keyboard_hook() {
+    uninstall_mouse_hook();
+    KEYBOARD_HOOK.uninstall();
+    if KEYBOARD_HOOK.users.load(Ordering::SeqCst) == 0 {
+        KEY_EVENTS.lock().unwrap().clear();
+        CONSUMED_KEYS.clear();
+        SWALLOWED_KEYS.clear();
     }
-    hook_handle.join().unwrap();
+}
+
+/// Set which keys (or mouse buttons) should not reach the game when pressed.
+///
+/// Consumed keys are still recorded, so they can trigger rules.
+pub fn set_consumed_keys(vks: &[u16]) {
+    CONSUMED_KEYS.clear();
+    vks.iter().for_each(|vk| {
+        CONSUMED_KEYS.set(*vk, true);
+    });
+}
+
+/// Only consume keys while the given process is in the foreground, or never if `None`.
+pub fn set_consume_target(pid: Option<u32>) {
+    CONSUME_FOR_PID.store(pid.unwrap_or(0), Ordering::SeqCst);
+}
+
+/// Take the key and mouse button events recorded since the last poll.
+pub fn poll_key_events() -> Vec<KeyEvent> {
+    std::mem::take(&mut *KEY_EVENTS.lock().unwrap())
 }
 
 /// Return `true` if the mouse wheel was turned up.
@@ -0,0 +1,71 @@
+use super::{keyboard, mouse};
+use crate::dispatch::{
+    InputSink, VK_CONTROL, VK_LBUTTON, VK_MBUTTON, VK_MENU, VK_RBUTTON, VK_SHIFT,
+};
+use std::thread::sleep;
+use std::time::Duration;
+use winapi::um::winuser::VkKeyScanW;
+
+/// Input sent to whichever window has focus, through `SendInput`.
+pub struct SystemInput;
+
+impl InputSink for SystemInput {
+    fn send(&mut self, vk: u16, down: bool) {
+        let button = match vk {
+            VK_LBUTTON => mouse::Button::Left,
+            VK_RBUTTON => mouse::Button::Right,
+            VK_MBUTTON => mouse::Button::Middle,
+            _ if down => return keyboard::hold(vk),
+            _ => return keyboard::release(vk),
+        };
+        if down {
+            mouse::hold(button);
+        } else {
+            mouse::release(button);
+        }
+    }
+
+    fn send_char(&mut self, c: char, down: bool) {
+        if down {
+            keyboard::hold_char(c);
+        } else {
+            keyboard::release_char(c);
+        }
+    }
+
+    /// # References
+    ///
+    /// https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-vkkeyscanw
+    fn scan(&self, c: char) -> Option<(u16, Vec<u16>)> {
+        let mut utf16 = [0; 2];
+        let [unit] = c.encode_utf16(&mut utf16) else {
+            return None;
+        };
+        let res = unsafe { VkKeyScanW(*unit) };
+        if res == -1 {
+            return None;
+        }
+        let modifiers = [(1, VK_SHIFT), (2, VK_CONTROL), (4, VK_MENU)]
+            .iter()
+            .filter(|(bit, _)| (res >> 8) & bit != 0)
+            .map(|(_, vk)| *vk)
+            .collect();
+        Some(((res & 0xff) as u16, modifiers))
+    }
+
+    fn move_to(&mut self, x: isize, y: isize) {
+        let _ = mouse::set(x, y);
+    }
+
+    fn cursor(&self) -> Option<(isize, isize)> {
+        mouse::get().ok()
+    }
+
+    fn scroll(&mut self, amount: isize) {
+        mouse::scroll(amount);
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        sleep(duration);
+    }
+}
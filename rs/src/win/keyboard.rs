@@ -4,7 +4,7 @@ use std::mem::{size_of, MaybeUninit};
 
 use winapi::um::winuser::{
     GetKeyState, INPUT_u, SendInput, VkKeyScanA, INPUT, INPUT_KEYBOARD, KEYEVENTF_KEYUP,
-    VK_CONTROL, VK_MENU, VK_SHIFT,
+    KEYEVENTF_UNICODE, VK_CONTROL, VK_MENU, VK_SHIFT,
 };
 
 /// Type a modifier.
@@ -99,6 +99,40 @@ pub fn release(vk: u16) {
     }
 }
 
+/// Send a character as a Unicode key event, pressed (`up == false`) or released.
+///
+/// Characters outside the Basic Multilingual Plane are sent as a surrogate pair.
+///
+/// # References
+///
+/// https://learn.microsoft.com/en-us/windows/win32/api/winuser/ns-winuser-keybdinput
+fn send_char(c: char, up: bool) {
+    let mut units = [0; 2];
+    for unit in c.encode_utf16(&mut units) {
+        unsafe {
+            let mut input = INPUT {
+                type_: INPUT_KEYBOARD,
+                u: MaybeUninit::<INPUT_u>::zeroed().assume_init(),
+            };
+
+            input.u.ki_mut().wVk = 0;
+            input.u.ki_mut().wScan = *unit;
+            input.u.ki_mut().dwFlags = KEYEVENTF_UNICODE | if up { KEYEVENTF_KEYUP } else { 0 };
+            SendInput(1, &mut input, size_of::<INPUT>() as i32);
+        }
+    }
+}
+
+/// Hold down the key for a character, regardless of the keyboard layout.
+pub fn hold_char(c: char) {
+    send_char(c, false);
+}
+
+/// Release the key for a character held with `hold_char`.
+pub fn release_char(c: char) {
+    send_char(c, true);
+}
+
 /// Press (hold down and then release) a Virtual Key Code.
 ///
 /// # References
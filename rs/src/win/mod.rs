@@ -3,6 +3,7 @@
 pub mod clipboard;
 pub mod console;
 pub mod hook;
+pub mod input;
 pub mod keyboard;
 pub mod mouse;
 pub mod proc;
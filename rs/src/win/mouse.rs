@@ -18,17 +18,19 @@ pub enum Button {
 
 /// Get the cursor's position as `(x, y)`.
 ///
+/// Points left of or above the primary monitor are negative.
+///
 /// # References
 ///
 /// https://docs.microsoft.com/en-us/windows/desktop/api/winuser/nf-winuser-getcursorpos
-pub fn get() -> Result<(usize, usize), Error> {
+pub fn get() -> Result<(isize, isize), Error> {
     unsafe {
         let mut point = MaybeUninit::uninit();
         if GetCursorPos(point.as_mut_ptr()) == 0 {
             Err(Error::last_os_error())
         } else {
             let point = point.assume_init();
-            Ok((point.x as usize, point.y as usize))
+            Ok((point.x as isize, point.y as isize))
         }
     }
 }
@@ -38,7 +40,7 @@ pub fn get() -> Result<(usize, usize), Error> {
 /// # References
 ///
 /// https://docs.microsoft.com/en-us/windows/desktop/api/winuser/nf-winuser-setcursorpos
-pub fn set(x: usize, y: usize) -> Result<(), Error> {
+pub fn set(x: isize, y: isize) -> Result<(), Error> {
     unsafe {
         if SetCursorPos(x as i32, y as i32) == 0 {
             Err(Error::last_os_error())
@@ -48,34 +50,50 @@ pub fn set(x: usize, y: usize) -> Result<(), Error> {
     }
 }
 
-/// Perform a mouse click (press down and release).
+/// Press down a mouse button.
 ///
 /// # References
 ///
 /// https://docs.microsoft.com/en-us/windows/desktop/api/winuser/nf-winuser-sendinput
-pub fn click(button: Button) {
+pub fn hold(button: Button) {
+    send_button(match button {
+        Button::Left => MOUSEEVENTF_LEFTDOWN,
+        Button::Right => MOUSEEVENTF_RIGHTDOWN,
+        Button::Middle => MOUSEEVENTF_MIDDLEDOWN,
+    });
+}
+
+/// Release a held mouse button.
+///
+/// # References
+///
+/// https://docs.microsoft.com/en-us/windows/desktop/api/winuser/nf-winuser-sendinput
+pub fn release(button: Button) {
+    send_button(match button {
+        Button::Left => MOUSEEVENTF_LEFTUP,
+        Button::Right => MOUSEEVENTF_RIGHTUP,
+        Button::Middle => MOUSEEVENTF_MIDDLEUP,
+    });
+}
+
+fn send_button(flags: u32) {
     unsafe {
         let mut input = INPUT {
             type_: INPUT_MOUSE,
             u: MaybeUninit::<INPUT_u>::zeroed().assume_init(),
         };
 
-        input.u.mi_mut().dwFlags = match button {
-            Button::Left => MOUSEEVENTF_LEFTDOWN,
-            Button::Right => MOUSEEVENTF_RIGHTDOWN,
-            Button::Middle => MOUSEEVENTF_MIDDLEDOWN,
-        };
-        SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
-
-        input.u.mi_mut().dwFlags = match button {
-            Button::Left => MOUSEEVENTF_LEFTUP,
-            Button::Right => MOUSEEVENTF_RIGHTUP,
-            Button::Middle => MOUSEEVENTF_MIDDLEUP,
-        };
+        input.u.mi_mut().dwFlags = flags;
         SendInput(1, &mut input, std::mem::size_of::<INPUT>() as i32);
     }
 }
 
+/// Perform a mouse click (press down and release).
+pub fn click(button: Button) {
+    hold(button);
+    release(button);
+}
+
 /// Scroll the mouse wheel a certain amount.
 ///
 /// # References
@@ -2,8 +2,8 @@
 use std::io::{Error, ErrorKind};
 use std::mem::{self, MaybeUninit};
 use std::ptr;
-use winapi::shared::minwindef::{ATOM, DWORD, LPVOID};
-use winapi::shared::windef::{HBITMAP, HBRUSH, HDC, HGDIOBJ, HWND, RECT};
+use winapi::shared::minwindef::{ATOM, BOOL, DWORD, FALSE, LPARAM, LPVOID, TRUE};
+use winapi::shared::windef::{HBITMAP, HBRUSH, HDC, HGDIOBJ, HWND, POINT, RECT};
 use winapi::shared::winerror::ERROR_INVALID_PARAMETER;
 use winapi::um::commctrl::TOOLTIPS_CLASS;
 use winapi::um::errhandlingapi::GetLastError;
@@ -14,41 +14,22 @@ use winapi::um::wingdi::{
 };
 use winapi::um::winnt::LPCSTR;
 use winapi::um::winuser::{
-    CreateWindowExA, DefWindowProcA, DestroyWindow, DrawTextA, GetDC, GetDesktopWindow,
-    GetForegroundWindow, GetWindowDC, GetWindowRect, GetWindowThreadProcessId, RegisterClassExA,
+    ClientToScreen, CreateWindowExA, DefWindowProcA, DestroyWindow, DrawTextA, EnumWindows,
+    GetClientRect, GetDC, GetDesktopWindow, GetForegroundWindow, GetWindow, GetWindowDC,
+    GetWindowRect, GetWindowThreadProcessId, IsIconic, IsWindowVisible, RegisterClassExA,
     ReleaseDC, SetWindowPos, COLOR_WINDOW, CW_USEDEFAULT, DT_CALCRECT, DT_NOCLIP, DT_SINGLELINE,
-    SWP_NOACTIVATE, SWP_NOZORDER, WNDCLASSEXA, WS_EX_TOPMOST, WS_POPUP, WS_VISIBLE,
+    GW_OWNER, SWP_NOACTIVATE, SWP_NOZORDER, WNDCLASSEXA, WS_EX_TOPMOST, WS_POPUP, WS_VISIBLE,
 };
 
 // Structures used for the automatic `Drop` cleanup
 struct Window(HWND);
 struct WindowDC(HDC);
 
+pub use crate::image::{Rect, Screenshot, ScreenshotIter};
+
 // TODO Probably should use https://doc.rust-lang.org/std/ffi/index.html to deal with wide strings
 // TODO Consider publishing this input lib on crates.io?
 
-#[derive(Clone, Debug)]
-pub struct Rect {
-    pub left: usize,
-    pub top: usize,
-    pub width: usize,
-    pub height: usize,
-}
-
-#[derive(Clone)]
-pub struct Screenshot {
-    pub region: Rect,
-    row_size: usize,
-    colors: Box<[u8]>,
-}
-
-pub struct ScreenshotIter<'s> {
-    screenshot: &'s Screenshot,
-    y_idx: usize,
-    x_cnt: usize,
-    i: usize,
-}
-
 pub struct Screen {
     dc: HDC,
     dc_mem: HDC,
@@ -57,63 +38,6 @@ pub struct Screen {
     screenshot: Screenshot,
 }
 
-impl Screenshot {
-    fn new(region: Rect) -> Self {
-        // https://learn.microsoft.com/en-us/windows/win32/api/wingdi/nf-wingdi-getdibits
-        // The scan lines must be aligned on a DWORD except for RLE compressed bitmaps.
-        // Else "exit code: 0xc0000374, STATUS_HEAP_CORRUPTION" will occur,
-        // because `GetDIBits` will attempt to write outside the designated buffer.
-        let row_byte_count = region.width * 3; // RGB, 1 byte per
-        let row_size = ((row_byte_count + 3) / 4) * 4; // DWORD, 4 bytes
-        let size = region.height * row_size;
-        Self {
-            region,
-            row_size,
-            colors: vec![0; size].into_boxed_slice(),
-        }
-    }
-
-    pub fn color(&self, x: usize, y: usize) -> (u8, u8, u8) {
-        let i = (y * self.row_size + x) * 3;
-        (self.colors[i + 2], self.colors[i + 1], self.colors[i + 0])
-    }
-
-    pub fn colors(&self) -> ScreenshotIter {
-        ScreenshotIter {
-            screenshot: self,
-            y_idx: 0,
-            x_cnt: 0,
-            i: 0,
-        }
-    }
-}
-
-impl<'s> Iterator for ScreenshotIter<'s> {
-    type Item = (u8, u8, u8);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let i = self.i;
-        if i == self.screenshot.colors.len() {
-            return None;
-        }
-
-        self.x_cnt += 1;
-        if self.x_cnt == self.screenshot.region.width {
-            self.x_cnt = 0;
-            self.y_idx += self.screenshot.row_size;
-            self.i = self.y_idx;
-        } else {
-            self.i += 3;
-        }
-
-        Some((
-            self.screenshot.colors[i + 2],
-            self.screenshot.colors[i + 1],
-            self.screenshot.colors[i + 0],
-        ))
-    }
-}
-
 impl Screen {
     /// Creates a capture of a region in the screen, which can be refreshed to contain data.
     ///
@@ -192,7 +116,7 @@ impl Screen {
                 self.bmp as HBITMAP,
                 0,
                 self.screenshot.region.height as u32,
-                self.screenshot.colors.as_mut_ptr() as LPVOID,
+                self.screenshot.bytes_mut().as_mut_ptr() as LPVOID,
                 &mut self.bmp_info,
                 DIB_RGB_COLORS,
             )
@@ -286,8 +210,8 @@ pub fn size() -> Result<Rect, Error> {
         } else {
             let desktop = desktop.assume_init();
             Ok(Rect {
-                left: desktop.left as usize,
-                top: desktop.top as usize,
+                left: desktop.left as isize,
+                top: desktop.top as isize,
                 width: (desktop.right - desktop.left) as usize,
                 height: (desktop.bottom - desktop.top) as usize,
             })
@@ -334,6 +258,85 @@ pub fn get_foreground_pid() -> Result<u32, Error> {
     }
 }
 
+/// Find the main window of the process with the given ID.
+///
+/// The main window is the first visible, top-level window without an owner.
+///
+/// # References
+///
+/// https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-enumwindows
+pub fn find_window(pid: u32) -> Option<HWND> {
+    struct Search {
+        pid: u32,
+        found: HWND,
+    }
+
+    unsafe extern "system" fn callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam as *mut Search);
+        let mut proc_id = 0;
+        GetWindowThreadProcessId(hwnd, &mut proc_id);
+        if proc_id == search.pid
+            && IsWindowVisible(hwnd) != 0
+            && GetWindow(hwnd, GW_OWNER).is_null()
+        {
+            search.found = hwnd;
+            FALSE
+        } else {
+            TRUE
+        }
+    }
+
+    let mut search = Search {
+        pid,
+        found: ptr::null_mut(),
+    };
+    unsafe { EnumWindows(Some(callback), &mut search as *mut Search as LPARAM) };
+    if search.found.is_null() {
+        None
+    } else {
+        Some(search.found)
+    }
+}
+
+/// Gets the client area of the main window of the process with the given ID, in screen coordinates.
+///
+/// Fails if the window can't be found or is minimized (which would make its area empty).
+///
+/// # References
+///
+/// https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-getclientrect
+/// https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-clienttoscreen
+pub fn client_rect(pid: u32) -> Result<Rect, Error> {
+    let hwnd = find_window(pid)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "process has no visible window"))?;
+    if unsafe { IsIconic(hwnd) } != 0 {
+        return Err(Error::other("window is minimized"));
+    }
+
+    let mut rect: RECT = unsafe { mem::zeroed() };
+    if unsafe { GetClientRect(hwnd, &mut rect) } == 0 {
+        return Err(Error::last_os_error());
+    }
+    let mut origin = POINT { x: 0, y: 0 };
+    if unsafe { ClientToScreen(hwnd, &mut origin) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(Rect {
+        left: origin.x as isize,
+        top: origin.y as isize,
+        width: (rect.right - rect.left) as usize,
+        height: (rect.bottom - rect.top) as usize,
+    })
+}
+
+/// Take a screenshot of the client area of the main window of the process with the given ID.
+pub fn capture_window(pid: u32) -> Result<Screenshot, Error> {
+    let mut screen = Screen::capture_region(client_rect(pid)?)?;
+    screen.refresh()?;
+    Ok(screen.screenshot().clone())
+}
+
 /// Using `CreateWindowExA` without previously having used
 /// `RegisterClassExA` will result in the last error to be
 /// `57f`: `ERROR_CANNOT_FIND_WND_CLASS`.
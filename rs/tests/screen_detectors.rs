@@ -0,0 +1,55 @@
+//! Run the screen detectors on every capture in `tests/screens`.
+//!
+//! Captures saved with `poe capture` store the verdict of each detector at the time.
+//! Once the verdicts have been checked to be right (edit the text chunks otherwise),
+//! copying the capture into `tests/screens` makes sure detectors keep agreeing with them.
+//!
+//! The captures there so far are synthetic, drawn to look like each screen, so for now this is
+//! a regression check of where the regions are placed in the window rather than of detection:
+//! they only show the detectors agree with the drawings, not with the game.
+use rshacks::detect::{self, Detector, CROP_TEXT_KEY};
+use rshacks::image::Screenshot;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn saved_captures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("screens");
+    let mut failures = Vec::new();
+    let mut checked = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "png") {
+            continue;
+        }
+
+        let (screenshot, text) = Screenshot::load_png(&path).unwrap();
+        if text.iter().any(|(key, _)| key == CROP_TEXT_KEY) {
+            // Crops of a single region can't be placed back in the window.
+            continue;
+        }
+
+        checked += 1;
+        let state = detect::detect_all(&screenshot, &Detector::ALL);
+        for (key, expected) in text {
+            if let Ok(detector) = key.parse::<Detector>() {
+                let actual = state.verdict(detector);
+                if actual != expected {
+                    failures.push(format!(
+                        "{}: {} detector said {} but expected {}",
+                        path.display(),
+                        detector,
+                        actual,
+                        expected
+                    ));
+                }
+            }
+        }
+    }
+
+    // Without any capture, nothing would be tested at all.
+    assert!(checked > 0, "found no captures in {}", dir.display());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}